@@ -0,0 +1,1061 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose, Engine as _};
+use rocket::data::ToByteUnit;
+use rocket::http::Status;
+use rocket::response::content::RawJson;
+use rocket::response::status::Custom;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{uri, Data, State};
+
+use crate::auth::{self, Credentials, EditToken, Identity, Scope};
+use crate::config::BibinConfig;
+use crate::get;
+use crate::ids;
+use crate::io::{expiration_date, PasteMeta, PasteOptions, ShareLink, ShareMode};
+use crate::language::is_valid_language;
+use crate::mimetype;
+use crate::readpassword::{self, ReadPassword};
+use crate::share::ShareKey;
+use crate::store::PasteStore;
+use crate::ttl::Ttl;
+use crate::write::{
+    authorize_delete, authorize_store_at, claim, paste_uri, read_raw_paste, WithEditToken,
+};
+
+/// A paste as described by the JSON API
+#[derive(Serialize)]
+pub struct PasteObject {
+    pub id: String,
+    /// Page of the paste, highlighted with its language
+    pub url: String,
+    pub raw_url: String,
+    /// QR code of the URL of the paste
+    pub qr_url: String,
+    /// Unix timestamp after which the paste is not served anymore
+    pub expires_at: Option<i64>,
+    pub metadata: PasteMeta,
+    /// Content of the paste, only when it was read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+    /// Secret to replace or delete the paste, only in the answer to its creation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit_token: Option<String>,
+}
+
+impl PasteObject {
+    pub fn new(config: &BibinConfig, meta: PasteMeta) -> PasteObject {
+        PasteObject {
+            url: format!(
+                "{}{}",
+                config.prefix,
+                paste_uri(&meta.id, meta.language.as_deref())
+            ),
+            raw_url: format!(
+                "{}{}",
+                config.prefix,
+                uri!(get::get_item_raw(meta.id.as_str()))
+            ),
+            qr_url: format!("{}{}", config.prefix, uri!(get::get_qr(&meta.id))),
+            id: meta.id.clone(),
+            expires_at: meta.expires_at,
+            metadata: meta.without_secrets(),
+            content: None,
+            encoding: None,
+            edit_token: None,
+        }
+    }
+
+    /// Add the content, as text when it is valid UTF-8
+    pub fn with_content(mut self, data: Vec<u8>) -> PasteObject {
+        let (content, encoding) = match String::from_utf8(data) {
+            Ok(text) => (text, Encoding::Utf8),
+            Err(e) => (
+                general_purpose::STANDARD.encode(e.into_bytes()),
+                Encoding::Base64,
+            ),
+        };
+        self.content = Some(content);
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub fn to_json(&self) -> Result<String, Status> {
+        get::to_json(self)
+    }
+}
+
+/// Encoding of the content of a paste in the JSON objects
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+/// A paste sent to the JSON API
+#[derive(Deserialize)]
+pub struct NewPaste {
+    content: String,
+    #[serde(default)]
+    encoding: Encoding,
+    language: Option<String>,
+    mime_type: Option<String>,
+    filename: Option<String>,
+    /// Time to live: 30m, 1h, 7d...
+    expire: Option<Ttl>,
+    max_views: Option<u32>,
+    /// Password needed to read the paste
+    read_password: Option<String>,
+    /// Left out of the listings, with a long id. The default of the key when not given.
+    private: Option<bool>,
+}
+
+impl NewPaste {
+    /// Content and options of the paste, checked like the ones of the other routes
+    fn into_paste(
+        self,
+        config: &BibinConfig,
+        identity: Option<&Identity>,
+    ) -> Result<(Vec<u8>, PasteOptions), Status> {
+        let data = match self.encoding {
+            Encoding::Utf8 => self.content.into_bytes(),
+            Encoding::Base64 => general_purpose::STANDARD
+                .decode(self.content)
+                .map_err(|_| Status::BadRequest)?,
+        };
+        if data.len() > config.max_paste_size {
+            return Err(Status::PayloadTooLarge);
+        }
+        if self.max_views == Some(0) {
+            return Err(Status::BadRequest);
+        }
+        if let Some(language) = &self.language {
+            if !is_valid_language(language) {
+                return Err(Status::BadRequest);
+            }
+        }
+        let mime_type = self
+            .mime_type
+            .unwrap_or_else(|| mimetype::sniff(&data).to_owned());
+
+        let options = PasteOptions {
+            ttl: config.effective_ttl(self.expire),
+            max_views: self.max_views,
+            language: self.language,
+            mime_type: Some(mime_type),
+            creator: identity.and_then(Identity::name).map(str::to_owned),
+            filename: self.filename,
+            read_password_hash: readpassword::hash(self.read_password.as_deref())?,
+            private: auth::is_private(self.private, identity),
+            ..Default::default()
+        };
+        Ok((data, options))
+    }
+}
+
+/// Parse the JSON body of a request. Base64 and the JSON escaping make it larger than the paste.
+async fn read_new_paste(input: Data<'_>, config: &BibinConfig) -> Result<NewPaste, Status> {
+    let body = read_raw_paste(input, config.max_paste_size * 2 + 64.kibibytes()).await?;
+    serde_json::from_slice(&body).map_err(|e| {
+        warn!("[API] Invalid paste: {}", e);
+        Status::BadRequest
+    })
+}
+
+/// JSON object of a paste just stored
+pub async fn stored_object(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    id: &str,
+) -> Result<PasteObject, Status> {
+    match store.meta(id).await {
+        Ok(Some(meta)) => Ok(PasteObject::new(config, meta)),
+        // Already expired
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            error!("[API] Error when reading the metadata: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[post("/api/v1/pastes", data = "<input>")]
+pub async fn create(
+    input: Data<'_>,
+    config: &State<BibinConfig>,
+    password: Credentials,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<WithEditToken<Custom<RawJson<String>>>, Status> {
+    let identity = password.authorize(config, Scope::Create).await?;
+
+    let (data, mut options) = read_new_paste(input, config)
+        .await?
+        .into_paste(config, Some(&identity))?;
+    let token = claim(&mut options, &identity);
+    let id = store.store(data, &options).await.map_err(|e| {
+        error!("[API_CREATE] {}", e);
+        Status::InternalServerError
+    })?;
+    let mut object = stored_object(store.as_ref(), config, &id).await?;
+    object.edit_token = Some(token.clone());
+    Ok(WithEditToken(
+        Custom(Status::Created, RawJson(object.to_json()?)),
+        Some(token),
+    ))
+}
+
+/// Metadata and URLs of a paste. This does not count as a view: the content is at `raw_url`.
+/// Those of a paste protected by a read password need the password, like its page.
+#[get("/api/v1/pastes/<id>")]
+pub async fn read(
+    id: &str,
+    password: ReadPassword,
+    config: &State<BibinConfig>,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<RawJson<String>, Status> {
+    let meta = match store.meta(id).await {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            error!("[API] Error when reading the metadata: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let hash = meta.read_password_hash.as_deref();
+    if !readpassword::opens(id, hash, password.0.as_deref()).await? {
+        return Err(Status::Unauthorized);
+    }
+    PasteObject::new(config, meta).to_json().map(RawJson)
+}
+
+#[put("/api/v1/pastes/<id>", data = "<input>")]
+pub async fn update(
+    id: &str,
+    input: Data<'_>,
+    config: &State<BibinConfig>,
+    password: Option<Credentials>,
+    token: Option<EditToken>,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<WithEditToken<RawJson<String>>, Status> {
+    let (identity, created) = authorize_store_at(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        id,
+    )
+    .await?;
+
+    let (data, mut options) = read_new_paste(input, config)
+        .await?
+        .into_paste(config, identity.as_ref())?;
+    let token = match (&identity, created) {
+        (Some(identity), true) => Some(claim(&mut options, identity)),
+        _ => None,
+    };
+    let id = store
+        .store_with_id(id.to_owned(), data, &options)
+        .await
+        .map_err(|e| {
+            error!("[API_UPDATE] {}", e);
+            Status::InternalServerError
+        })?;
+    let mut object = stored_object(store.as_ref(), config, &id).await?;
+    object.edit_token = token.clone();
+    Ok(WithEditToken(RawJson(object.to_json()?), token))
+}
+
+#[delete("/api/v1/pastes/<id>")]
+pub async fn delete(
+    id: &str,
+    config: &State<BibinConfig>,
+    password: Option<Credentials>,
+    token: Option<EditToken>,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Status {
+    let authorized = authorize_delete(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        id,
+    )
+    .await;
+    if let Err(status) = authorized {
+        return status;
+    }
+
+    match store.delete(id).await {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            error!("[API_DELETE] {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Time to live of the share links minted without one
+const DEFAULT_LINK_TTL: Ttl = Ttl(std::time::Duration::from_secs(24 * 60 * 60));
+
+/// A share link to mint. The body can be empty to get a link to the page for a day.
+#[derive(Default, Deserialize)]
+pub struct NewLink {
+    #[serde(default)]
+    mode: ShareMode,
+    /// Time to live of the link: 30m, 1h, 7d...
+    expire: Option<Ttl>,
+    max_views: Option<u32>,
+}
+
+/// A share link as described by the JSON API
+#[derive(Serialize)]
+pub struct LinkObject {
+    /// To revoke the link
+    pub id: String,
+    pub url: String,
+    pub mode: ShareMode,
+    pub expires_at: i64,
+    pub max_views: Option<u32>,
+}
+
+/// Check that the share links of the paste can be managed: by its owner, the holders of its
+/// edit token and the admins
+async fn authorize_links(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    credentials: Option<&Credentials>,
+    token: Option<&EditToken>,
+    id: &str,
+) -> Result<(), Status> {
+    if credentials.is_none() && token.is_none() {
+        return Err(Status::Unauthorized);
+    }
+    match store.meta(id).await {
+        Ok(Some(meta)) => {
+            auth::authorize_on_paste(&meta, config, credentials, token, Scope::Create).await?;
+            Ok(())
+        }
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            error!(
+                "[API_LINKS] Error when reading the metadata of {}: {}",
+                id, e
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Mint a signed link opening the paste, even with a read password, until it expires
+#[post("/api/v1/pastes/<id>/links", data = "<input>")]
+pub async fn create_link(
+    id: &str,
+    input: Data<'_>,
+    config: &State<BibinConfig>,
+    password: Option<Credentials>,
+    token: Option<EditToken>,
+    key: &State<ShareKey>,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<Custom<RawJson<String>>, Status> {
+    authorize_links(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        id,
+    )
+    .await?;
+
+    let body = read_raw_paste(input, 64.kibibytes()).await?;
+    let new_link = if body.is_empty() {
+        NewLink::default()
+    } else {
+        serde_json::from_slice::<NewLink>(&body).map_err(|e| {
+            warn!("[API_LINKS] Invalid link: {}", e);
+            Status::BadRequest
+        })?
+    };
+    if new_link.max_views == Some(0) {
+        return Err(Status::BadRequest);
+    }
+    let ttl = new_link.expire.unwrap_or(DEFAULT_LINK_TTL);
+    let ttl = config.max_ttl.map_or(ttl, |max_ttl| ttl.min(max_ttl));
+
+    let link = ShareLink {
+        id: ids::generate_private_id(),
+        paste_id: id.to_owned(),
+        mode: new_link.mode,
+        expires_at: expiration_date(Some(ttl)).unwrap_or(i64::MAX),
+        max_views: new_link.max_views,
+    };
+    store.add_link(&link).await.map_err(|e| {
+        error!("[API_LINKS] {}", e);
+        Status::InternalServerError
+    })?;
+
+    let path = match link.mode {
+        ShareMode::Page => uri!(get::get_item(id)).to_string(),
+        ShareMode::Raw => uri!(get::get_item_raw(id)).to_string(),
+        ShareMode::Qr => format!("{}.qr", uri!(get::get_item(id))),
+    };
+    let object = LinkObject {
+        url: format!("{}{}?share={}", config.prefix, path, key.sign(&link)),
+        id: link.id,
+        mode: link.mode,
+        expires_at: link.expires_at,
+        max_views: link.max_views,
+    };
+    Ok(Custom(Status::Created, RawJson(get::to_json(&object)?)))
+}
+
+/// Revoke a share link: it does not open the paste anymore
+#[delete("/api/v1/pastes/<id>/links/<link>")]
+pub async fn revoke_link(
+    id: &str,
+    link: &str,
+    config: &State<BibinConfig>,
+    password: Option<Credentials>,
+    token: Option<EditToken>,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Status {
+    let authorized = authorize_links(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        id,
+    )
+    .await;
+    if let Err(status) = authorized {
+        return status;
+    }
+
+    match store.revoke_link(id, link).await {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            error!("[API_LINKS] {}", e);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Utilisation of the keyspace of the short ids, and the length of the new ones
+#[get("/api/v1/keyspace")]
+pub async fn keyspace(
+    config: &State<BibinConfig>,
+    password: Credentials,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<RawJson<String>, Status> {
+    password.authorize(config, Scope::Admin).await?;
+    let usage = store.id_usage().await.map_err(|e| {
+        error!("[API_KEYSPACE] {}", e);
+        Status::InternalServerError
+    })?;
+    serde_json::to_string(&usage.report(config.id_length))
+        .map(RawJson)
+        .map_err(|e| {
+            error!("[API_KEYSPACE] {}", e);
+            Status::InternalServerError
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::BibinConfig;
+    use crate::get::{get_item, get_item_raw, get_qr};
+    use crate::highlight::Highlighter;
+    use crate::rocket;
+    use crate::share::ShareKey;
+    use crate::store;
+    use crate::write::{delete as delete_paste, submit_raw, submit_raw_with_key};
+    use rocket::config::SecretKey;
+    use rocket::http::{Accept, ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::Value;
+    use tempfile::NamedTempFile;
+
+    use super::{create, create_link, delete, keyspace, read, revoke_link, update};
+
+    const PASSWORD: &str = "password123";
+
+    async fn create_test_client() -> (NamedTempFile, Client) {
+        let temp = NamedTempFile::new().unwrap();
+        let mut config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "password123", "prefix": "https://bib.in", "max_paste_size": 64,
+                 "keys": [
+                     { "name": "ci", "key": "ci-key", "scopes": ["create"] },
+                     { "name": "alice", "key": "alice-key",
+                       "scopes": ["create", "overwrite", "delete"] },
+                     { "name": "bob", "key": "bob-key",
+                       "scopes": ["create", "overwrite", "delete"] },
+                     { "name": "vault", "key": "vault-key", "scopes": ["create"],
+                       "private": true }
+                 ] }"#,
+        )
+        .unwrap();
+        config.database_file = temp.path().to_str().unwrap().to_owned();
+        let store = store::open(&config).await.unwrap();
+
+        let rocket = rocket::custom(config.apply_limits(rocket::Config::figment()))
+            .manage(store)
+            .manage(Highlighter::new())
+            .manage(ShareKey::new(Some(&SecretKey::from(&[1; 64]))))
+            .manage(config)
+            .mount(
+                "/",
+                routes![
+                    create,
+                    read,
+                    update,
+                    delete,
+                    keyspace,
+                    create_link,
+                    revoke_link,
+                    submit_raw,
+                    submit_raw_with_key,
+                    get_item,
+                    get_item_raw,
+                    get_qr,
+                    delete_paste
+                ],
+            );
+        (temp, Client::untracked(rocket).await.unwrap())
+    }
+
+    async fn json_of(response: rocket::local::asynchronous::LocalResponse<'_>) -> Value {
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_create_read_update_delete() {
+        let (_temp, client) = create_test_client().await;
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(r#"{ "content": "fn main() {}", "language": "rs", "max_views": 3 }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let object = json_of(response).await;
+        let id = object["id"].as_str().unwrap().to_owned();
+        assert_eq!(object["url"], format!("https://bib.in/{}.rs", id));
+        assert_eq!(object["raw_url"], format!("https://bib.in/{}/raw", id));
+        assert_eq!(object["qr_url"], format!("https://bib.in/{}/qr", id));
+        assert_eq!(object["metadata"]["views_left"], 3);
+        assert_eq!(object["metadata"]["size"], 12);
+        assert!(object.get("content").is_none());
+
+        // Reading the metadata is not a view
+        let path = format!("/api/v1/pastes/{}", id);
+        let object = json_of(client.get(path.clone()).dispatch().await).await;
+        assert_eq!(object["metadata"]["views_left"], 3);
+        assert_eq!(object["metadata"]["language"], "rs");
+
+        let response = client
+            .put(path.clone())
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(r#"{ "content": "AAEC/w==", "encoding": "base64" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let object = json_of(response).await;
+        assert_eq!(object["metadata"]["size"], 4);
+        assert_eq!(object["metadata"]["revision"], 2);
+
+        let response = client
+            .get(format!("/{}", id))
+            .header(Accept::JSON)
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        assert_eq!(object["content"], "AAEC/w==");
+        assert_eq!(object["encoding"], "base64");
+
+        let response = client.delete(path.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .delete(path.clone())
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.get(path.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client
+            .delete(path)
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_read_password() {
+        let (_temp, client) = create_test_client().await;
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(r#"{ "content": "secret notes", "read_password": "open" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let id = json_of(response).await["id"].as_str().unwrap().to_owned();
+
+        let path = format!("/api/v1/pastes/{}", id);
+        let response = client.get(path.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get(path.clone())
+            .header(Header::new("X-Read-Password", "wrong"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get(path)
+            .header(Header::new("X-Read-Password", "open"))
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        assert_eq!(object["id"], id);
+        assert!(object["metadata"].get("read_password_hash").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn test_invalid_pastes() {
+        let (_temp, client) = create_test_client().await;
+
+        for (body, status) in [
+            (r#"{ "content": "test" }"#, Status::Unauthorized),
+            (r#"{ "content": 12 }"#, Status::BadRequest),
+            (
+                r#"{ "content": "not base64", "encoding": "base64" }"#,
+                Status::BadRequest,
+            ),
+            (
+                r#"{ "content": "test", "language": "../../etc" }"#,
+                Status::BadRequest,
+            ),
+            (
+                r#"{ "content": "test", "max_views": 0 }"#,
+                Status::BadRequest,
+            ),
+        ] {
+            let mut request = client.post("/api/v1/pastes").body(body);
+            if status != Status::Unauthorized {
+                request = request.header(Header::new("X-API-Key", PASSWORD));
+            }
+            assert_eq!(request.dispatch().await.status(), status, "{}", body);
+        }
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(format!(r#"{{ "content": "{}" }}"#, "a".repeat(65)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+
+    #[rocket::async_test]
+    async fn test_scopes() {
+        let (_temp, client) = create_test_client().await;
+        let ci_key = Header::new("X-API-Key", "ci-key");
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(ci_key.clone())
+            .body(r#"{ "content": "build log" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let object = json_of(response).await;
+        assert_eq!(object["metadata"]["creator"], "ci");
+        let path = format!("/api/v1/pastes/{}", object["id"].as_str().unwrap());
+
+        // Creating a paste at a free id is allowed, replacing one is not
+        let response = client
+            .put("/api/v1/pastes/new-build")
+            .header(ci_key.clone())
+            .body(r#"{ "content": "build log" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .put(path.clone())
+            .header(ci_key.clone())
+            .body(r#"{ "content": "another log" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .put("/new-build")
+            .header(ci_key.clone())
+            .body("another log")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client.delete(path.clone()).header(ci_key).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .delete(path)
+            .header(Header::new("X-API-Key", "unknown"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_ownership() {
+        let (_temp, client) = create_test_client().await;
+        let alice = Header::new("X-API-Key", "alice-key");
+        let bob = Header::new("X-API-Key", "bob-key");
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(alice.clone())
+            .body(r#"{ "content": "alice's" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let token = response
+            .headers()
+            .get_one("X-Edit-Token")
+            .unwrap()
+            .to_owned();
+        let object = json_of(response).await;
+        assert_eq!(object["edit_token"], token.as_str());
+        assert_eq!(object["metadata"]["owner"], "key:alice");
+        assert!(object["metadata"].get("token_hash").is_none());
+        let path = format!("/api/v1/pastes/{}", object["id"].as_str().unwrap());
+
+        // Only the owner of the paste and the admins manage it
+        let response = client
+            .put(path.clone())
+            .header(bob.clone())
+            .body(r#"{ "content": "bob's" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client.delete(path.clone()).header(bob).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .put(path.clone())
+            .header(alice.clone())
+            .body(r#"{ "content": "alice's, again" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        // Only a new paste comes with a token
+        assert!(response.headers().get_one("X-Edit-Token").is_none());
+        let object = json_of(response).await;
+        assert!(object.get("edit_token").is_none());
+        assert_eq!(object["metadata"]["owner"], "key:alice");
+        let response = client
+            .put(path.clone())
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(r#"{ "content": "admin's" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // The token is enough, without a key
+        let response = client
+            .put(path.clone())
+            .header(Header::new("X-Edit-Token", "wrong"))
+            .body(r#"{ "content": "anonymous" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .put(path.clone())
+            .header(Header::new("X-Edit-Token", token.clone()))
+            .body(r#"{ "content": "anonymous" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .delete(path.clone())
+            .header(Header::new("X-Edit-Token", token.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        // Raw pastes too
+        let response = client
+            .put("/")
+            .header(Header::new("X-API-Key", "ci-key"))
+            .body("build log")
+            .dispatch()
+            .await;
+        let token = response
+            .headers()
+            .get_one("X-Edit-Token")
+            .unwrap()
+            .to_owned();
+        let url = response.into_string().await.unwrap();
+        let id = url.trim_start_matches("https://bib.in/");
+        let response = client
+            .delete(format!("/{}", id))
+            .header(Header::new("X-API-Key", "alice-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .delete(format!("/{}", id))
+            .header(Header::new("X-Edit-Token", token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_share_links() {
+        let (_temp, client) = create_test_client().await;
+        let response = client
+            .post("/api/v1/pastes")
+            .header(Header::new("X-API-Key", "alice-key"))
+            .body(r#"{ "content": "for your eyes only", "read_password": "secret" }"#)
+            .dispatch()
+            .await;
+        let id = json_of(response).await["id"].as_str().unwrap().to_owned();
+        let links = format!("/api/v1/pastes/{}/links", id);
+
+        // Only the owner, its edit token and the admins can share the paste
+        let response = client
+            .post(links.as_str())
+            .header(Header::new("X-API-Key", "bob-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client.post(links.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post(links.as_str())
+            .header(Header::new("X-API-Key", "alice-key"))
+            .body(r#"{ "mode": "raw", "expire": "1h", "max_views": 2 }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let link = json_of(response).await;
+        assert_eq!(link["mode"], "raw");
+        assert_eq!(link["max_views"], 2);
+        let url = link["url"].as_str().unwrap();
+        let prefix = format!("https://bib.in/{}/raw?share=", id);
+        assert!(url.starts_with(&prefix), "{}", url);
+        let uri = url.trim_start_matches("https://bib.in");
+
+        // The link opens the raw content only, without the password, twice
+        let response = client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "for your eyes only");
+        let page = uri.replace("/raw?", "?");
+        let response = client.get(page).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(client.get(uri).dispatch().await.status(), Status::Ok);
+        assert_eq!(client.get(uri).dispatch().await.status(), Status::Forbidden);
+
+        // Forged links are refused
+        let forged = format!("{}x", uri);
+        assert_eq!(
+            client.get(forged).dispatch().await.status(),
+            Status::Forbidden
+        );
+        let other = client
+            .post(links.as_str())
+            .header(Header::new("X-API-Key", "alice-key"))
+            .dispatch()
+            .await;
+        let other = json_of(other).await;
+        let (_, token) = other["url"].as_str().unwrap().split_once("share=").unwrap();
+        let elsewhere = format!("/{}?share={}", "abcd", token);
+        assert_ne!(client.get(elsewhere).dispatch().await.status(), Status::Ok);
+
+        // A QR code link opens both QR codes, and nothing else
+        let response = client
+            .post(links.as_str())
+            .header(Header::new("X-API-Key", "alice-key"))
+            .body(r#"{ "mode": "qr" }"#)
+            .dispatch()
+            .await;
+        let qr = json_of(response).await;
+        let (_, qr_token) = qr["url"].as_str().unwrap().split_once("share=").unwrap();
+        for uri in [
+            format!("/{}.qr?share={}", id, qr_token),
+            format!("/{}/qr?share={}", id, qr_token),
+        ] {
+            let response = client.get(uri.as_str()).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", uri);
+            assert_eq!(response.content_type(), Some(ContentType::PNG), "{}", uri);
+        }
+        let uri = format!("/{}/qr?share={}", id, token);
+        assert_eq!(client.get(uri).dispatch().await.status(), Status::Forbidden);
+
+        // Until it is revoked, the default link opens the page
+        let uri = other["url"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches("https://bib.in");
+        assert_eq!(client.get(uri).dispatch().await.status(), Status::Ok);
+        let revoke = format!("{}/{}", links, other["id"].as_str().unwrap());
+        let response = client
+            .delete(revoke.as_str())
+            .header(Header::new("X-API-Key", "alice-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(client.get(uri).dispatch().await.status(), Status::Forbidden);
+        let response = client
+            .delete(revoke.as_str())
+            .header(Header::new("X-API-Key", "alice-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_keyspace() {
+        let (_temp, client) = create_test_client().await;
+        client
+            .put("/")
+            .header(Header::new("X-API-Key", "ci-key"))
+            .body("some text")
+            .dispatch()
+            .await;
+
+        let response = client
+            .get("/api/v1/keyspace")
+            .header(Header::new("X-API-Key", "ci-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .get("/api/v1/keyspace")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        let report = json_of(response).await;
+        assert_eq!(report["id_length"], 4);
+        assert_eq!(report["current_length"], 4);
+        assert_eq!(report["lengths"][0]["used"], 1);
+        assert_eq!(report["lengths"][0]["capacity"], 707_281);
+    }
+
+    #[rocket::async_test]
+    async fn test_private() {
+        let (_temp, client) = create_test_client().await;
+
+        let id_of = |key: &'static str, private: Option<&'static str>| {
+            let mut request = client
+                .put("/")
+                .header(Header::new("X-API-Key", key))
+                .body("some secret");
+            if let Some(private) = private {
+                request = request.header(Header::new("X-Private", private));
+            }
+            async move {
+                let url = request.dispatch().await.into_string().await.unwrap();
+                url.trim_start_matches("https://bib.in/").to_owned()
+            }
+        };
+        assert_eq!(id_of(PASSWORD, None).await.len(), 4);
+        assert_eq!(id_of(PASSWORD, Some("1")).await.len(), 32);
+        assert_eq!(id_of("vault-key", None).await.len(), 32);
+        assert_eq!(id_of("vault-key", Some("false")).await.len(), 4);
+
+        let response = client
+            .post("/api/v1/pastes")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(r#"{ "content": "some secret", "private": true }"#)
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        assert_eq!(object["id"].as_str().unwrap().len(), 32);
+        assert_eq!(object["metadata"]["private"], true);
+
+        let response = client
+            .put("/")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .header(Header::new("X-Private", "maybe"))
+            .body("some secret")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_content_negotiation() {
+        let (_temp, client) = create_test_client().await;
+
+        let response = client
+            .put("/")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body("burn after reading")
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .starts_with("https://bib.in/"));
+
+        let response = client
+            .put("/")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .header(Header::new("X-Burn-After-Reading", "1"))
+            .header(Accept::JSON)
+            .body("burn after reading")
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        let id = object["id"].as_str().unwrap().to_owned();
+        assert_eq!(object["metadata"]["views_left"], 1);
+
+        // The last view is still answered with the metadata
+        let response = client
+            .get(format!("/{}", id))
+            .header(Accept::JSON)
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        assert_eq!(object["content"], "burn after reading");
+        assert_eq!(object["encoding"], "utf8");
+        assert_eq!(object["metadata"]["views_left"], 0);
+        let response = client
+            .get(format!("/{}", id))
+            .header(Accept::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .put("/")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body("to delete")
+            .dispatch()
+            .await;
+        let url = response.into_string().await.unwrap();
+        let id = url.trim_start_matches("https://bib.in/");
+        let response = client
+            .delete(format!("/{}", id))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .header(Accept::JSON)
+            .dispatch()
+            .await;
+        let object = json_of(response).await;
+        assert_eq!(object["id"], id);
+        assert_eq!(object["deleted"], true);
+    }
+}
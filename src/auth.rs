@@ -1,17 +1,364 @@
+use crate::config::BibinConfig;
+use crate::io::PasteMeta;
+use crate::tailscale::LocalApi;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use rocket::form;
 use rocket::http::Status;
 use rocket::outcome::Outcome;
 use rocket::request::{self, FromRequest, Request};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+use subtle::ConstantTimeEq;
 
+/// What a key allows to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Upload new pastes
+    Create,
+    /// Replace an existing paste
+    Overwrite,
+    Delete,
+    /// Read every paste at once: `all_entries`, the search and the listings
+    List,
+    /// Replace and delete the pastes of the others, along with the overwrite and delete scopes
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::Create,
+        Scope::Overwrite,
+        Scope::Delete,
+        Scope::List,
+        Scope::Admin,
+    ];
+}
+
+/// A named key of the configuration, limited to some scopes
+#[derive(Clone, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    /// `key_hash`, or `key` in plaintext
+    #[serde(flatten)]
+    pub key: KeySecret,
+    pub scopes: Vec<Scope>,
+    /// Its pastes are private unless asked otherwise
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// The main password of the configuration
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Password {
+    /// Argon2 hash in the PHC format, as printed by `bibin hash-password`
+    Hashed { password_hash: String },
+    /// Plaintext, still accepted for the existing configurations
+    Plain { password: AuthKey },
+}
+
+impl Password {
+    /// Whether the key is the password, without leaking where they differ
+    pub async fn matches(&self, key: &str) -> bool {
+        match self {
+            Password::Hashed { password_hash } => check_password(key, password_hash).await,
+            Password::Plain { password } => constant_time_eq(&password.0, key),
+        }
+    }
+
+    /// Refuse an unusable hash, and warn about a plaintext password
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Password::Hashed { password_hash } => PasswordHash::new(password_hash)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid password_hash: {}", e)),
+            Password::Plain { .. } => {
+                warn!(
+                    "[AUTH] The password is stored in plaintext in the configuration, replace it \
+                     with the password_hash printed by `bibin hash-password`"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The secret of a named key, stored like the main password
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum KeySecret {
+    /// Argon2 hash in the PHC format, as printed by `bibin hash-password`
+    Hashed { key_hash: String },
+    /// Plaintext, still accepted for the existing configurations
+    Plain { key: String },
+}
+
+impl KeySecret {
+    /// Whether the key given by a client is this one, without leaking where they differ
+    pub async fn matches(&self, key: &str) -> bool {
+        match self {
+            KeySecret::Hashed { key_hash } => check_password(key, key_hash).await,
+            KeySecret::Plain { key: secret } => constant_time_eq(secret, key),
+        }
+    }
+
+    fn is_hashed(&self) -> bool {
+        matches!(self, KeySecret::Hashed { .. })
+    }
+}
+
+impl ApiKey {
+    fn identity(&self) -> Identity {
+        Identity {
+            name: Some(self.name.clone()),
+            owner: Some(format!("key:{}", self.name)),
+            scopes: self.scopes.clone(),
+            private: self.private,
+        }
+    }
+
+    /// Refuse an unusable hash, and warn about a plaintext key
+    pub fn check(&self) -> Result<(), String> {
+        match &self.key {
+            KeySecret::Hashed { key_hash } => PasswordHash::new(key_hash)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid key_hash of the key {}: {}", self.name, e)),
+            KeySecret::Plain { .. } => {
+                warn!(
+                    "[AUTH] The key {} is stored in plaintext in the configuration, replace it \
+                     with the key_hash printed by `bibin hash-password`",
+                    self.name
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Argon2 hash of a password, with a random salt, in the PHC format expected by `password_hash`
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Whether the password is the one of this Argon2 hash
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            error!("[AUTH] Invalid password hash: {}", e);
+            false
+        }
+    }
+}
+
+/// Passwords already checked against a hash, so that a client repeating a valid one does not pay
+/// for Argon2 each time. Only an HMAC of the hash and the password is kept, under a key drawn at
+/// startup.
+struct VerifiedPasswords {
+    key: [u8; 32],
+    macs: Mutex<HashSet<Vec<u8>>>,
+}
+
+/// Forgotten all at once when full, the valid passwords are then checked again
+const MAX_VERIFIED_PASSWORDS: usize = 1024;
+
+impl VerifiedPasswords {
+    fn get() -> &'static VerifiedPasswords {
+        static VERIFIED: OnceLock<VerifiedPasswords> = OnceLock::new();
+        VERIFIED.get_or_init(|| VerifiedPasswords {
+            key: rand::random(),
+            macs: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn mac(&self, password: &str, hash: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key");
+        mac.update(hash.as_bytes());
+        mac.update(&[0]);
+        mac.update(password.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn macs(&self) -> std::sync::MutexGuard<'_, HashSet<Vec<u8>>> {
+        // The set stays consistent even if a thread panicked while holding it
+        self.macs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether the password is the one of this Argon2 hash, like `verify_password` but outside of
+/// the async executor, and remembering the passwords that were right
+pub async fn check_password(password: &str, hash: &str) -> bool {
+    let verified = VerifiedPasswords::get();
+    let mac = verified.mac(password, hash);
+    if verified.macs().contains(&mac) {
+        return true;
+    }
+    let (password, hash) = (password.to_owned(), hash.to_owned());
+    let valid = rocket::tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or_else(|e| {
+            error!("[AUTH] Error when checking a password: {}", e);
+            false
+        });
+    if valid {
+        let mut macs = verified.macs();
+        if macs.len() >= MAX_VERIFIED_PASSWORDS {
+            macs.clear();
+        }
+        macs.insert(mac);
+    }
+    valid
+}
+
+/// Compare two secrets in a time that only depends on their lengths
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Whoever a known key belongs to
+#[derive(Clone, Debug)]
+pub struct Identity {
+    name: Option<String>,
+    /// Recorded on the pastes it creates, None for the main password
+    owner: Option<String>,
+    scopes: Vec<Scope>,
+    /// Whether its pastes are private by default
+    private: bool,
+}
+
+impl Identity {
+    /// Name of the key, the user of the Basic authentication for the main password, or the
+    /// Tailscale login
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Whether this identity may replace or delete the paste: its own, or any as an admin
+    pub fn may_manage(&self, meta: &PasteMeta) -> bool {
+        self.allows(Scope::Admin) || (self.owner.is_some() && self.owner == meta.owner)
+    }
+}
+
+/// Whether a new paste is private: as requested, or by default for its author
+pub fn is_private(requested: Option<bool>, identity: Option<&Identity>) -> bool {
+    requested.unwrap_or_else(|| identity.is_some_and(|identity| identity.private))
+}
+
+/// Secret given to the author of a new paste, to replace or delete it without a key
+pub struct EditToken(String);
+
+impl EditToken {
+    /// A new token, and the hash to store along with the paste
+    pub fn generate() -> (String, String) {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let hash = token_hash(&token);
+        (token, hash)
+    }
+
+    pub fn matches(&self, meta: &PasteMeta) -> bool {
+        meta.token_hash
+            .as_deref()
+            .is_some_and(|hash| constant_time_eq(hash, &token_hash(&self.0)))
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for EditToken {
+    type Error = AuthError;
+
+    async fn from_request(request: &'a Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let tokens: Vec<_> = request.headers().get("X-Edit-Token").collect();
+        match tokens.len() {
+            0 => Outcome::Forward(Status::Unauthorized),
+            1 => Outcome::Success(EditToken(tokens[0].to_owned())),
+            _ => Outcome::Error((Status::BadRequest, AuthError::BadCount)),
+        }
+    }
+}
+
+/// Identity allowed to `scope` (overwrite or delete) an existing paste: with its edit token, or
+/// as its owner or an admin. None when the request only has the token.
+pub async fn authorize_on_paste(
+    meta: &PasteMeta,
+    config: &BibinConfig,
+    credentials: Option<&Credentials>,
+    token: Option<&EditToken>,
+    scope: Scope,
+) -> Result<Option<Identity>, Status> {
+    if let Some(token) = token {
+        if !token.matches(meta) {
+            warn!("[AUTH] Invalid edit token for {}", meta.id);
+            return Err(Status::Forbidden);
+        }
+        return Ok(match credentials {
+            Some(credentials) => credentials.identify(config).await,
+            None => None,
+        });
+    }
+
+    let identity = credentials
+        .ok_or(Status::Unauthorized)?
+        .authorize(config, scope)
+        .await?;
+    if !identity.may_manage(meta) {
+        warn!(
+            "[AUTH] {} is not allowed to {:?} {}, owned by {:?}",
+            identity.name().unwrap_or("The password"),
+            scope,
+            meta.id,
+            meta.owner
+        );
+        return Err(Status::Forbidden);
+    }
+    Ok(Some(identity))
+}
+
+/// A tailnet member allowed to use bibin without a key
+#[derive(Clone, Deserialize)]
+pub struct TailnetMember {
+    /// Tailscale login, tag of the tagged nodes (`tag:ci`), or `*` for every member of the tailnet
+    pub login: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// A key given by a client, along with an optional label (the user of the Basic authentication)
 #[derive(Clone, Deserialize)]
 #[serde(transparent)]
-pub struct AuthKey(String);
+pub struct AuthKey(String, #[serde(skip)] Option<String>);
 
 impl<'v> rocket::form::FromFormField<'v> for AuthKey {
     fn from_value(value: form::ValueField) -> form::Result<'v, Self> {
-        Ok(AuthKey(String::from(value.value)))
+        Ok(AuthKey(String::from(value.value), None))
     }
 }
 
@@ -24,8 +371,53 @@ pub enum AuthError {
 }
 
 impl AuthKey {
-    pub fn is_valid(&self, password: &AuthKey) -> bool {
-        password.0 == self.0
+    /// Who this key belongs to: the main password has every scope, the named keys the ones they
+    /// are configured with. None for an unknown key.
+    ///
+    /// Checking a hash is slow, so a key stored hashed is named by the client: as the user of the
+    /// Basic authentication, or as `<name>:<key>`. At most one hash is checked per request, the
+    /// one of this key or else the one of the main password.
+    pub async fn identify(&self, config: &BibinConfig) -> Option<Identity> {
+        if let Some(api_key) = config.keys.iter().find(|api_key| match &api_key.key {
+            KeySecret::Plain { key } => constant_time_eq(key, &self.0),
+            KeySecret::Hashed { .. } => false,
+        }) {
+            return Some(api_key.identity());
+        }
+        if let Some((api_key, secret)) = self.hashed_key(config) {
+            return match api_key.key.matches(secret).await {
+                true => Some(api_key.identity()),
+                false => None,
+            };
+        }
+        if config.password.matches(&self.0).await {
+            return Some(Identity {
+                name: self.label().map(str::to_owned),
+                owner: None,
+                scopes: Scope::ALL.to_vec(),
+                private: false,
+            });
+        }
+        None
+    }
+
+    /// The hashed key named by the client, and the secret to check against it
+    fn hashed_key<'k>(&'k self, config: &'k BibinConfig) -> Option<(&'k ApiKey, &'k str)> {
+        config
+            .keys
+            .iter()
+            .filter(|api_key| api_key.key.is_hashed())
+            .find_map(|api_key| {
+                if self.label() == Some(api_key.name.as_str()) {
+                    return Some((api_key, self.0.as_str()));
+                }
+                let secret = self.0.strip_prefix(api_key.name.as_str())?;
+                Some((api_key, secret.strip_prefix(':')?))
+            })
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.1.as_deref()
     }
 }
 
@@ -47,7 +439,7 @@ fn auth_from_api_header(request: &'_ Request<'_>) -> Result<Option<AuthKey>, Aut
     let api_keys: Vec<_> = request.headers().get("X-API-Key").collect();
     match api_keys.len() {
         0 => Ok(None),
-        1 => Ok(Some(AuthKey(api_keys[0].to_string()))),
+        1 => Ok(Some(AuthKey(api_keys[0].to_string(), None))),
         _ => Err(AuthError::BadCount),
     }
 }
@@ -68,7 +460,10 @@ fn auth_from_auth_header(request: &'_ Request<'_>) -> Result<Option<AuthKey>, Au
                     Err(AuthError::InvalidData)
                 } else {
                     let decoded_token: Vec<&str> = decoded.splitn(2, ':').collect();
-                    Ok(Some(AuthKey(decoded_token[1].to_string())))
+                    let label = Some(decoded_token[0])
+                        .filter(|user| !user.is_empty())
+                        .map(str::to_owned);
+                    Ok(Some(AuthKey(decoded_token[1].to_string(), label)))
                 }
             }
         }
@@ -99,14 +494,159 @@ impl<'a> FromRequest<'a> for AuthKey {
     }
 }
 
+/// The tailnet member sending the request. Either from the headers added by `tailscale serve`,
+/// only trusted when the request comes from one of the `trusted_proxies`, or asked to tailscaled
+/// when `tailscale_socket` is configured.
+#[derive(Clone, Debug)]
+pub struct TailscaleLogin {
+    /// Login of the user, or name of a tagged node
+    login: String,
+    tags: Vec<String>,
+}
+
+impl TailscaleLogin {
+    pub fn new(login: String, tags: Vec<String>) -> TailscaleLogin {
+        TailscaleLogin { login, tags }
+    }
+
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    /// The scopes of this member, of one of the tags of the node, or of every member (`*`). None
+    /// when the member is not allowed.
+    pub fn identify(&self, config: &BibinConfig) -> Option<Identity> {
+        let find = |login: &str| {
+            config
+                .tailscale_users
+                .iter()
+                .find(|member| member.login == login)
+        };
+        let member = find(&self.login)
+            .or_else(|| self.tags.iter().find_map(|tag| find(tag)))
+            .or_else(|| find("*"))?;
+        Some(Identity {
+            name: Some(self.login.clone()),
+            owner: Some(format!("tailscale:{}", self.login)),
+            scopes: member.scopes.clone(),
+            private: false,
+        })
+    }
+}
+
+/// Login given by a trusted proxy
+fn tailscale_login_from_headers(
+    request: &Request<'_>,
+    config: &BibinConfig,
+) -> Option<TailscaleLogin> {
+    let login = request
+        .headers()
+        .get_one("Tailscale-User-Login")
+        .filter(|login| !login.is_empty())?;
+    let remote = request.remote().map(|remote| remote.ip().to_canonical());
+    if !remote.is_some_and(|ip| config.trusted_proxies.contains(&ip)) {
+        warn!(
+            "[AUTH] Ignoring the Tailscale headers sent by {:?}, not a trusted proxy",
+            remote
+        );
+        return None;
+    }
+    Some(TailscaleLogin::new(login.to_owned(), Vec::new()))
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for TailscaleLogin {
+    type Error = AuthError;
+
+    async fn from_request(request: &'a Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let config = match request.rocket().state::<BibinConfig>() {
+            Some(config) => config,
+            None => return Outcome::Forward(Status::Unauthorized),
+        };
+        if let Some(login) = tailscale_login_from_headers(request, config) {
+            return Outcome::Success(login);
+        }
+
+        let local_api = request.rocket().state::<LocalApi>();
+        let login = match (local_api, request.remote()) {
+            (Some(local_api), Some(remote)) => local_api.whois(remote).await,
+            _ => None,
+        };
+        match login {
+            Some(login) => Outcome::Success(login),
+            None => Outcome::Forward(Status::Unauthorized),
+        }
+    }
+}
+
+/// Whatever authenticates a request: a key, or else the login of a tailnet member
+pub enum Credentials {
+    Key(AuthKey),
+    Tailnet(TailscaleLogin),
+}
+
+impl Credentials {
+    /// Credentials of a form: the password when one was typed, the tailnet login otherwise
+    pub fn from_form(password: Option<AuthKey>, tailnet: Option<TailscaleLogin>) -> Credentials {
+        match (password, tailnet) {
+            (Some(key), Some(login)) if key.0.is_empty() => Credentials::Tailnet(login),
+            (None, Some(login)) => Credentials::Tailnet(login),
+            (key, _) => Credentials::Key(key.unwrap_or_else(|| AuthKey(String::new(), None))),
+        }
+    }
+
+    pub async fn identify(&self, config: &BibinConfig) -> Option<Identity> {
+        match self {
+            Credentials::Key(key) => key.identify(config).await,
+            Credentials::Tailnet(login) => login.identify(config),
+        }
+    }
+
+    /// Identity allowed to do `scope`: 401 for unknown credentials, 403 without the scope
+    pub async fn authorize(&self, config: &BibinConfig, scope: Scope) -> Result<Identity, Status> {
+        let identity = self.identify(config).await.ok_or(Status::Unauthorized)?;
+        if !identity.allows(scope) {
+            warn!(
+                "[AUTH] {} is not allowed to {:?}",
+                identity.name().unwrap_or("The password"),
+                scope
+            );
+            return Err(Status::Forbidden);
+        }
+        Ok(identity)
+    }
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for Credentials {
+    type Error = AuthError;
+
+    async fn from_request(request: &'a Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.guard::<AuthKey>().await {
+            Outcome::Success(key) => return Outcome::Success(Credentials::Key(key)),
+            Outcome::Error((_, AuthError::Missing)) => (),
+            Outcome::Error(error) => return Outcome::Error(error),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        }
+
+        match request.guard::<TailscaleLogin>().await {
+            Outcome::Success(login) => Outcome::Success(Credentials::Tailnet(login)),
+            _ => Outcome::Error((Status::Unauthorized, AuthError::Missing)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::rocket;
     use rocket::http::Header;
     use rocket::http::Status;
     use rocket::local::blocking::Client;
+    use rocket::State;
+    use std::net::SocketAddr;
 
-    use super::AuthKey;
+    use super::{hash_password, AuthKey, Credentials, Password, Scope};
+    use crate::config::BibinConfig;
 
     /// Dummy endpoint that returns the authentication
     /// key for testing purposes
@@ -115,6 +655,213 @@ mod test {
         key.0
     }
 
+    #[get("/tests/authlabel")]
+    fn tests_authlabel(key: AuthKey) -> String {
+        format!("{:?}", key.label())
+    }
+
+    #[rocket::async_test]
+    async fn test_identify() {
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "main", "prefix": "/", "keys": [
+                { "name": "ci", "key": "ci-key", "scopes": ["create"] },
+                { "name": "admin", "key": "admin-key", "scopes": ["list", "delete"] }
+            ] }"#,
+        )
+        .unwrap();
+
+        let identity = AuthKey("main".to_owned(), Some("me".to_owned()))
+            .identify(&config)
+            .await
+            .unwrap();
+        assert_eq!(identity.name(), Some("me"));
+        assert!(Scope::ALL.iter().all(|scope| identity.allows(*scope)));
+
+        let key = AuthKey("ci-key".to_owned(), Some("ignored".to_owned()));
+        assert_eq!(key.identify(&config).await.unwrap().name(), Some("ci"));
+        let credentials = Credentials::Key(key);
+        assert!(credentials.authorize(&config, Scope::Create).await.is_ok());
+        assert_eq!(
+            credentials
+                .authorize(&config, Scope::Overwrite)
+                .await
+                .unwrap_err(),
+            Status::Forbidden
+        );
+        let credentials = Credentials::Key(AuthKey("admin-key".to_owned(), None));
+        assert!(credentials.authorize(&config, Scope::List).await.is_ok());
+        assert_eq!(
+            credentials
+                .authorize(&config, Scope::Create)
+                .await
+                .unwrap_err(),
+            Status::Forbidden
+        );
+
+        let key = AuthKey("ci".to_owned(), None);
+        assert!(key.identify(&config).await.is_none());
+        assert_eq!(
+            Credentials::Key(key)
+                .authorize(&config, Scope::Create)
+                .await
+                .unwrap_err(),
+            Status::Unauthorized
+        );
+    }
+
+    /// Dummy endpoint that returns the identity of the request
+    #[get("/tests/identity")]
+    async fn tests_identity(credentials: Credentials, config: &State<BibinConfig>) -> String {
+        match credentials.identify(config).await {
+            Some(identity) => format!("{:?}", identity),
+            None => "unknown".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_tailscale_headers() {
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "main", "prefix": "/", "trusted_proxies": ["127.0.0.1", "::1"],
+                 "tailscale_users": [
+                    { "login": "alice@example.com", "scopes": ["create", "list"] },
+                    { "login": "*", "scopes": ["create"] }
+                 ] }"#,
+        )
+        .unwrap();
+        let rocket = rocket::build()
+            .manage(config)
+            .mount("/", routes![tests_identity]);
+        let client = Client::debug(rocket).unwrap();
+        let proxy: SocketAddr = "127.0.0.1:41641".parse().unwrap();
+
+        let identity = |login: &str, remote: SocketAddr| {
+            let response = client
+                .get(uri!(tests_identity))
+                .header(Header::new("Tailscale-User-Login", login.to_owned()))
+                .header(Header::new("Tailscale-User-Name", "Alice"))
+                .remote(remote)
+                .dispatch();
+            (
+                response.status(),
+                response.into_string().unwrap_or_default(),
+            )
+        };
+
+        let (status, body) = identity("alice@example.com", proxy);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            body,
+            r#"Identity { name: Some("alice@example.com"), owner: Some("tailscale:alice@example.com"), scopes: [Create, List], private: false }"#
+        );
+        let (_, body) = identity("bob@example.com", proxy);
+        assert_eq!(
+            body,
+            r#"Identity { name: Some("bob@example.com"), owner: Some("tailscale:bob@example.com"), scopes: [Create], private: false }"#
+        );
+        let (_, body) = identity(
+            "alice@example.com",
+            "[::ffff:127.0.0.1]:41641".parse().unwrap(),
+        );
+        assert!(body.contains("alice@example.com"), "{}", body);
+
+        // Anybody else could send the headers
+        let (status, _) = identity("alice@example.com", "100.64.0.2:41641".parse().unwrap());
+        assert_eq!(status, Status::Unauthorized);
+        let response = client
+            .get(uri!(tests_identity))
+            .header(Header::new("Tailscale-User-Login", "alice@example.com"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        // A key is preferred to the headers
+        let response = client
+            .get(uri!(tests_identity))
+            .header(Header::new("Tailscale-User-Login", "alice@example.com"))
+            .header(Header::new("X-API-Key", "main"))
+            .remote(proxy)
+            .dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "Identity { name: None, owner: None, scopes: [Create, Overwrite, Delete, List, Admin], private: false }"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_password_hash() {
+        let hash = hash_password("s3cret").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("s3cret").unwrap(), "The salt is random");
+
+        let config = serde_json::from_str::<BibinConfig>(&format!(
+            r#"{{ "password_hash": "{}", "prefix": "/" }}"#,
+            hash
+        ))
+        .unwrap();
+        assert!(config.password.check().is_ok());
+        assert!(config.password.matches("s3cret").await);
+        assert!(!config.password.matches("s3cret ").await);
+        assert!(!config.password.matches("").await);
+        assert!(AuthKey("s3cret".to_owned(), None)
+            .identify(&config)
+            .await
+            .is_some());
+
+        let password = Password::Hashed {
+            password_hash: "s3cret".to_owned(),
+        };
+        assert!(password.check().is_err());
+        assert!(!password.matches("s3cret").await);
+    }
+
+    #[rocket::async_test]
+    async fn test_key_hash() {
+        let hash = hash_password("ci-secret").unwrap();
+        let config = serde_json::from_str::<BibinConfig>(&format!(
+            r#"{{ "password": "main", "prefix": "/", "keys": [
+                {{ "name": "ci", "key_hash": "{}", "scopes": ["create"] }},
+                {{ "name": "old", "key": "old-secret", "scopes": ["list"] }}
+            ] }}"#,
+            hash
+        ))
+        .unwrap();
+        assert!(config.check_secrets(false).is_ok());
+        let name = |key: &str, label: Option<&str>| {
+            let key = AuthKey(key.to_owned(), label.map(str::to_owned));
+            let config = &config;
+            async move { key.identify(config).await.map(|identity| identity.name) }
+        };
+        // A hashed key is named by the client, as the user or as a prefix
+        let ci = Some(Some("ci".to_owned()));
+        assert_eq!(name("ci:ci-secret", None).await, ci);
+        assert_eq!(name("ci-secret", Some("ci")).await, ci);
+        assert_eq!(name("ci:ci-secret", None).await, ci, "Cached");
+        assert_eq!(name("ci-secret", None).await, None);
+        assert_eq!(name("ci:wrong", None).await, None);
+        assert_eq!(name("main", Some("ci")).await, None);
+        assert_eq!(name(&hash, None).await, None);
+        assert_eq!(name("old-secret", None).await, Some(Some("old".to_owned())));
+        assert_eq!(name("main", None).await, Some(None));
+
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "main", "prefix": "/", "keys": [
+                { "name": "ci", "key_hash": "ci-secret", "scopes": ["create"] }
+            ] }"#,
+        )
+        .unwrap();
+        assert!(config.check_secrets(false).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn test_plain_password() {
+        let password = Password::Plain {
+            password: AuthKey("s3cret".to_owned(), None),
+        };
+        assert!(password.check().is_ok());
+        assert!(password.matches("s3cret").await);
+        assert!(!password.matches("s3cre").await);
+        assert!(!password.matches("s3cret!").await);
+    }
+
     #[test]
     fn test_no_header() {
         let client = Client::debug_with(routes![tests_authkey]).unwrap();
@@ -159,6 +906,37 @@ mod test {
         assert_eq!(response.status(), Status::Unauthorized);
     }
 
+    #[test]
+    fn test_auth_label() {
+        let client = Client::debug_with(routes![tests_authlabel]).unwrap();
+
+        let response = client
+            .get(uri!(tests_authlabel))
+            .header(Header::new(
+                "Authorization",
+                // my_name:my_password
+                "Basic bXlfbmFtZTpteV9wYXNzd29yZA==",
+            ))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), r#"Some("my_name")"#);
+
+        let response = client
+            .get(uri!(tests_authlabel))
+            .header(Header::new(
+                "Authorization",
+                // :my_password
+                "Basic Om15X3Bhc3N3b3Jk",
+            ))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "None");
+
+        let response = client
+            .get(uri!(tests_authlabel))
+            .header(Header::new("X-API-Key", "my_password"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "None");
+    }
+
     #[test]
     fn test_auth_from_api_key() {
         let client = Client::debug_with(routes![tests_authkey]).unwrap();
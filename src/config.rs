@@ -1,4 +1,9 @@
 use crate::auth;
+use crate::ttl::Ttl;
+use rocket::config::SecretKey;
+use rocket::data::{ByteUnit, ToByteUnit};
+use rocket::figment::Figment;
+use std::net::IpAddr;
 
 fn default_id_length() -> usize {
     4
@@ -16,9 +21,53 @@ fn default_database_file() -> String {
     "bibin.sqlite".to_owned()
 }
 
+fn default_janitor_interval() -> u64 {
+    60
+}
+
+fn default_max_paste_size() -> ByteUnit {
+    5.megabytes()
+}
+
+fn default_storage_directory() -> String {
+    "pastes".to_owned()
+}
+
+fn default_max_revisions() -> u32 {
+    10
+}
+
+/// Backend keeping the pastes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// In the SQLite database `database_file`
+    #[default]
+    Sqlite,
+    /// One file per paste in `storage_directory`
+    Filesystem,
+    /// In memory, lost on restart
+    Memory,
+}
+
 #[derive(serde::Deserialize)]
 pub struct BibinConfig {
-    pub password: auth::AuthKey,
+    /// Main password, allowed to do everything: `password_hash`, or `password` in plaintext
+    #[serde(flatten)]
+    pub password: auth::Password,
+    /// Additional named keys, each limited to some scopes
+    #[serde(default)]
+    pub keys: Vec<auth::ApiKey>,
+    /// Reverse proxies (`tailscale serve`) whose Tailscale identity headers are trusted
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Tailnet members allowed to use bibin without a key, behind a trusted proxy
+    #[serde(default)]
+    pub tailscale_users: Vec<auth::TailnetMember>,
+    /// Socket of tailscaled (usually /var/run/tailscale/tailscaled.sock), to ask who the peers
+    /// connected through the tailnet are
+    #[serde(default)]
+    pub tailscale_socket: Option<String>,
     pub prefix: String,
     #[serde(default = "default_id_length")]
     pub id_length: usize,
@@ -28,14 +77,81 @@ pub struct BibinConfig {
     pub database_connections: u32,
     #[serde(default = "default_max_entries")]
     pub max_entries: i32,
+    /// TTL applied to pastes submitted without one
+    #[serde(default)]
+    pub default_ttl: Option<Ttl>,
+    /// Upper bound of the TTL a client can ask for
+    #[serde(default)]
+    pub max_ttl: Option<Ttl>,
+    /// Seconds between two purges of the expired pastes
+    #[serde(default = "default_janitor_interval")]
+    pub janitor_interval: u64,
+    /// Maximum size of a paste, whatever the way it is uploaded
+    #[serde(default = "default_max_paste_size")]
+    pub max_paste_size: ByteUnit,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default = "default_storage_directory")]
+    pub storage_directory: String,
+    /// Answer a raw upload with the URL of an existing paste when the same content was already
+    /// posted with the same key
+    #[serde(default)]
+    pub reuse_duplicates: bool,
+    /// Previous revisions kept when a paste is overwritten, 0 to keep none
+    #[serde(default = "default_max_revisions")]
+    pub max_revisions: u32,
+    /// Key signing the share links, in the format of Rocket's `secret_key`: an invalid key fails
+    /// at startup. Random, and changing at each restart, when not given.
+    #[serde(default)]
+    pub secret_key: Option<SecretKey>,
+}
+
+impl BibinConfig {
+    /// Refuse the unusable hashes, and warn about the password and the keys in plaintext. A
+    /// missing secret_key is refused in release, where the share links must survive a restart.
+    pub fn check_secrets(&self, release: bool) -> Result<(), String> {
+        if !self.secret_key.as_ref().is_some_and(SecretKey::is_provided) {
+            if release {
+                return Err("A secret_key is required to sign the share links".to_owned());
+            }
+            warn!("[SHARE] No secret_key configured, the share links will not survive a restart");
+        }
+        self.password.check()?;
+        self.keys.iter().try_for_each(auth::ApiKey::check)
+    }
+
+    /// Configure the data limits of Rocket according to max_paste_size.
+    ///
+    /// The limits of the forms leave some room for the other fields and the url encoding: the
+    /// size of the paste itself is checked by the routes.
+    pub fn apply_limits(&self, figment: Figment) -> Figment {
+        let form_overhead = 64.kibibytes();
+        figment
+            .merge(("limits.file", self.max_paste_size))
+            .merge(("limits.data-form", self.max_paste_size + form_overhead))
+            .merge(("limits.form", self.max_paste_size * 3 + form_overhead))
+    }
+
+    /// Resolve the TTL of a new paste from the one requested by the client and the configured
+    /// default and maximum.
+    pub fn effective_ttl(&self, requested: Option<Ttl>) -> Option<Ttl> {
+        match (requested.or(self.default_ttl), self.max_ttl) {
+            (Some(ttl), Some(max_ttl)) => Some(ttl.min(max_ttl)),
+            (None, max_ttl) => max_ttl,
+            (ttl, None) => ttl,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
         default_database_connections, default_database_file, default_id_length,
-        default_max_entries, BibinConfig,
+        default_janitor_interval, default_max_entries, default_max_paste_size,
+        default_max_revisions, default_storage_directory, BibinConfig, Storage,
     };
+    use crate::ttl::Ttl;
+    use std::time::Duration;
 
     #[test]
     fn check_default_values() {
@@ -49,6 +165,86 @@ mod tests {
         );
         assert_eq!(default_values.max_entries, default_max_entries());
         assert_eq!(default_values.database_file, default_database_file());
+        assert_eq!(default_values.default_ttl, None);
+        assert_eq!(default_values.max_ttl, None);
+        assert_eq!(default_values.janitor_interval, default_janitor_interval());
+        assert_eq!(default_values.max_paste_size, default_max_paste_size());
+        assert_eq!(default_values.storage, Storage::Sqlite);
+        assert_eq!(
+            default_values.storage_directory,
+            default_storage_directory()
+        );
+        assert_eq!(default_values.max_revisions, default_max_revisions());
+    }
+
+    #[test]
+    fn check_limits() {
+        use rocket::data::{Limits, ToByteUnit};
+
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/", "max_paste_size": "1 MiB" }"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_paste_size, 1.mebibytes());
+
+        let figment = config.apply_limits(rocket::Config::figment());
+        let limits = figment.extract_inner::<Limits>("limits").unwrap();
+        assert_eq!(limits.get("file"), Some(1.mebibytes()));
+        assert!(limits.get("data-form").unwrap() > 1.mebibytes());
+        assert!(limits.get("form").unwrap() > 3.mebibytes());
+        // Other limits are not modified
+        assert_eq!(limits.get("json"), Limits::default().get("json"));
+    }
+
+    #[test]
+    fn check_secret_key() {
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/",
+            "secret_key": "jhqTG1chy13SzpyT1whkK+oIpfmN+RQRzA60DxkTG64=" }"#,
+        )
+        .unwrap();
+        assert!(config.check_secrets(true).is_ok());
+        assert!(config.secret_key.unwrap().is_provided());
+
+        // Only a debug build starts without one
+        let config =
+            serde_json::from_str::<BibinConfig>(r#"{ "password": "A", "prefix": "/" }"#).unwrap();
+        assert!(config.check_secrets(false).is_ok());
+        assert!(config.check_secrets(true).is_err());
+
+        // Rejected instead of being used as-is
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/", "secret_key": "too short" }"#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn check_effective_ttl() {
+        let hour = Ttl(Duration::from_secs(3600));
+        let day = Ttl(Duration::from_secs(86400));
+
+        let config =
+            serde_json::from_str::<BibinConfig>(r#"{ "password": "A", "prefix": "/" }"#).unwrap();
+        assert_eq!(config.effective_ttl(None), None);
+        assert_eq!(config.effective_ttl(Some(day)), Some(day));
+
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/", "default_ttl": "1h", "max_ttl": 86400 }"#,
+        )
+        .unwrap();
+        assert_eq!(config.effective_ttl(None), Some(hour));
+        assert_eq!(config.effective_ttl(Some(day)), Some(day));
+        assert_eq!(
+            config.effective_ttl(Some(Ttl(Duration::from_secs(100 * 86400)))),
+            Some(day)
+        );
+
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/", "max_ttl": "1h" }"#,
+        )
+        .unwrap();
+        assert_eq!(config.effective_ttl(None), Some(hour));
     }
 
     #[test]
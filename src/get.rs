@@ -1,17 +1,27 @@
-use crate::auth::AuthKey;
+use crate::api::PasteObject;
+use crate::auth::{Credentials, Scope, TailscaleLogin};
 use crate::config::BibinConfig;
 use crate::highlight::Highlighter;
-use crate::io::{get_all_paste, get_paste, ReadPool};
+use crate::io::{Cursor, IOError, ListFilter, Paste, PasteSummary, SearchResult, ShareMode};
+use crate::mimetype::{self, RawContent};
+use crate::readpassword::{self, PasswordRequired, ReadPassword};
+use crate::share::SharedLink;
+use crate::store::PasteStore;
+use crate::wantsjson::WantsJson;
 use crate::RedirectOrContent;
 use crate::{isplaintextrequest::IsPlaintextRequest, HtmlOrPlain};
 use base64::{engine::general_purpose, Engine as _};
 use qrcode_generator::QrCodeEcc;
+use rocket::form::Form;
 use rocket::http::{RawStr, Status};
 use rocket::response::content::RawJson;
 use rocket::response::Redirect;
+use rocket::serde::Serialize;
 use rocket::State;
+use similar::{ChangeTag, TextDiff};
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use askama::{Html as AskamaHtml, MarkupDisplay, Template};
 
@@ -23,36 +33,137 @@ use askama::{Html as AskamaHtml, MarkupDisplay, Template};
 #[response(content_type = "image/png")]
 pub struct PngResponder(Vec<u8>);
 
+/// A response body in JSON. Serializing the objects of bibin is not expected to fail, but a
+/// handler must not panic if it does.
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, Status> {
+    serde_json::to_string(value).map_err(|e| {
+        warn!("[JSON] Error when serializing a response: {}", e);
+        Status::InternalServerError
+    })
+}
+
 #[derive(Template)]
 #[template(path = "paste.html")]
 struct ShowPaste<'a> {
-    content: MarkupDisplay<AskamaHtml, Cow<'a, String>>,
+    content: MarkupDisplay<AskamaHtml, Cow<'a, str>>,
+}
+
+#[derive(Template)]
+#[template(path = "binary.html")]
+struct ShowBinary<'a> {
+    mime_type: &'a str,
+    filename: &'a str,
+    size: usize,
+    is_image: bool,
+    /// Relative to the page of the paste
+    raw_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "diff.html")]
+struct ShowDiff {
+    lines: Vec<DiffLine>,
+}
+
+struct DiffLine {
+    /// Change of the line: "equal", "delete" or "insert"
+    tag: &'static str,
+    sign: char,
+    /// Line numbers in the old and the new paste, starting at 1
+    old: Option<usize>,
+    new: Option<usize>,
+    /// The line as HTML, highlighted or escaped
+    content: String,
+}
+
+#[derive(Template)]
+#[template(path = "password.html")]
+struct AskPassword<'a> {
+    /// Last segment of the URL of the paste, under which the form is posted
+    key: &'a str,
+    wrong: bool,
+}
+
+/// Password typed in the prompt of a protected paste
+#[derive(FromForm)]
+pub struct UnlockForm {
+    password: String,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct ShowSearch<'a> {
+    query: &'a str,
+    results: Vec<SearchResult>,
+}
+
+/// Maximum number of pastes returned by a search
+const SEARCH_LIMIT: u32 = 50;
+
+/// Number of pastes per page of the listings, by default and at most
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// Query string of the paginated listings
+#[derive(FromForm)]
+pub struct ListQuery<'r> {
+    limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    cursor: Option<&'r str>,
+    /// Unix timestamp
+    since: Option<i64>,
+    lang: Option<&'r str>,
+}
+
+#[derive(Serialize)]
+struct PastesPage {
+    pastes: Vec<PasteSummary>,
+    /// Cursor of the next page, None on the last page
+    next_cursor: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "pastes.html")]
+struct ShowPastes {
+    pastes: Vec<PasteSummary>,
+    /// Link to the next page, with the same filters
+    next_page: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
-pub struct Index;
+pub struct Index<'a> {
+    /// Tailscale login of the user, who does not need the password
+    login: Option<&'a str>,
+}
 
 #[derive(Template)]
 #[template(path = "curl_help.txt")]
 pub struct CurlIndex {
     root_url: String,
+    max_paste_size: String,
 }
 
 #[get("/")]
 pub fn index(
     config: &State<BibinConfig>,
     plaintext: IsPlaintextRequest,
+    tailnet: Option<TailscaleLogin>,
 ) -> Result<HtmlOrPlain, Status> {
     if plaintext.0 {
         CurlIndex {
             root_url: config.prefix.clone(),
+            max_paste_size: config.max_paste_size.to_string(),
         }
         .render()
         .map(HtmlOrPlain::Plain)
         .map_err(|_| Status::InternalServerError)
     } else {
-        Index
+        let login = tailnet
+            .as_ref()
+            .filter(|login| login.identify(config).is_some())
+            .map(TailscaleLogin::login);
+        Index { login }
             .render()
             .map(HtmlOrPlain::Html)
             .map_err(|_| Status::InternalServerError)
@@ -61,18 +172,16 @@ pub fn index(
 
 #[get("/all_entries")]
 pub async fn all_entries(
-    pool: &State<ReadPool>,
-    password: AuthKey,
+    store: &State<Arc<dyn PasteStore>>,
+    password: Credentials,
     config: &State<BibinConfig>,
 ) -> Result<RawJson<String>, Status> {
-    if !password.is_valid(&config.password) {
-        return Err(Status::Unauthorized);
-    }
+    password.authorize(config, Scope::List).await?;
 
-    let entries = match get_all_paste(pool).await {
+    let entries = match store.list().await {
         Ok(entries) => entries,
         Err(e) => {
-            warn!("[ALL_ENTRIES] Error in get_all_paste: {}", e);
+            warn!("[ALL_ENTRIES] Error when listing the pastes: {}", e);
             return Err(Status::InternalServerError);
         }
     };
@@ -81,23 +190,156 @@ pub async fn all_entries(
         .iter()
         .map(|(k, v)| (k, v))
         .collect::<HashMap<&String, &String>>();
-    let json = serde_json::to_string(&result).unwrap();
-    Ok(RawJson(json))
+    Ok(RawJson(to_json(&result)?))
+}
+
+#[get("/search?<q>")]
+pub async fn search(
+    q: Option<&str>,
+    store: &State<Arc<dyn PasteStore>>,
+    password: Credentials,
+    config: &State<BibinConfig>,
+    plaintext: IsPlaintextRequest,
+) -> Result<HtmlOrPlain, Status> {
+    password.authorize(config, Scope::List).await?;
+
+    let query = q.unwrap_or_default();
+    let results = match store.search(query, SEARCH_LIMIT).await {
+        Ok(results) => results,
+        Err(e) => {
+            warn!("[SEARCH] Error when searching the pastes: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    if *plaintext {
+        Ok(HtmlOrPlain::Json(to_json(&results)?))
+    } else {
+        ShowSearch { query, results }
+            .render()
+            .map(HtmlOrPlain::Html)
+            .map_err(|_| Status::InternalServerError)
+    }
+}
+
+/// Read the page of the listings selected by the query, and the cursor of the next one
+async fn list_page(
+    store: &dyn PasteStore,
+    query: &ListQuery<'_>,
+) -> Result<(Vec<PasteSummary>, Option<Cursor>), Status> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = match query.cursor.map(str::parse).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            warn!("[LIST_PASTES] {}", e);
+            return Err(Status::BadRequest);
+        }
+    };
+    let filter = ListFilter {
+        // One more to know whether there is a next page
+        limit: limit + 1,
+        cursor,
+        since: query.since,
+        language: query.lang.map(str::to_owned),
+    };
+
+    let mut pastes = match store.list_page(&filter).await {
+        Ok(pastes) => pastes,
+        Err(e) => {
+            warn!("[LIST_PASTES] Error when listing the pastes: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    if pastes.len() <= limit as usize {
+        return Ok((pastes, None));
+    }
+    pastes.truncate(limit as usize);
+    let next = pastes.last().map(Cursor::of);
+    Ok((pastes, next))
+}
+
+/// Paginated listing of the pastes, without their content. Unlike `all_entries`, its size does
+/// not grow with the database.
+#[get("/api/pastes?<query..>")]
+pub async fn list_pastes(
+    query: ListQuery<'_>,
+    store: &State<Arc<dyn PasteStore>>,
+    password: Credentials,
+    config: &State<BibinConfig>,
+) -> Result<RawJson<String>, Status> {
+    password.authorize(config, Scope::List).await?;
+
+    let (pastes, next) = list_page(store.as_ref(), &query).await?;
+    let page = PastesPage {
+        pastes,
+        next_cursor: next.map(|cursor| cursor.to_string()),
+    };
+    Ok(RawJson(to_json(&page)?))
+}
+
+/// The listing of `list_pastes` as a page for the administrators
+#[get("/pastes?<query..>")]
+pub async fn pastes_page(
+    query: ListQuery<'_>,
+    store: &State<Arc<dyn PasteStore>>,
+    password: Credentials,
+    config: &State<BibinConfig>,
+) -> Result<HtmlOrPlain, Status> {
+    password.authorize(config, Scope::List).await?;
+
+    let (pastes, next) = list_page(store.as_ref(), &query).await?;
+    let next_page = next.map(|cursor| {
+        let mut url = format!(
+            "pastes?cursor={}",
+            RawStr::new(&cursor.to_string()).percent_encode()
+        );
+        if let Some(limit) = query.limit {
+            url.push_str(&format!("&limit={}", limit));
+        }
+        if let Some(since) = query.since {
+            url.push_str(&format!("&since={}", since));
+        }
+        if let Some(lang) = query.lang {
+            url.push_str(&format!("&lang={}", RawStr::new(lang).percent_encode()));
+        }
+        url
+    });
+    ShowPastes { pastes, next_page }
+        .render()
+        .map(HtmlOrPlain::Html)
+        .map_err(|_| Status::InternalServerError)
 }
 
 #[get("/<name>/qr")]
 pub async fn get_qr(
     name: String,
+    password: ReadPassword,
+    link: SharedLink,
     config: &State<BibinConfig>,
-    pool: &State<ReadPool>,
+    store: &State<Arc<dyn PasteStore>>,
 ) -> Result<PngResponder, Status> {
     let mut splitter = name.splitn(2, '.');
     let key = splitter.next().ok_or(Status::NotFound)?;
-    match get_paste(pool, key).await {
+    if !may_open(
+        store.as_ref(),
+        key,
+        &link,
+        ShareMode::Qr,
+        password.0.as_deref(),
+    )
+    .await?
+    {
+        return Err(Status::Unauthorized);
+    }
+    // Only check that the paste exists, reading it would count as a view
+    match store.meta(key).await {
         // TODO: not found or Internal error
         Ok(None) => return Err(Status::NotFound),
         Err(e) => {
-            warn!("[GET_QR] Error in get_paste: {}", e);
+            warn!("[GET_QR] Error when reading the metadata: {}", e);
             return Err(Status::InternalServerError);
         }
         Ok(Some(_)) => (),
@@ -108,51 +350,435 @@ pub async fn get_qr(
         QrCodeEcc::Medium,
         1024,
     )
-    .unwrap();
+    .map_err(|e| {
+        warn!("[GET_QR] qrcode_generator: {}", e);
+        Status::InternalServerError
+    })?;
     Ok(PngResponder(result))
 }
 
+#[get("/<key>/meta")]
+pub async fn get_meta(
+    key: &str,
+    password: ReadPassword,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<RawJson<String>, Status> {
+    let mut splitter = key.splitn(2, '.');
+    let key = splitter.next().ok_or(Status::NotFound)?;
+    let meta = match store.meta(key).await {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            warn!("[GET_META] Error when reading the metadata: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let hash = meta.read_password_hash.as_deref();
+    if !readpassword::opens(key, hash, password.0.as_deref()).await? {
+        return Err(Status::Unauthorized);
+    }
+    to_json(&meta.without_secrets()).map(RawJson)
+}
+
+#[get("/<key>/history")]
+pub async fn get_history(
+    key: &str,
+    password: ReadPassword,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<RawJson<String>, Status> {
+    let mut splitter = key.splitn(2, '.');
+    let key = splitter.next().ok_or(Status::NotFound)?;
+    if !may_read(store.as_ref(), key, password.0.as_deref()).await? {
+        return Err(Status::Unauthorized);
+    }
+    match store.history(key).await {
+        Ok(Some(history)) => to_json(&history).map(RawJson),
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            warn!("[GET_HISTORY] Error when reading the history: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Whether the reader has the password of the paste designated by the key of the URL, when it
+/// has one. Checked before reading the paste: a refused request must not count as a view.
+async fn may_read(
+    store: &dyn PasteStore,
+    key: &str,
+    password: Option<&str>,
+) -> Result<bool, Status> {
+    let id = paste_id(key);
+    match store.meta(id).await {
+        Ok(Some(meta)) => {
+            readpassword::opens(id, meta.read_password_hash.as_deref(), password).await
+        }
+        // Answered by a 404 when reading it
+        Ok(None) => Ok(true),
+        Err(e) => {
+            warn!("[READ_PASSWORD] Error when reading the metadata: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Id of the paste designated by the key of the URL, without the revision
+fn paste_id(key: &str) -> &str {
+    key.split_once('@').map_or(key, |(id, _)| id)
+}
+
+/// Whether the reader may open the paste: with a share link when one is given, which is then
+/// the only way in, or with the password of the paste
+async fn may_open(
+    store: &dyn PasteStore,
+    key: &str,
+    link: &SharedLink,
+    mode: ShareMode,
+    password: Option<&str>,
+) -> Result<bool, Status> {
+    if link.0.is_none() {
+        return may_read(store, key, password).await;
+    }
+    if !link.opens(store, key, mode).await? {
+        warn!("[SHARE] The share link does not open {} anymore", key);
+        return Err(Status::Forbidden);
+    }
+    Ok(true)
+}
+
+/// Answer to a reader without the password of the paste: the prompt for the browsers
+fn password_required(name: &str, browser: bool, wrong: bool) -> RedirectOrContent {
+    let html = if browser {
+        let prompt = AskPassword { key: name, wrong };
+        match prompt.render() {
+            Ok(html) => Some(html),
+            Err(e) => {
+                error!("[READ_PASSWORD] Error when rendering the template: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    RedirectOrContent::PasswordRequired(PasswordRequired { html })
+}
+
+/// Whether reading the paste again would need its password or use one of its views, once it
+/// has been read
+async fn is_guarded(store: &dyn PasteStore, key: &str) -> Result<bool, Status> {
+    match store.meta(paste_id(key)).await {
+        Ok(Some(meta)) => Ok(meta.is_guarded()),
+        // Deleted by its last view
+        Ok(None) => Ok(true),
+        Err(e) => {
+            warn!("[SHOW_PASTE] Error when reading the metadata: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Read the paste designated by the key of the URL: `<id>` for its current content,
+/// `<id>@<revision>` for one of its revisions
+async fn read_paste(store: &dyn PasteStore, key: &str) -> Result<Option<Paste>, IOError> {
+    match key.split_once('@') {
+        None => store.get(key).await,
+        Some((id, revision)) => match revision.parse() {
+            // Reading a revision does not count a view: those of view-limited pastes are not served
+            Ok(revision) => match store.meta(id).await? {
+                Some(meta) if meta.views_left.is_none() => store.get_revision(id, revision).await,
+                _ => Ok(None),
+            },
+            Err(_) => Ok(None),
+        },
+    }
+}
+
+/// JSON object of a paste, with its content. Counts as a view, like the page of the paste.
+async fn read_paste_json(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    key: &str,
+) -> Result<RedirectOrContent, Status> {
+    let (id, revision) = match key.split_once('@') {
+        None => (key, None),
+        Some((id, revision)) => (id, Some(revision.parse().map_err(|_| Status::NotFound)?)),
+    };
+    // Read before the content: the paste may be deleted by this very view
+    let mut meta = match store.meta(id).await {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            warn!("[SHOW_PASTE] Error when reading the metadata: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let paste = match read_paste(store, key).await {
+        Ok(Some(paste)) => paste,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            warn!("[SHOW_PASTE] Error when reading the paste: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    match revision {
+        Some(revision) if revision != meta.revision => meta.revision = revision,
+        _ => meta.views_left = meta.views_left.map(|views| views.saturating_sub(1)),
+    }
+    meta.language = paste.language;
+    meta.mime_type = paste.mime_type;
+    meta.filename = paste.filename;
+    let object = PasteObject::new(config, meta).with_content(paste.data);
+    Ok(RedirectOrContent::Json(object.to_json()?))
+}
+
 #[get("/<key>/raw")]
-pub async fn get_item_raw(key: &str, pool: &State<ReadPool>) -> Result<HtmlOrPlain, Status> {
+pub async fn get_item_raw(
+    key: &str,
+    password: ReadPassword,
+    link: SharedLink,
+    store: &State<Arc<dyn PasteStore>>,
+) -> Result<RedirectOrContent, Status> {
     let mut splitter = key.splitn(2, '.');
     let key = splitter.next().ok_or(Status::NotFound)?;
-    let content = match get_paste(pool, key).await {
+    if !may_open(
+        store.as_ref(),
+        key,
+        &link,
+        ShareMode::Raw,
+        password.0.as_deref(),
+    )
+    .await?
+    {
+        return Ok(password_required(key, false, password.0.is_some()));
+    }
+    let paste = match read_paste(store.as_ref(), key).await {
         // TODO: not found or Internal error
         Ok(None) => return Err(Status::NotFound),
         Err(e) => {
-            warn!("[GET_RAW] Error in get_paste: {}", e);
+            warn!("[GET_RAW] Error when reading the paste: {}", e);
             return Err(Status::InternalServerError);
         }
-        Ok(Some(content)) => content,
+        Ok(Some(paste)) => paste,
+    };
+
+    Ok(RedirectOrContent::Binary(RawContent::new(paste)))
+}
+
+/// Text and language of a paste to compare. A view-limited paste is refused: a diff would
+/// count as one of its views.
+async fn read_text(store: &dyn PasteStore, key: &str) -> Result<(String, Option<String>), Status> {
+    match store.meta(paste_id(key)).await {
+        Ok(Some(meta)) if meta.views_left.is_some() => return Err(Status::Forbidden),
+        Ok(_) => (),
+        Err(e) => {
+            warn!("[GET_DIFF] Error when reading the metadata: {}", e);
+            return Err(Status::InternalServerError);
+        }
+    }
+    match read_paste(store, key).await {
+        Ok(Some(paste)) => {
+            let language = paste.language;
+            String::from_utf8(paste.data)
+                .map(|text| (text, language))
+                .map_err(|_| Status::UnsupportedMediaType)
+        }
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            warn!("[GET_DIFF] Error when reading the paste: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[get("/<old>/diff/<new>")]
+pub async fn get_diff(
+    old: &str,
+    new: &str,
+    plaintext: IsPlaintextRequest,
+    password: ReadPassword,
+    store: &State<Arc<dyn PasteStore>>,
+    highlighter: &State<Highlighter>,
+) -> Result<HtmlOrPlain, Status> {
+    let mut splitter = old.splitn(2, '.');
+    let old_key = splitter.next().ok_or(Status::NotFound)?;
+    let old_ext = splitter.next();
+    let mut splitter = new.splitn(2, '.');
+    let new_key = splitter.next().ok_or(Status::NotFound)?;
+    let new_ext = splitter.next();
+    // Two revisions of the same paste need its password once
+    let keys = match paste_id(old_key) == paste_id(new_key) {
+        true => &[old_key][..],
+        false => &[old_key, new_key][..],
     };
+    for key in keys {
+        if !may_read(store.as_ref(), key, password.0.as_deref()).await? {
+            return Err(Status::Unauthorized);
+        }
+    }
+
+    let (old_text, old_language) = read_text(store.as_ref(), old_key).await?;
+    let (new_text, new_language) = read_text(store.as_ref(), new_key).await?;
+    let diff = TextDiff::from_lines(&old_text, &new_text);
+
+    if *plaintext {
+        return Ok(HtmlOrPlain::Plain(
+            diff.unified_diff().header(old_key, new_key).to_string(),
+        ));
+    }
 
-    Ok(HtmlOrPlain::Plain(content))
+    // An extension in the URL wins over the languages declared by the authors
+    let extension = new_ext
+        .or(old_ext)
+        .or(new_language.as_deref())
+        .or(old_language.as_deref());
+    let highlight = |text: &str| match extension {
+        Some(extension) => highlighter
+            .highlight_lines(text, extension)
+            .map_err(|error| {
+                error!("Error highlighting from extension {} {}", extension, error);
+                Status::InternalServerError
+            }),
+        None => Ok(text
+            .lines()
+            .map(|line| RawStr::new(line).html_escape().into_owned())
+            .collect()),
+    };
+    let old_lines = highlight(&old_text)?;
+    let new_lines = highlight(&new_text)?;
+
+    let lines = diff
+        .iter_all_changes()
+        .map(|change| {
+            let (tag, sign, content) = match change.tag() {
+                ChangeTag::Equal => (
+                    "equal",
+                    ' ',
+                    change.new_index().and_then(|i| new_lines.get(i)),
+                ),
+                ChangeTag::Delete => (
+                    "delete",
+                    '-',
+                    change.old_index().and_then(|i| old_lines.get(i)),
+                ),
+                ChangeTag::Insert => (
+                    "insert",
+                    '+',
+                    change.new_index().and_then(|i| new_lines.get(i)),
+                ),
+            };
+            DiffLine {
+                tag,
+                sign,
+                old: change.old_index().map(|i| i + 1),
+                new: change.new_index().map(|i| i + 1),
+                content: content.cloned().unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    ShowDiff { lines }
+        .render()
+        .map(HtmlOrPlain::Html)
+        .map_err(|_| Status::InternalServerError)
 }
 
 #[get("/<key>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn get_item(
     key: &str,
     plaintext: IsPlaintextRequest,
-    pool: &State<ReadPool>,
+    json: WantsJson,
+    password: ReadPassword,
+    link: SharedLink,
+    config: &State<BibinConfig>,
+    store: &State<Arc<dyn PasteStore>>,
     highlighter: &State<Highlighter>,
 ) -> Result<RedirectOrContent, Status> {
-    let mut splitter = key.splitn(2, '.');
+    let password = password.0.as_deref();
+    show_item(
+        key,
+        password,
+        &link,
+        plaintext,
+        json,
+        config,
+        store,
+        highlighter,
+    )
+    .await
+}
+
+/// The prompt of a protected paste is posted here, to be answered with the paste
+#[post("/<key>/unlock", data = "<form>")]
+pub async fn unlock(
+    key: &str,
+    form: Form<UnlockForm>,
+    plaintext: IsPlaintextRequest,
+    json: WantsJson,
+    config: &State<BibinConfig>,
+    store: &State<Arc<dyn PasteStore>>,
+    highlighter: &State<Highlighter>,
+) -> Result<RedirectOrContent, Status> {
+    let password = Some(form.password.as_str());
+    let link = SharedLink(None);
+    show_item(
+        key,
+        password,
+        &link,
+        plaintext,
+        json,
+        config,
+        store,
+        highlighter,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn show_item(
+    name: &str,
+    password: Option<&str>,
+    link: &SharedLink,
+    plaintext: IsPlaintextRequest,
+    json: WantsJson,
+    config: &BibinConfig,
+    store: &Arc<dyn PasteStore>,
+    highlighter: &Highlighter,
+) -> Result<RedirectOrContent, Status> {
+    let mut splitter = name.splitn(2, '.');
     let key = splitter.next().ok_or(Status::NotFound)?;
     let ext = splitter.next();
 
-    let entry = match get_paste(pool, key).await {
-        Ok(Some(data)) => data,
+    let mode = match ext {
+        Some("qr") => ShareMode::Qr,
+        _ => ShareMode::Page,
+    };
+    if !may_open(store.as_ref(), key, link, mode, password).await? {
+        let browser = !*plaintext && !*json;
+        return Ok(password_required(name, browser, password.is_some()));
+    }
+
+    if *json && ext.is_none() {
+        return read_paste_json(store.as_ref(), config, key).await;
+    }
+
+    let paste = match read_paste(store.as_ref(), key).await {
+        Ok(Some(paste)) => paste,
         Ok(None) => return Err(Status::NotFound),
         Err(e) => {
-            warn!("[SHOW_PASTE] get_paste error: {}", e);
+            warn!("[SHOW_PASTE] Error when reading the paste: {}", e);
             return Err(Status::InternalServerError);
         }
     };
 
     if let Some(extension) = ext {
         match extension {
-            "url" => return Ok(RedirectOrContent::Redirect(Redirect::to(entry))),
-            "qr" => match qrcode_generator::to_png_to_vec(entry, QrCodeEcc::Medium, 1024) {
+            "url" => {
+                let url = paste.text().ok_or(Status::UnsupportedMediaType)?;
+                return Ok(RedirectOrContent::Redirect(Redirect::to(url.to_owned())));
+            }
+            "qr" => match qrcode_generator::to_png_to_vec(&paste.data, QrCodeEcc::Medium, 1024) {
                 Ok(code) => return Ok(RedirectOrContent::Png(code)),
                 Err(e) => {
                     warn!("[SHOW_PASTE] qrcode_generator: {}", e);
@@ -161,17 +787,39 @@ pub async fn get_item(
             },
             "b64" => {
                 return Ok(RedirectOrContent::Plain(
-                    general_purpose::STANDARD.encode(entry),
+                    general_purpose::STANDARD.encode(&paste.data),
                 ))
             }
             _ => (),
         }
     }
 
+    if paste.text().is_none() {
+        // The preview loads the paste again from the raw route, which would ask for its password
+        // or use another of its views: those pastes are served directly instead
+        if *plaintext || link.0.is_some() || is_guarded(store.as_ref(), key).await? {
+            return Ok(RedirectOrContent::Binary(RawContent::new(paste)));
+        }
+        let mime_type = mimetype::content_type(&paste).to_string();
+        let template = ShowBinary {
+            mime_type: &mime_type,
+            filename: paste.filename.as_deref().unwrap_or(key),
+            size: paste.data.len(),
+            is_image: mimetype::is_image(&mime_type),
+            raw_url: format!("{}/raw", key),
+        };
+        return match template.render() {
+            Ok(html) => Ok(RedirectOrContent::Html(html)),
+            Err(_) => Err(Status::InternalServerError),
+        };
+    }
+    let entry = String::from_utf8(paste.data).map_err(|_| Status::InternalServerError)?;
+
     if *plaintext {
         Ok(RedirectOrContent::Plain(entry))
     } else {
-        let code_highlighted = match ext {
+        // Without extension in the URL, use the language declared by the author
+        let code_highlighted = match ext.or(paste.language.as_deref()) {
             Some(extension) => match highlighter.highlight(&entry, extension) {
                 Ok(html) => html,
                 Err(error) => {
@@ -188,7 +836,7 @@ pub async fn get_item(
             code_highlighted.replace('\n', "</code><code>")
         );
 
-        let content = MarkupDisplay::new_safe(Cow::Borrowed(&html), AskamaHtml);
+        let content = MarkupDisplay::new_safe(Cow::Borrowed(html.as_str()), AskamaHtml);
 
         let template = ShowPaste { content };
         match template.render() {
@@ -202,55 +850,66 @@ pub async fn get_item(
 mod test {
     use std::collections::HashMap;
     use std::iter::FromIterator;
+    use std::sync::Arc;
 
     use crate::config::BibinConfig;
     use crate::highlight::Highlighter;
-    use crate::io;
-    use crate::io::{ReadPool, WritePool};
+    use crate::io::PasteOptions;
     use crate::rocket;
-    use rocket::http::{Header, Status};
+    use crate::store::{self, PasteStore};
+    use rocket::http::{ContentType, Header, Status};
     use rocket::local::asynchronous::Client;
     use rocket::tokio;
     use tempfile::NamedTempFile;
 
-    use super::get_qr;
     use super::index;
     use super::{all_entries, rocket_uri_macro_all_entries};
+    use super::{get_diff, rocket_uri_macro_get_diff};
+    use super::{get_history, rocket_uri_macro_get_history};
     use super::{get_item, rocket_uri_macro_get_item};
     use super::{get_item_raw, rocket_uri_macro_get_item_raw};
+    use super::{get_meta, rocket_uri_macro_get_meta};
+    use super::{get_qr, rocket_uri_macro_get_qr};
+    use super::{list_pastes, pastes_page};
+    use super::{rocket_uri_macro_search, search};
+    use super::{rocket_uri_macro_unlock, unlock};
 
     const ENTRY_CONTENT: &str = "This is a test";
     const PASSWORD: &str = "password123";
+    const BROWSER_AGENT: &str =
+        "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/114.0";
 
     async fn create_test_client() -> (NamedTempFile, Client) {
         let temp = NamedTempFile::new().unwrap();
-        let file_name = temp.path().to_str().unwrap();
-        let write_pool = WritePool::new(file_name)
-            .await
-            .expect("Error when creating the writing pool");
-
-        write_pool
-            .init()
+        let config = serde_json::from_str::<BibinConfig>(&format!(
+            r#"{{ "password": "password123", "prefix": "/", "id_length": 5, "database_file": {:?} }}"#,
+            temp.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let store = store::open(&config)
             .await
-            .expect("Error during initialization");
-
-        let read_pool = ReadPool::new(file_name, 10)
-            .await
-            .expect("Error when creating the reading pool");
+            .expect("Error when opening the store");
 
         let rocket = rocket::Rocket::build()
-            .manage(read_pool)
-            .manage(write_pool)
+            .manage(store)
             .manage(Highlighter::new())
-            .manage(
-                serde_json::from_str::<BibinConfig>(
-                    r#"{ "password": "password123", "prefix": "/" }"#,
-                )
-                .unwrap(),
-            )
+            .manage(config)
             .mount(
                 "/",
-                routes![index, all_entries, get_qr, get_item, get_item_raw],
+                routes![
+                    index,
+                    all_entries,
+                    get_qr,
+                    get_item,
+                    unlock,
+                    get_item_raw,
+                    get_meta,
+                    get_history,
+                    get_diff,
+                    search,
+                    list_pastes,
+                    pastes_page
+                ],
             );
         // the NamedTempFile will be deleted when `temp` goes out of scope. We need
         // to hand it over to the tests so that it stays on the fs until the end of the test
@@ -260,7 +919,7 @@ mod test {
     #[rocket::async_test]
     async fn test_simple_case() {
         let (_temp, client) = create_test_client().await;
-        let write_pool = client.rocket().state::<WritePool>().unwrap();
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
 
         let response = client.get(uri!(get_item_raw("bob"))).dispatch().await;
         assert_eq!(response.status(), Status::NotFound);
@@ -269,7 +928,8 @@ mod test {
         assert_eq!(response.status(), Status::NotFound);
 
         const ENTRY_CONTENT: &str = "This is a test";
-        let key = io::store_paste(&write_pool, 5, 1000, ENTRY_CONTENT.to_string())
+        let key = store
+            .store(ENTRY_CONTENT.as_bytes().to_vec(), &PasteOptions::default())
             .await
             .unwrap();
         assert_ne!(key, "");
@@ -285,10 +945,500 @@ mod test {
         assert_eq!(response.status(), Status::Ok);
     }
 
+    #[rocket::async_test]
+    async fn test_read_password() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let options = PasteOptions {
+            max_views: Some(2),
+            read_password_hash: Some(crate::auth::hash_password("secret").unwrap()),
+            ..Default::default()
+        };
+        let key = store
+            .store(b"https://example.com".to_vec(), &options)
+            .await
+            .unwrap();
+        let secret = Header::new("X-Read-Password", "secret");
+
+        // Refused requests do not count as views
+        for uri in [
+            uri!(get_item(&key)),
+            uri!(get_item(format!("{}.url", key))),
+            uri!(get_item(format!("{}.b64", key))),
+            uri!(get_item_raw(&key)),
+            uri!(get_qr(&key)),
+            uri!(get_meta(&key)),
+            uri!(get_history(&key)),
+        ] {
+            let response = client.get(uri.clone()).dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized, "{}", uri);
+        }
+        // A few wrong passwords, not enough to be throttled
+        for uri in [uri!(get_item(&key)), uri!(get_meta(&key))] {
+            let response = client
+                .get(uri.clone())
+                .header(Header::new("X-Read-Password", "wrong"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Unauthorized, "{}", uri);
+        }
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert!(response.headers().contains("WWW-Authenticate"));
+        let meta = client
+            .get(uri!(get_meta(&key)))
+            .header(secret.clone())
+            .dispatch()
+            .await;
+        let meta = meta.into_string().await.unwrap();
+        assert!(meta.contains(r#""views_left":2"#), "{}", meta);
+        assert!(!meta.contains("read_password"), "{}", meta);
+
+        // Browsers are prompted for the password, then get the paste
+        let response = client
+            .get(uri!(get_item(&key)))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let prompt = response.into_string().await.unwrap();
+        assert!(prompt.contains(&format!(r#"action="{}/unlock""#, key)));
+        let response = client
+            .post(uri!(unlock(&key)))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .header(ContentType::Form)
+            .body("password=secret")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains("example.com"));
+
+        let basic = Header::new("Authorization", "Basic OnNlY3JldA==");
+        let response = client
+            .get(uri!(get_item_raw(&key)))
+            .header(basic)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "https://example.com");
+        let response = client
+            .get(uri!(get_item(&key)))
+            .header(secret)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_burn_after_reading() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let options = PasteOptions {
+            max_views: Some(2),
+            ..Default::default()
+        };
+        let key = store
+            .store(ENTRY_CONTENT.as_bytes().to_vec(), &options)
+            .await
+            .unwrap();
+
+        // The QR code of the URL does not need the content, it is not a view
+        let response = client.get(uri!(get_qr(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get(uri!(get_item(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), ENTRY_CONTENT);
+
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.get(uri!(get_item(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_meta() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let response = client.get(uri!(get_meta("bob"))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let options = PasteOptions {
+            language: Some("rs".to_owned()),
+            creator: Some("bot".to_owned()),
+            ..Default::default()
+        };
+        let key = store
+            .store(ENTRY_CONTENT.as_bytes().to_vec(), &options)
+            .await
+            .unwrap();
+
+        let response = client
+            .get(uri!(get_meta(format!("{}.py", key))))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let meta: serde_json::Value =
+            serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(meta["id"], key);
+        assert_eq!(meta["size"], ENTRY_CONTENT.len());
+        assert_eq!(meta["language"], "rs");
+        assert_eq!(meta["creator"], "bot");
+        assert!(meta["created_at"].is_i64());
+        assert!(meta["expires_at"].is_null());
+    }
+
+    #[rocket::async_test]
+    async fn test_revisions() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let response = client.get(uri!(get_history("notes"))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        for content in ["first", "second"] {
+            store
+                .store_with_id("notes".to_owned(), content.into(), &PasteOptions::default())
+                .await
+                .unwrap();
+        }
+
+        let response = client.get(uri!(get_history("notes"))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let history: serde_json::Value =
+            serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(history[0]["revision"], 1);
+        assert_eq!(history[0]["size"], 5);
+        assert_eq!(history[1]["revision"], 2);
+
+        let response = client.get(uri!(get_item("notes@1"))).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "first");
+        let response = client.get(uri!(get_item_raw("notes@2"))).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "second");
+        let response = client
+            .get(uri!(get_item("notes@1.rs")))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        for missing in ["notes@3", "notes@x", "other@1"] {
+            let response = client.get(uri!(get_item(missing))).dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+
+        // The revisions of a view-limited paste would be read without counting a view
+        let options = PasteOptions {
+            max_views: Some(2),
+            ..Default::default()
+        };
+        for content in ["first", "second"] {
+            store
+                .store_with_id("limited".to_owned(), content.into(), &options)
+                .await
+                .unwrap();
+        }
+        for key in ["limited@1", "limited@2"] {
+            let response = client.get(uri!(get_item_raw(key))).dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+        let meta = store.meta("limited").await.unwrap().unwrap();
+        assert_eq!(meta.views_left, Some(2));
+    }
+
+    #[rocket::async_test]
+    async fn test_diff() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let options = PasteOptions {
+            language: Some("toml".to_owned()),
+            ..Default::default()
+        };
+        let before = store
+            .store(b"[server]\nport = 80\nhost = \"a\"\n".to_vec(), &options)
+            .await
+            .unwrap();
+        let after = store
+            .store(b"[server]\nport = 8080\nhost = \"a\"\n".to_vec(), &options)
+            .await
+            .unwrap();
+
+        let response = client.get(uri!(get_diff(&before, &after))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            format!(
+                "--- {}\n+++ {}\n@@ -1,3 +1,3 @@\n [server]\n-port = 80\n+port = 8080\n host = \"a\"\n",
+                before, after
+            )
+        );
+
+        let response = client
+            .get(uri!(get_diff(&before, format!("{}.toml", after))))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let html = response.into_string().await.unwrap();
+        assert_eq!(html.matches("<code class=\"equal\">").count(), 2);
+        assert_eq!(html.matches("<code class=\"delete\">").count(), 1);
+        assert_eq!(html.matches("<code class=\"insert\">").count(), 1);
+        assert!(html.contains("<span style="));
+
+        // Revisions can be compared too
+        store
+            .store_with_id(before.clone(), b"[client]\n".to_vec(), &options)
+            .await
+            .unwrap();
+        let first = format!("{}@1", before);
+        let response = client.get(uri!(get_diff(&first, &before))).dispatch().await;
+        let diff = response.into_string().await.unwrap();
+        assert!(diff.contains("-[server]\n"));
+        assert!(diff.contains("+[client]\n"));
+
+        let binary = store
+            .store(b"\x00\xff".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        let response = client
+            .get(uri!(get_diff(&before, &binary)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let response = client.get(uri!(get_diff(&before, "bob"))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Comparing a view-limited paste would use one of its views
+        let options = PasteOptions {
+            max_views: Some(1),
+            ..Default::default()
+        };
+        let limited = store.store(b"[server]\n".to_vec(), &options).await.unwrap();
+        let response = client
+            .get(uri!(get_diff(&before, &limited)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let meta = store.meta(&limited).await.unwrap().unwrap();
+        assert_eq!(meta.views_left, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn test_binary() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+        let options = PasteOptions {
+            mime_type: Some("image/png".to_owned()),
+            ..Default::default()
+        };
+        let key = store.store(PNG.to_vec(), &options).await.unwrap();
+
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.into_bytes().await.unwrap(), PNG);
+
+        // curl gets the content as-is
+        let response = client.get(uri!(get_item(&key))).dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.into_bytes().await.unwrap(), PNG);
+
+        // browsers get a preview
+        let response = client
+            .get(uri!(get_item(&key)))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let html = response.into_string().await.unwrap();
+        assert!(html.contains(&format!("<img src=\"{}/raw\"", key)));
+        assert!(!html.contains("base64"));
+
+        // Browsers must not guess another type, and only show images and text
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert_eq!(
+            response.headers().get_one("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("inline")
+        );
+        let options = PasteOptions {
+            mime_type: Some("application/pdf".to_owned()),
+            filename: Some("report.pdf".to_owned()),
+            ..Default::default()
+        };
+        let pdf = store.store(b"%PDF-\xff".to_vec(), &options).await.unwrap();
+        let response = client.get(uri!(get_item_raw(&pdf))).dispatch().await;
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("attachment; filename=\"report.pdf\"")
+        );
+
+        // A view-limited paste is served directly: loading the preview would use another view
+        let options = PasteOptions {
+            mime_type: Some("image/png".to_owned()),
+            max_views: Some(2),
+            ..Default::default()
+        };
+        let limited = store.store(PNG.to_vec(), &options).await.unwrap();
+        let response = client
+            .get(uri!(get_item(&limited)))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.into_bytes().await.unwrap(), PNG);
+        let meta = store.meta(&limited).await.unwrap().unwrap();
+        assert_eq!(meta.views_left, Some(1));
+
+        let response = client
+            .get(uri!(get_item(format!("{}.url", key))))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+        // Text is always served as plain text
+        let options = PasteOptions {
+            mime_type: Some("text/html".to_owned()),
+            ..Default::default()
+        };
+        let key = store.store(b"<b>hi</b>".to_vec(), &options).await.unwrap();
+        let response = client.get(uri!(get_item_raw(&key))).dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+    }
+
+    #[rocket::async_test]
+    async fn test_search() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        let key = store
+            .store(ENTRY_CONTENT.as_bytes().to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+
+        let response = client.get(uri!(search(Some("test")))).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get(uri!(search(Some("test"))))
+            .header(Header::new("X-API-Key", "wrong"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .get(uri!(search(Some("test"))))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let results: serde_json::Value =
+            serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(results[0]["id"], key);
+        assert_eq!(results[0]["snippet"], "This is a <mark>test</mark>");
+
+        let response = client
+            .get(uri!(search(Some("test"))))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let html = response.into_string().await.unwrap();
+        assert!(html.contains(&format!("<a href=\"{}\">", key)));
+        assert!(html.contains("This is a <mark>test</mark>"));
+
+        let response = client
+            .get(uri!(search(None::<&str>)))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.into_string().await.unwrap(), "[]");
+    }
+
+    #[rocket::async_test]
+    async fn test_list_pastes() {
+        let (_temp, client) = create_test_client().await;
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
+
+        for id in ["paste1", "paste2", "paste3"] {
+            store
+                .store_with_id(id.to_owned(), id.into(), &PasteOptions::default())
+                .await
+                .unwrap();
+        }
+
+        let response = client.get("/api/pastes").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let get = |url: String| {
+            let client = &client;
+            async move {
+                let response = client
+                    .get(url)
+                    .header(Header::new("X-API-Key", PASSWORD))
+                    .dispatch()
+                    .await;
+                assert_eq!(response.status(), Status::Ok);
+                serde_json::from_str::<serde_json::Value>(&response.into_string().await.unwrap())
+                    .unwrap()
+            }
+        };
+        let page = get("/api/pastes?limit=2".to_owned()).await;
+        assert_eq!(page["pastes"][0]["id"], "paste3");
+        assert_eq!(page["pastes"][0]["preview"], "paste3");
+        assert_eq!(page["pastes"][1]["id"], "paste2");
+        assert!(page["pastes"][0].get("data").is_none());
+        let cursor = page["next_cursor"].as_str().unwrap();
+        let page = get(format!("/api/pastes?limit=2&cursor={}", cursor)).await;
+        assert_eq!(page["pastes"].as_array().unwrap().len(), 1);
+        assert_eq!(page["pastes"][0]["id"], "paste1");
+        assert!(page["next_cursor"].is_null());
+
+        let response = client
+            .get("/api/pastes?cursor=invalid")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = client
+            .get("/pastes?limit=1&lang=rs")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let response = client
+            .get("/pastes?limit=1")
+            .header(Header::new("X-API-Key", PASSWORD))
+            .dispatch()
+            .await;
+        let html = response.into_string().await.unwrap();
+        assert!(html.contains("<a href=\"paste3\">"));
+        assert!(html.contains("pastes?cursor="));
+    }
+
     #[tokio::test]
     async fn test_all_entries() {
         let (_temp, client) = create_test_client().await;
-        let write_pool = client.rocket().state::<WritePool>().unwrap();
+        let store = client.rocket().state::<Arc<dyn PasteStore>>().unwrap();
 
         let response = client.get(uri!(all_entries)).dispatch().await;
         assert_eq!(response.status(), Status::Unauthorized);
@@ -301,7 +1451,8 @@ mod test {
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(response.into_string().await.unwrap(), "{}");
 
-        let key = io::store_paste(&write_pool, 5, 1000, ENTRY_CONTENT.to_string())
+        let key = store
+            .store(ENTRY_CONTENT.as_bytes().to_vec(), &PasteOptions::default())
             .await
             .unwrap();
         assert_ne!(key, "");
@@ -1,7 +1,8 @@
 use syntect::easy::HighlightLines;
 use syntect::highlighting::ThemeSet;
 use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
 use syntect::Error;
 
 pub struct Highlighter {
@@ -15,17 +16,39 @@ pub struct Highlighter {
 /// Returns `None` if the extension isn't supported.
 impl Highlighter {
     pub fn highlight(&self, content: &str, ext: &str) -> Result<String, Error> {
-        let syntax = self
-            .syntax_set
+        let mut h = self.highlight_lines_for(ext);
+        let regions = h.highlight_line(content, &self.syntax_set)?;
+
+        styled_line_to_highlighted_html(&regions[..], IncludeBackground::No)
+    }
+
+    /// Same as `highlight`, with one HTML fragment per line of the content, without the line
+    /// endings. The lines are highlighted in order, so that multi-line constructs stay coloured.
+    pub fn highlight_lines(&self, content: &str, ext: &str) -> Result<Vec<String>, Error> {
+        let mut h = self.highlight_lines_for(ext);
+        LinesWithEndings::from(content)
+            .map(|line| {
+                let regions = h.highlight_line(line, &self.syntax_set)?;
+                let html = styled_line_to_highlighted_html(&regions[..], IncludeBackground::No)?;
+                Ok(html.replace(['\r', '\n'], ""))
+            })
+            .collect()
+    }
+
+    fn highlight_lines_for(&self, ext: &str) -> HighlightLines<'_> {
+        HighlightLines::new(
+            self.find_syntax(ext),
+            &self.theme_set.themes["base16-ocean.dark"],
+        )
+    }
+
+    fn find_syntax(&self, ext: &str) -> &SyntaxReference {
+        self.syntax_set
             .find_syntax_by_extension(ext)
             // Made the decision to always try to return "something", even if the extension is not right.
             // Some extensions might not be recognized by the highlighter but would still be valid. In that
             // case the user will probably still want the extension to be kept in the URL.
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
-        let mut h = HighlightLines::new(syntax, &self.theme_set.themes["base16-ocean.dark"]);
-        let regions = h.highlight_line(content, &self.syntax_set)?;
-
-        styled_line_to_highlighted_html(&regions[..], IncludeBackground::No)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
     }
 
     pub fn new() -> Highlighter {
@@ -0,0 +1,263 @@
+use rand::rngs::OsRng;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+use std::sync::Mutex;
+
+use crate::io::PasteOptions;
+
+// removed 0/o, i/1/l, u/v as they are too similar. with 4 char this gives us >700'000 unique ids
+const CHARSET: &[u8] = b"abcdefghjkmnpqrstwxyz23456789";
+
+/// Length of the ids of the private pastes: about 155 bits of entropy
+pub const PRIVATE_ID_LENGTH: usize = 32;
+
+/// Number of random ids tried at a length before trying longer ones
+pub const MAX_ID_ATTEMPTS: usize = 20;
+
+/// Share of the ids of a length that can be taken before the new ids get one more character.
+/// Below it, a random id is free at least half of the time.
+pub const MAX_UTILISATION: f64 = 0.5;
+
+pub fn generate_id(length: usize) -> String {
+    (0..length)
+        .map(|_| {
+            let idx = thread_rng().gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect::<String>()
+}
+
+/// Id of a private paste, drawn from the random generator of the operating system so that it
+/// cannot be guessed
+pub fn generate_private_id() -> String {
+    (0..PRIVATE_ID_LENGTH)
+        .map(|_| CHARSET[OsRng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+/// Number of different ids of this length
+pub fn capacity(length: usize) -> u128 {
+    u32::try_from(length)
+        .ok()
+        .and_then(|length| (CHARSET.len() as u128).checked_pow(length))
+        .unwrap_or(u128::MAX)
+}
+
+/// Ids to try in turn for a new paste. The short ones start at the given length, the one of
+/// `IdUsage::length_for`, and get longer when all the attempts at a length collide. Private ones
+/// are long and unguessable.
+///
+/// The backends insert the first one that is not taken: nothing is ever evicted to make room.
+pub fn candidates(options: &PasteOptions, length: usize) -> impl Iterator<Item = String> {
+    let private = options.private;
+    let lengths = if private {
+        PRIVATE_ID_LENGTH..=PRIVATE_ID_LENGTH
+    } else {
+        length..=length.max(PRIVATE_ID_LENGTH)
+    };
+    let first = *lengths.start();
+    lengths.flat_map(move |length| {
+        if length > first {
+            warn!(
+                "[ID] No free id of {} characters after {} attempts",
+                length - 1,
+                MAX_ID_ATTEMPTS
+            );
+        }
+        (0..MAX_ID_ATTEMPTS).map(move |_| {
+            if private {
+                generate_private_id()
+            } else {
+                generate_id(length)
+            }
+        })
+    })
+}
+
+/// The ids taken, counted once then kept up to date by the new pastes: counting them goes
+/// through every paste. They are counted again after a new id collided with a taken one, which
+/// gets likely as the keyspace fills up, to catch up with the deleted pastes and the ids chosen
+/// by the clients.
+#[derive(Debug, Default)]
+pub struct UsageCache(Mutex<Option<IdUsage>>);
+
+impl UsageCache {
+    /// The usage kept, None when the ids must be counted
+    pub fn get(&self) -> Option<IdUsage> {
+        self.usage().clone()
+    }
+
+    pub fn set(&self, usage: IdUsage) {
+        *self.usage() = Some(usage);
+    }
+
+    /// Counts the id of a new paste
+    pub fn add(&self, id: &str) {
+        if let Some(usage) = self.usage().as_mut() {
+            usage.add(id.chars().count(), 1);
+        }
+    }
+
+    /// After a collision: the next paste counts the ids again
+    pub fn forget(&self) {
+        *self.usage() = None;
+    }
+
+    fn usage(&self) -> std::sync::MutexGuard<'_, Option<IdUsage>> {
+        // The usage is only a hint, even when left by a panicking thread
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// How many ids of each length are taken, by pastes alive or expired but not evicted yet
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdUsage(BTreeMap<usize, u64>);
+
+impl IdUsage {
+    pub fn add(&mut self, length: usize, count: u64) {
+        *self.0.entry(length).or_insert(0) += count;
+    }
+
+    pub fn used(&self, length: usize) -> u64 {
+        self.0.get(&length).copied().unwrap_or(0)
+    }
+
+    /// Total number of ids taken
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    fn utilisation(&self, length: usize) -> f64 {
+        self.used(length) as f64 / capacity(length) as f64
+    }
+
+    /// Length of the new ids: the configured one, or a longer one once its keyspace is too full
+    pub fn length_for(&self, id_length: usize) -> usize {
+        let mut length = id_length;
+        while self.utilisation(length) >= MAX_UTILISATION {
+            length += 1;
+        }
+        length
+    }
+
+    /// Utilisation of the keyspaces from the configured length to the one of the new ids
+    pub fn report(&self, id_length: usize) -> KeyspaceReport {
+        let current_length = self.length_for(id_length);
+        KeyspaceReport {
+            id_length,
+            current_length,
+            max_utilisation: MAX_UTILISATION,
+            lengths: (id_length..=current_length)
+                .map(|length| LengthUsage {
+                    length,
+                    used: self.used(length),
+                    capacity: capacity(length),
+                    utilisation: self.utilisation(length),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a str> for IdUsage {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(ids: I) -> IdUsage {
+        let mut usage = IdUsage::default();
+        for id in ids {
+            usage.add(id.chars().count(), 1);
+        }
+        usage
+    }
+}
+
+/// Utilisation of the keyspace of the short ids, for the admins
+#[derive(Debug, Serialize)]
+pub struct KeyspaceReport {
+    /// Length of the ids in the configuration
+    pub id_length: usize,
+    /// Length of the new ids, longer than id_length once its keyspace is too full
+    pub current_length: usize,
+    /// Share of a keyspace that can be taken before the ids get longer
+    pub max_utilisation: f64,
+    pub lengths: Vec<LengthUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LengthUsage {
+    pub length: usize,
+    pub used: u64,
+    pub capacity: u128,
+    pub utilisation: f64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(capacity(1), 29);
+        assert_eq!(capacity(4), 707_281);
+        assert_eq!(capacity(100), u128::MAX);
+    }
+
+    #[test]
+    fn test_length_for() {
+        let mut usage: IdUsage = ["a", "b", "cd", "named"].iter().copied().collect();
+        assert_eq!(usage.used(1), 2);
+        assert_eq!(usage.total(), 4);
+        assert_eq!(usage.length_for(1), 1);
+
+        // Half of the 29 ids of one character
+        usage.add(1, 13);
+        assert_eq!(usage.length_for(1), 2);
+        assert_eq!(usage.length_for(4), 4);
+        usage.add(2, 419);
+        assert_eq!(usage.length_for(1), 2);
+        usage.add(2, 1);
+        assert_eq!(usage.length_for(1), 3);
+
+        let report = usage.report(1);
+        assert_eq!(report.current_length, 3);
+        let used: Vec<_> = report.lengths.iter().map(|l| (l.length, l.used)).collect();
+        assert_eq!(used, vec![(1, 15), (2, 421), (3, 0)]);
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut usage = IdUsage::default();
+        usage.add(1, 15);
+        let ids: Vec<_> = candidates(&PasteOptions::default(), usage.length_for(1))
+            .take(MAX_ID_ATTEMPTS + 1)
+            .collect();
+        assert!(ids[..MAX_ID_ATTEMPTS].iter().all(|id| id.len() == 2));
+        assert_eq!(ids[MAX_ID_ATTEMPTS].len(), 3);
+
+        let private = PasteOptions {
+            private: true,
+            ..Default::default()
+        };
+        let ids: Vec<_> = candidates(&private, 2).collect();
+        assert_eq!(ids.len(), MAX_ID_ATTEMPTS);
+        assert!(ids.iter().all(|id| id.len() == PRIVATE_ID_LENGTH));
+    }
+
+    #[test]
+    fn test_usage_cache() {
+        let cache = UsageCache::default();
+        cache.add("ab");
+        assert_eq!(cache.get(), None);
+        cache.set(["ab", "cd"].iter().copied().collect());
+        cache.add("ef");
+        cache.add("ghi");
+        assert_eq!(cache.get().unwrap().used(2), 3);
+        assert_eq!(cache.get().unwrap().used(3), 1);
+        cache.forget();
+        assert_eq!(cache.get(), None);
+    }
+}
@@ -1,16 +1,22 @@
-extern crate rand;
-
-use rand::{thread_rng, Rng};
-
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use sqlx::Row;
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt::Display;
+use std::sync::Arc;
 
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool};
 use sqlx::Executor;
 
-pub struct WritePool(pub SqlitePool);
+use crate::ids::{self, IdUsage, UsageCache};
+use crate::migrations;
+use crate::ttl::Ttl;
+
+/// The connection for the writes, with the ids taken that it last counted
+#[derive(Clone)]
+pub struct WritePool(pub SqlitePool, Arc<UsageCache>);
 
 impl WritePool {
     pub async fn new(file_name: &str) -> Result<WritePool, IOError> {
@@ -26,23 +32,20 @@ impl WritePool {
                         .read_only(false),
                 )
                 .await?,
+            Arc::default(),
         ))
     }
 
+    /// Create the database schema or upgrade it to the latest version
     pub async fn init(&self) -> Result<(), IOError> {
-        let mut cnx = self.0.acquire().await?;
-        cnx.execute(
-            "CREATE TABLE IF NOT EXISTS entries (
-            internal_id INTEGER PRIMARY KEY AUTOINCREMENT,
-            id VARCHAR(16) UNIQUE,
-            data TEXT NOT NULL
-        )",
-        )
-        .await?;
-
-        cnx.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_id ON entries(id)")
-            .await?;
-
+        let version = migrations::schema_version(&mut self.0.acquire().await?).await?;
+        migrations::migrate(self).await?;
+        // The content may be compressed, the search index of the existing entries can only be
+        // built here
+        if version < migrations::SEARCH_INDEX_VERSION {
+            let indexed = rebuild_search_index(self).await?;
+            info!("[MIGRATION] Indexed {} entries for the search", indexed);
+        }
         Ok(())
     }
 }
@@ -64,21 +67,19 @@ impl ReadPool {
     }
 }
 
-fn generate_id(length: usize) -> String {
-    // removed 0/o, i/1/l, u/v as they are too similar. with 4 char this gives us >700'000 unique ids
-    const CHARSET: &[u8] = b"abcdefghjkmnpqrstwxyz23456789";
-    (0..length)
-        .map(|_| {
-            let idx = thread_rng().gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect::<String>()
+/// Current time as a unix timestamp, the format of the expires_at column
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
 }
 
-pub async fn remove_old(
-    cnx: &mut sqlx::pool::PoolConnection<sqlx::Sqlite>,
-    max_entries: i32,
-) -> Result<u64, IOError> {
+pub fn expiration_date(ttl: Option<Ttl>) -> Option<i64> {
+    ttl.map(|ttl| now().saturating_add(i64::try_from(ttl.0.as_secs()).unwrap_or(i64::MAX)))
+}
+
+pub async fn remove_old(cnx: &mut SqliteConnection, max_entries: i32) -> Result<u64, IOError> {
     let result = cnx
         .execute(
             sqlx::query(
@@ -93,17 +94,243 @@ pub async fn remove_old(
     Ok(result.rows_affected())
 }
 
-/// Delete a paste under the given id
-pub async fn delete_paste<'a>(pool: &WritePool, id: &'a str) -> Result<&'a str, IOError> {
+/// Delete all the pastes that have expired, and the contents that are not referenced anymore
+pub async fn purge_expired(pool: &WritePool) -> Result<u64, IOError> {
+    let result = sqlx::query("DELETE FROM entries WHERE expires_at <= ?")
+        .bind(now())
+        .execute(&pool.0)
+        .await?;
+    // Left behind when storing an entry failed after its content was stored
+    sqlx::query("DELETE FROM contents WHERE refcount <= 0")
+        .execute(&pool.0)
+        .await?;
+    sqlx::query("DELETE FROM share_links WHERE expires_at <= ?")
+        .bind(now())
+        .execute(&pool.0)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delete a paste under the given id. Returns false if there was none.
+pub async fn delete_paste(pool: &WritePool, id: &str) -> Result<bool, IOError> {
     let result = sqlx::query("DELETE FROM entries WHERE id = ?")
         .bind(id)
         .execute(&pool.0)
         .await?;
 
-    if result.rows_affected() == 0 {
-        return Err(IOError("Not found".to_owned()));
+    Ok(result.rows_affected() > 0)
+}
+
+/// Options and metadata given by the author of a paste
+#[derive(Clone, Debug, Default)]
+pub struct PasteOptions {
+    /// The paste is not served anymore after this duration
+    pub ttl: Option<Ttl>,
+    /// The paste is deleted after having been read this many times
+    pub max_views: Option<u32>,
+    /// Extension used to highlight the paste when none is given in the URL
+    pub language: Option<String>,
+    pub mime_type: Option<String>,
+    /// Label of the key used to create the paste
+    pub creator: Option<String>,
+    /// Name of the uploaded file
+    pub filename: Option<String>,
+    /// Identity allowed to manage the paste, besides the admins
+    pub owner: Option<String>,
+    /// SHA-256 of the edit token given to the author, in hexadecimal
+    pub token_hash: Option<String>,
+    /// Argon2 hash of the password needed to read the paste
+    pub read_password_hash: Option<String>,
+    /// Left out of the listings and of the search, with a long id when it is generated
+    pub private: bool,
+}
+
+/// A paste, as served to a reader
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Paste {
+    pub data: Vec<u8>,
+    pub language: Option<String>,
+    pub mime_type: Option<String>,
+    pub filename: Option<String>,
+}
+
+impl Paste {
+    /// Content of the paste, if it is text
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
     }
-    Ok(id)
+}
+
+/// Pastes smaller than this are stored as-is: they would not shrink much, and short pastes such as
+/// the links of the URL shortener are the ones read the most.
+const COMPRESSION_THRESHOLD: usize = 4096;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Value of the codec column for zstd compressed data. Uncompressed data has a NULL codec.
+const ZSTD_CODEC: &str = "zstd";
+
+/// Compress the content of a paste if it is worth it. Returns the data to store and its codec.
+fn encode(content: &[u8]) -> Result<(Cow<'_, [u8]>, Option<&'static str>), IOError> {
+    if content.len() < COMPRESSION_THRESHOLD {
+        return Ok((Cow::Borrowed(content), None));
+    }
+    let compressed = zstd::bulk::compress(content, ZSTD_LEVEL)
+        .map_err(|e| IOError(format!("Compression error: {}", e)))?;
+    if compressed.len() < content.len() {
+        Ok((Cow::Owned(compressed), Some(ZSTD_CODEC)))
+    } else {
+        Ok((Cow::Borrowed(content), None))
+    }
+}
+
+/// Content of a paste from the stored data and its codec
+fn decode(data: Vec<u8>, codec: Option<&str>) -> Result<Vec<u8>, IOError> {
+    match codec {
+        None => Ok(data),
+        Some(ZSTD_CODEC) => zstd::stream::decode_all(data.as_slice())
+            .map_err(|e| IOError(format!("Decompression error: {}", e))),
+        Some(codec) => Err(IOError(format!("Unknown codec {}", codec))),
+    }
+}
+
+/// Identifier of a content in the contents table
+fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Store a content in the contents table if it is not there yet, and return its hash.
+///
+/// The reference count of the content is maintained by triggers on the entries table: a content
+/// that ends up not referenced by any entry is removed by purge_expired.
+async fn store_content(cnx: &mut SqliteConnection, content: &[u8]) -> Result<String, IOError> {
+    let hash = content_hash(content);
+    let exists = cnx
+        .fetch_optional(sqlx::query("SELECT 1 FROM contents WHERE hash = ?").bind(&hash))
+        .await?
+        .is_some();
+    if exists {
+        return Ok(hash);
+    }
+
+    let (data, codec) = encode(content)?;
+    let query =
+        sqlx::query("INSERT INTO contents (hash, data, codec) VALUES (?, ?, ?)").bind(&hash);
+    // Text is kept as TEXT to keep the database easy to inspect, anything else is a BLOB
+    let query = match (codec, std::str::from_utf8(&data)) {
+        (None, Ok(text)) => query.bind(text),
+        _ => query.bind(data.as_ref()),
+    };
+    cnx.execute(query.bind(codec)).await?;
+    Ok(hash)
+}
+
+/// Columns read by paste_from_row. Entries stored before the deduplication have their content
+/// inline instead of a content_hash.
+const PASTE_COLUMNS: &str = "
+    CASE WHEN entries.content_hash IS NULL THEN CAST(entries.data AS BLOB)
+        ELSE (SELECT CAST(contents.data AS BLOB) FROM contents
+            WHERE contents.hash = entries.content_hash) END AS data,
+    CASE WHEN entries.content_hash IS NULL THEN entries.codec
+        ELSE (SELECT contents.codec FROM contents
+            WHERE contents.hash = entries.content_hash) END AS codec,
+    language, mime_type, filename";
+
+fn paste_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Paste, IOError> {
+    Ok(Paste {
+        data: decode(row.get("data"), row.get("codec"))?,
+        language: row.get("language"),
+        mime_type: row.get("mime_type"),
+        filename: row.get("filename"),
+    })
+}
+
+/// Everything we know about a paste, except its content
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PasteMeta {
+    pub id: String,
+    /// Unix timestamps, unknown for pastes created before bibin recorded them
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    /// Size of the content in bytes
+    pub size: i64,
+    pub language: Option<String>,
+    pub mime_type: Option<String>,
+    pub creator: Option<String>,
+    pub filename: Option<String>,
+    pub expires_at: Option<i64>,
+    pub views_left: Option<u32>,
+    /// Number of the current revision, starting at 1 and incremented by each overwrite
+    #[serde(default = "default_revision")]
+    pub revision: u32,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Never shown to the readers, see `without_secrets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_hash: Option<String>,
+    /// Never shown to the readers either
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_password_hash: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+}
+
+impl PasteMeta {
+    /// The metadata that can be shown to anybody
+    pub fn without_secrets(mut self) -> PasteMeta {
+        self.token_hash = None;
+        self.read_password_hash = None;
+        self
+    }
+
+    /// Whether reading the content uses a view or needs a password: no part of it may be shown
+    /// elsewhere than on the paste itself
+    pub fn is_guarded(&self) -> bool {
+        self.views_left.is_some() || self.read_password_hash.is_some()
+    }
+}
+
+fn default_revision() -> u32 {
+    1
+}
+
+/// A version of a paste, as listed in its history
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Revision {
+    pub revision: u32,
+    /// Unix timestamp of the creation of this revision
+    pub created_at: Option<i64>,
+    pub size: i64,
+    pub language: Option<String>,
+    pub mime_type: Option<String>,
+    pub creator: Option<String>,
+    pub filename: Option<String>,
+}
+
+fn insert_entry<'q>(
+    statement: &'q str,
+    id: &'q str,
+    content: &'q [u8],
+    hash: &'q str,
+    options: &'q PasteOptions,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    // Statements use numbered parameters to be able to reference the same value twice
+    sqlx::query(statement)
+        .bind(id)
+        .bind(hash)
+        .bind(expiration_date(options.ttl))
+        .bind(options.max_views)
+        .bind(content.len() as i64)
+        .bind(options.language.as_deref())
+        .bind(options.mime_type.as_deref())
+        .bind(options.creator.as_deref())
+        .bind(now())
+        .bind(options.filename.as_deref())
+        .bind(options.owner.as_deref())
+        .bind(options.token_hash.as_deref())
+        .bind(options.read_password_hash.as_deref())
+        .bind(options.private)
 }
 
 /// Stores a paste under a new id
@@ -111,109 +338,869 @@ pub async fn store_paste(
     pool: &WritePool,
     id_length: usize,
     max_entries: i32,
-    content: String,
+    content: Vec<u8>,
+    options: &PasteOptions,
 ) -> Result<String, IOError> {
-    // If we acquire the connection, nobody else can get it
-    let mut cnx = pool.0.acquire().await?;
-
-    let id = generate_id(id_length);
-    let result = cnx
-        .execute(
-            sqlx::query("INSERT OR IGNORE INTO entries (id, data) VALUES (?, ?)")
-                .bind(&id)
-                .bind(&content),
-        )
-        .await?;
-
-    if result.rows_affected() == 1 {
-        return Ok(id);
+    // If we begin the transaction, nobody else can get the connection
+    let mut transaction = pool.0.begin().await?;
+    let id = insert_new_entry(
+        &mut transaction,
+        &pool.1,
+        id_length,
+        max_entries,
+        &content,
+        options,
+    )
+    .await?;
+    let indexed = index_content(&mut transaction, &id, &content).await;
+    if let Err(e) = indexed {
+        // The id counted as taken is rolled back
+        pool.1.forget();
+        return Err(e);
     }
+    transaction.commit().await?;
+    Ok(id)
+}
 
-    let entries = cnx
-        .fetch_one(sqlx::query("select count(*) from entries"))
-        .await?
-        .get::<i32, usize>(0);
+/// Inserts the entry under the first free id given by the allocator. The oldest entries are
+/// removed afterwards if there are more than max_entries of them, never to make room for an id.
+async fn insert_new_entry(
+    cnx: &mut SqliteConnection,
+    taken: &UsageCache,
+    id_length: usize,
+    max_entries: i32,
+    content: &[u8],
+    options: &PasteOptions,
+) -> Result<String, IOError> {
+    const INSERT_OR_IGNORE: &str = "INSERT OR IGNORE INTO entries
+        (id, data, content_hash, expires_at, views_left, size, language, mime_type, creator,
+        created_at, updated_at, filename, owner, token_hash, read_password_hash, private)
+        VALUES (?1, '', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9, ?10, ?11, ?12, ?13, ?14)";
 
-    warn!(
-        "ID Collision ({} entries), cleaning up old entries and retrying",
-        entries
-    );
-    let nb_entries_removed = remove_old(&mut cnx, max_entries).await?;
-    warn!("Removed {} entries", nb_entries_removed);
-
-    let mut retries = 0;
-    let max_retries = 20;
-    while retries < max_entries {
-        warn!("Another ID Collision: {}/{}", retries, max_retries);
-        let id = generate_id(id_length);
+    let hash = store_content(cnx, content).await?;
+    let usage = match taken.get() {
+        Some(usage) => usage,
+        None => {
+            let usage = count_ids(cnx).await?;
+            taken.set(usage.clone());
+            usage
+        }
+    };
+
+    for id in ids::candidates(options, usage.length_for(id_length)) {
         let result = cnx
-            .execute(
-                sqlx::query("INSERT OR IGNORE INTO entries (id, data) VALUES (?, ?)")
-                    .bind(&id)
-                    .bind(&content),
-            )
+            .execute(insert_entry(INSERT_OR_IGNORE, &id, content, &hash, options))
             .await?;
-
         if result.rows_affected() == 1 {
+            taken.add(&id);
+            let removed = remove_old(cnx, max_entries).await?;
+            if removed > 0 {
+                info!("[ID] Removed {} entries over max_entries", removed);
+            }
             return Ok(id);
         }
-        retries += 1;
+        taken.forget();
     }
+    Err(IOError("No free id left".to_owned()))
+}
 
-    warn!("ID Collision again, last attempt");
-    let id = generate_id(id_length);
-    cnx.execute(
-        sqlx::query("INSERT INTO entries (id, data) VALUES (?, ?)")
-            .bind(generate_id(id_length))
-            .bind(&content),
-    )
-    .await?;
+async fn count_ids(cnx: &mut SqliteConnection) -> Result<IdUsage, IOError> {
+    let rows = cnx
+        .fetch_all(sqlx::query(
+            "SELECT length(id), COUNT(*) FROM entries GROUP BY length(id)",
+        ))
+        .await?;
+    let mut usage = IdUsage::default();
+    for row in rows {
+        let length: i64 = row.get(0);
+        let count: i64 = row.get(1);
+        usage.add(
+            usize::try_from(length).unwrap_or(0),
+            u64::try_from(count).unwrap_or(0),
+        );
+    }
+    Ok(usage)
+}
 
-    Ok(id)
+/// How many ids of each length are taken
+pub async fn id_usage(pool: &ReadPool) -> Result<IdUsage, IOError> {
+    let mut cnx = pool.0.acquire().await?;
+    count_ids(&mut cnx).await
 }
 
-/// Stores a paste under a new id
+/// Stores a paste under the given id. The paste it replaces becomes a revision of the new one,
+/// the oldest revisions are deleted to keep at most max_revisions of them.
 pub async fn store_paste_given_id(
     pool: &WritePool,
     id: String,
-    content: String,
+    content: Vec<u8>,
+    options: &PasteOptions,
+    max_revisions: u32,
 ) -> Result<String, IOError> {
-    let mut cnx = pool.0.acquire().await?;
+    let mut transaction = pool.0.begin().await?;
 
-    let _result = cnx
-        .execute(
-            sqlx::query("INSERT OR REPLACE INTO entries (id, data) VALUES (?, ?)")
-                .bind(&id)
-                .bind(&content),
+    let current = transaction
+        .fetch_optional(
+            sqlx::query(
+                "SELECT revision, content_hash IS NULL AS inline,
+                expires_at IS NOT NULL AND expires_at <= ? AS expired
+                FROM entries WHERE id = ?",
+            )
+            .bind(now())
+            .bind(&id),
         )
         .await?;
 
+    match current {
+        Some(row) if !row.get::<bool, &str>("expired") => {
+            if max_revisions > 0 {
+                if row.get::<bool, &str>("inline") {
+                    // Revisions reference their content: move the content of entries stored
+                    // before the deduplication to the contents table
+                    let row = transaction
+                        .fetch_one(
+                            sqlx::query(
+                                "SELECT CAST(data AS BLOB) AS data, codec FROM entries WHERE id = ?",
+                            )
+                            .bind(&id),
+                        )
+                        .await?;
+                    let data = decode(row.get("data"), row.get("codec"))?;
+                    let hash = store_content(&mut transaction, &data).await?;
+                    transaction
+                        .execute(
+                            sqlx::query(
+                                "UPDATE entries SET content_hash = ?, data = '', codec = NULL
+                                WHERE id = ?",
+                            )
+                            .bind(hash)
+                            .bind(&id),
+                        )
+                        .await?;
+                }
+
+                transaction
+                    .execute(
+                        sqlx::query(
+                            "INSERT INTO revisions
+                            (id, revision, content_hash, size, language, mime_type, creator,
+                            filename, created_at)
+                            SELECT id, revision, content_hash, size, language, mime_type, creator,
+                            filename, COALESCE(updated_at, created_at)
+                            FROM entries WHERE id = ?",
+                        )
+                        .bind(&id),
+                    )
+                    .await?;
+                let revision: u32 = row.get("revision");
+                transaction
+                    .execute(
+                        sqlx::query("DELETE FROM revisions WHERE id = ? AND revision <= ?")
+                            .bind(&id)
+                            .bind(i64::from(revision) - i64::from(max_revisions)),
+                    )
+                    .await?;
+            }
+
+            let hash = store_content(&mut transaction, &content).await?;
+            transaction
+                .execute(insert_entry(
+                    // The new revision counts as a new entry for remove_old
+                    "UPDATE entries SET
+                    internal_id = (SELECT MAX(internal_id) + 1 FROM entries),
+                    data = '', codec = NULL, content_hash = ?2, expires_at = ?3, views_left = ?4,
+                    size = ?5, language = ?6, mime_type = ?7, creator = ?8, updated_at = ?9,
+                    filename = ?10, revision = revision + 1,
+                    read_password_hash = COALESCE(?13, read_password_hash)
+                    WHERE id = ?1",
+                    &id,
+                    &content,
+                    &hash,
+                    options,
+                ))
+                .await?;
+        }
+        current => {
+            if current.is_some() {
+                // Expired, but not purged yet. Its revisions are deleted with it.
+                transaction
+                    .execute(sqlx::query("DELETE FROM entries WHERE id = ?").bind(&id))
+                    .await?;
+            }
+            let hash = store_content(&mut transaction, &content).await?;
+            transaction
+                .execute(insert_entry(
+                    "INSERT INTO entries
+                    (id, data, content_hash, expires_at, views_left, size, language, mime_type,
+                    creator, created_at, updated_at, filename, owner, token_hash,
+                    read_password_hash, private)
+                    VALUES (?1, '', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9, ?10, ?11, ?12, ?13, ?14)",
+                    &id,
+                    &content,
+                    &hash,
+                    options,
+                ))
+                .await?;
+        }
+    }
+
+    index_content(&mut transaction, &id, &content).await?;
+    transaction.commit().await?;
     Ok(id)
 }
 
-pub async fn get_paste(pool: &ReadPool, id: &str) -> Result<Option<String>, IOError> {
-    let result = sqlx::query("SELECT data FROM entries WHERE id = ?")
-        .bind(id)
-        .fetch_one(&pool.0)
-        .await;
+/// Start and end of the matches in the raw snippets
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+/// A paste matching a search
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SearchResult {
+    pub id: String,
+    /// Extract of the content around the matches, as HTML: the text is escaped and the matches
+    /// are in <mark> tags
+    pub snippet: String,
+}
+
+/// Build the HTML snippet from an extract where the matches are between MATCH_START and MATCH_END
+fn mark_snippet(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len());
+    for (index, part) in raw.split([MATCH_START, MATCH_END]).enumerate() {
+        if index % 2 == 1 {
+            html.push_str("<mark>");
+            html.push_str(&rocket::http::RawStr::new(part).html_escape());
+            html.push_str("</mark>");
+        } else {
+            html.push_str(&rocket::http::RawStr::new(part).html_escape());
+        }
+    }
+    html
+}
+
+/// Start and length of the first word of the query in the text, if it contains every word,
+/// ignoring the case of ASCII letters
+pub fn find_words(text: &str, query: &str) -> Option<(usize, usize)> {
+    let lowercase = text.to_ascii_lowercase();
+    let mut first = None;
+    for word in query.split_whitespace() {
+        let start = lowercase.find(&word.to_ascii_lowercase())?;
+        first = first.or(Some((start, word.len())));
+    }
+    first
+}
+
+/// HTML snippet of a text around the match of the given length at start
+pub fn snippet_around(text: &str, start: usize, length: usize) -> String {
+    const CONTEXT: usize = 40;
+    let mut from = start.saturating_sub(CONTEXT);
+    while !text.is_char_boundary(from) {
+        from -= 1;
+    }
+    let mut to = (start + length + CONTEXT).min(text.len());
+    while !text.is_char_boundary(to) {
+        to += 1;
+    }
+    let raw = format!(
+        "{}{}{}{}{}{}{}",
+        if from > 0 { "…" } else { "" },
+        &text[from..start],
+        MATCH_START,
+        &text[start..start + length],
+        MATCH_END,
+        &text[start + length..to],
+        if to < text.len() { "…" } else { "" },
+    );
+    mark_snippet(&raw)
+}
+
+/// Add the content of an entry to the search index. Binary content is not indexed.
+///
+/// The index is keyed by the internal id of the entries: the triggers remove the tokens of the
+/// entries deleted or overwritten.
+async fn index_content(
+    cnx: &mut SqliteConnection,
+    id: &str,
+    content: &[u8],
+) -> Result<(), IOError> {
+    if let Ok(text) = std::str::from_utf8(content) {
+        cnx.execute(
+            sqlx::query(
+                "INSERT INTO entries_search (rowid, content)
+                SELECT internal_id, ? FROM entries WHERE id = ?",
+            )
+            .bind(text)
+            .bind(id),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Index again the content of every entry. Returns the number of entries indexed.
+pub async fn rebuild_search_index(pool: &WritePool) -> Result<u64, IOError> {
+    const BATCH_SIZE: i64 = 100;
+    let mut cnx = pool.0.acquire().await?;
+    cnx.execute("DELETE FROM entries_search").await?;
+
+    let mut indexed = 0;
+    let mut last = i64::MIN;
+    loop {
+        let rows = cnx
+            .fetch_all(
+                sqlx::query(&format!(
+                    "SELECT internal_id, id, {} FROM entries
+                    WHERE internal_id > ? ORDER BY internal_id LIMIT ?",
+                    PASTE_COLUMNS
+                ))
+                .bind(last)
+                .bind(BATCH_SIZE),
+            )
+            .await?;
+        for row in rows.iter() {
+            last = row.get("internal_id");
+            let id: String = row.get("id");
+            index_content(&mut cnx, &id, &paste_from_row(row)?.data).await?;
+            indexed += 1;
+        }
+        if rows.len() < BATCH_SIZE as usize {
+            return Ok(indexed);
+        }
+    }
+}
+
+/// Pastes whose content contains every word of the query, best matches first. The private pastes
+/// are never found, nor those guarded by a view limit or a read password.
+pub async fn search(
+    pool: &ReadPool,
+    query: &str,
+    limit: u32,
+) -> Result<Vec<SearchResult>, IOError> {
+    // Each word is searched as-is: quoted, the FTS5 operators and punctuation are not interpreted
+    let pattern = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if pattern.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The index does not hold the content: the snippets are cut from the decompressed pastes
+    let rows = sqlx::query(&format!(
+        "SELECT entries.id, {}
+        FROM entries_search JOIN entries ON entries.internal_id = entries_search.rowid
+        WHERE entries_search MATCH ?1 AND (expires_at IS NULL OR expires_at > ?2)
+        AND NOT private AND views_left IS NULL AND read_password_hash IS NULL
+        ORDER BY rank LIMIT ?3",
+        PASTE_COLUMNS
+    ))
+    .bind(pattern)
+    .bind(now())
+    .bind(limit)
+    .fetch_all(&pool.0)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            let paste = paste_from_row(row)?;
+            let text = String::from_utf8_lossy(&paste.data);
+            Ok(SearchResult {
+                id: row.get("id"),
+                snippet: search_snippet(&text, query),
+            })
+        })
+        .collect()
+}
+
+/// Snippet of a text found by the index. The index also ignores the case of the non-ASCII
+/// letters: the beginning of the text is shown when the words cannot be found here.
+fn search_snippet(text: &str, query: &str) -> String {
+    match find_words(text, query) {
+        Some((start, length)) => snippet_around(text, start, length),
+        None => snippet_around(text, 0, 0).replace("<mark></mark>", ""),
+    }
+}
+
+/// Id of a paste with the same content, whoever stored it, that can be served instead of storing
+/// this one: it has no view limit and does not expire before the new paste would.
+pub async fn find_duplicate(
+    pool: &ReadPool,
+    content: &[u8],
+    options: &PasteOptions,
+) -> Result<Option<String>, IOError> {
+    let result = sqlx::query(
+        "SELECT id FROM entries
+        WHERE content_hash = ?1 AND views_left IS NULL
+        AND read_password_hash IS NULL AND private = ?3
+        AND (expires_at IS NULL OR (?2 IS NOT NULL AND expires_at >= ?2))
+        ORDER BY internal_id DESC LIMIT 1",
+    )
+    .bind(content_hash(content))
+    .bind(expiration_date(options.ttl))
+    .bind(options.private)
+    .fetch_optional(&pool.0)
+    .await?;
+
+    Ok(result.map(|row| row.get(0)))
+}
+
+/// Get the content of a paste without counting it as a view
+#[cfg(test)]
+pub async fn get_paste(pool: &ReadPool, id: &str) -> Result<Option<Paste>, IOError> {
+    let result = sqlx::query(&format!(
+        "SELECT {} FROM entries WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)",
+        PASTE_COLUMNS
+    ))
+    .bind(id)
+    .bind(now())
+    .fetch_one(&pool.0)
+    .await;
 
     match result {
         Err(sqlx::Error::RowNotFound) => Ok(None),
-        Ok(row) => Ok(row.get(0)),
+        Ok(row) => Ok(Some(paste_from_row(&row)?)),
         Err(e) => Err(e.into()),
     }
 }
 
-pub async fn get_all_paste(pool: &ReadPool) -> Result<Vec<(String, String)>, IOError> {
-    let result = sqlx::query("SELECT id, data FROM entries")
-        .fetch_all(&pool.0)
+/// Get the content of a paste to show it to a reader.
+///
+/// Pastes without a view limit are served from the read-only pool. The others go through the
+/// single connection of the WritePool, where the view counter is decremented and the entry deleted
+/// on its last view in one transaction: concurrent readers can never see more views than allowed.
+pub async fn read_paste(
+    read_pool: &ReadPool,
+    write_pool: &WritePool,
+    id: &str,
+) -> Result<Option<Paste>, IOError> {
+    let result = sqlx::query(&format!(
+        "SELECT {}, views_left FROM entries
+        WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)",
+        PASTE_COLUMNS
+    ))
+    .bind(id)
+    .bind(now())
+    .fetch_optional(&read_pool.0)
+    .await?;
+
+    match result {
+        None => Ok(None),
+        Some(row) => match row.get::<Option<u32>, &str>("views_left") {
+            None => Ok(Some(paste_from_row(&row)?)),
+            Some(_) => consume_view(write_pool, id).await,
+        },
+    }
+}
+
+async fn consume_view(pool: &WritePool, id: &str) -> Result<Option<Paste>, IOError> {
+    let mut transaction = pool.0.begin().await?;
+
+    let result = transaction
+        .fetch_optional(
+            sqlx::query(&format!(
+                "UPDATE entries SET views_left = views_left - 1
+                WHERE id = ? AND views_left > 0 AND (expires_at IS NULL OR expires_at > ?)
+                RETURNING {}, views_left",
+                PASTE_COLUMNS
+            ))
+            .bind(id)
+            .bind(now()),
+        )
         .await?;
 
-    Ok(result.iter().map(|row| (row.get(0), row.get(1))).collect())
+    let data = match result {
+        None => None,
+        Some(row) => {
+            if row.get::<u32, &str>("views_left") == 0 {
+                info!("[READ_PASTE] Last view of {}, deleting it", id);
+                transaction
+                    .execute(sqlx::query("DELETE FROM entries WHERE id = ?").bind(id))
+                    .await?;
+            }
+            Some(paste_from_row(&row)?)
+        }
+    };
+
+    transaction.commit().await?;
+    Ok(data)
+}
+
+pub async fn get_paste_meta(pool: &ReadPool, id: &str) -> Result<Option<PasteMeta>, IOError> {
+    let result = sqlx::query(
+        "SELECT id, created_at, updated_at, size, language, mime_type, creator, filename,
+        expires_at, views_left, revision, owner, token_hash, read_password_hash, private
+        FROM entries WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)",
+    )
+    .bind(id)
+    .bind(now())
+    .fetch_optional(&pool.0)
+    .await?;
+
+    Ok(result.map(|row| PasteMeta {
+        id: row.get(0),
+        created_at: row.get(1),
+        updated_at: row.get(2),
+        size: row.get(3),
+        language: row.get(4),
+        mime_type: row.get(5),
+        creator: row.get(6),
+        filename: row.get(7),
+        expires_at: row.get(8),
+        views_left: row.get(9),
+        revision: row.get(10),
+        owner: row.get(11),
+        token_hash: row.get(12),
+        read_password_hash: row.get(13),
+        private: row.get(14),
+    }))
+}
+
+/// Revisions of a paste, the current one last. None if the paste does not exist.
+pub async fn get_history(pool: &ReadPool, id: &str) -> Result<Option<Vec<Revision>>, IOError> {
+    let current = sqlx::query(
+        "SELECT revision, COALESCE(updated_at, created_at) AS created_at, size, language,
+        mime_type, creator, filename
+        FROM entries WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)",
+    )
+    .bind(id)
+    .bind(now())
+    .fetch_optional(&pool.0)
+    .await?;
+    let current = match current {
+        None => return Ok(None),
+        Some(current) => current,
+    };
+
+    let mut history: Vec<Revision> = sqlx::query(
+        "SELECT revision, created_at, size, language, mime_type, creator, filename
+        FROM revisions WHERE id = ? ORDER BY revision",
+    )
+    .bind(id)
+    .fetch_all(&pool.0)
+    .await?
+    .iter()
+    .map(revision_from_row)
+    .collect();
+    history.push(revision_from_row(&current));
+    Ok(Some(history))
+}
+
+fn revision_from_row(row: &sqlx::sqlite::SqliteRow) -> Revision {
+    Revision {
+        revision: row.get("revision"),
+        created_at: row.get("created_at"),
+        size: row.get("size"),
+        language: row.get("language"),
+        mime_type: row.get("mime_type"),
+        creator: row.get("creator"),
+        filename: row.get("filename"),
+    }
+}
+
+/// Content of a previous revision of a paste. The current revision is not included: it is read
+/// with read_paste, which counts the views.
+pub async fn get_revision(
+    pool: &ReadPool,
+    id: &str,
+    revision: u32,
+) -> Result<Option<Paste>, IOError> {
+    let result = sqlx::query(
+        "SELECT CAST(contents.data AS BLOB) AS data, contents.codec AS codec,
+        revisions.language AS language, revisions.mime_type AS mime_type,
+        revisions.filename AS filename
+        FROM revisions
+        JOIN contents ON contents.hash = revisions.content_hash
+        JOIN entries ON entries.id = revisions.id
+        WHERE revisions.id = ? AND revisions.revision = ?
+        AND (entries.expires_at IS NULL OR entries.expires_at > ?)",
+    )
+    .bind(id)
+    .bind(revision)
+    .bind(now())
+    .fetch_optional(&pool.0)
+    .await?;
+
+    result.map(|row| paste_from_row(&row)).transpose()
+}
+
+/// Compress the pastes stored as-is, such as the ones created before bibin compressed them, then
+/// reclaim the space they freed in the database file.
+///
+/// Returns the number of pastes compressed and the number of bytes saved.
+pub async fn recompress(pool: &WritePool) -> Result<(u64, u64), IOError> {
+    const BATCH_SIZE: i64 = 100;
+    let mut compressed = 0;
+    let mut saved = 0;
+
+    // Entries stored before the deduplication have their data inline
+    for table in ["contents", "entries"] {
+        let mut last_id: i64 = 0;
+        loop {
+            let rows = sqlx::query(&format!(
+                "SELECT rowid, CAST(data AS BLOB) FROM {}
+                WHERE codec IS NULL AND rowid > ? AND length(CAST(data AS BLOB)) >= ?
+                ORDER BY rowid LIMIT ?",
+                table
+            ))
+            .bind(last_id)
+            .bind(COMPRESSION_THRESHOLD as i64)
+            .bind(BATCH_SIZE)
+            .fetch_all(&pool.0)
+            .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in rows {
+                last_id = row.get(0);
+                let data: Vec<u8> = row.get(1);
+                if let (Cow::Owned(stored), Some(codec)) = encode(&data)? {
+                    sqlx::query(&format!(
+                        "UPDATE {} SET data = ?, codec = ? WHERE rowid = ?",
+                        table
+                    ))
+                    .bind(&stored)
+                    .bind(codec)
+                    .bind(last_id)
+                    .execute(&pool.0)
+                    .await?;
+                    compressed += 1;
+                    saved += (data.len() - stored.len()) as u64;
+                }
+            }
+        }
+    }
+
+    if compressed > 0 {
+        pool.0.execute("VACUUM").await?;
+    }
+    Ok((compressed, saved))
+}
+
+/// Get the id and content of all the pastes, except the private ones and those guarded by a view
+/// limit or a read password. Binary content is converted lossily to text.
+pub async fn get_all_paste(pool: &ReadPool) -> Result<Vec<(String, String)>, IOError> {
+    let result = sqlx::query(&format!(
+        "SELECT id, {} FROM entries WHERE (expires_at IS NULL OR expires_at > ?) AND NOT private
+        AND views_left IS NULL AND read_password_hash IS NULL",
+        PASTE_COLUMNS
+    ))
+    .bind(now())
+    .fetch_all(&pool.0)
+    .await?;
+
+    result
+        .iter()
+        .map(|row| {
+            let paste = paste_from_row(row)?;
+            Ok((
+                row.get("id"),
+                String::from_utf8_lossy(&paste.data).into_owned(),
+            ))
+        })
+        .collect()
+}
+
+/// Number of characters of the content shown in the listings
+const PREVIEW_LENGTH: usize = 100;
+
+/// First characters of a text content, None for binary content
+pub fn preview(data: &[u8]) -> Option<String> {
+    std::str::from_utf8(data)
+        .ok()
+        .map(|text| text.chars().take(PREVIEW_LENGTH).collect())
+}
+
+/// A paste in the listings: its metadata and the beginning of its content
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PasteSummary {
+    pub id: String,
+    pub created_at: Option<i64>,
+    pub size: i64,
+    pub language: Option<String>,
+    /// First characters of the content, None for binary content and for the pastes whose content
+    /// is guarded by a view limit or a read password
+    pub preview: Option<String>,
+}
+
+/// Position in the listings, which are ordered by creation date then id, most recent first.
+/// Pastes created before the creation date was recorded are listed last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: i64,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn of(summary: &PasteSummary) -> Cursor {
+        Cursor {
+            created_at: summary.created_at.unwrap_or(0),
+            id: summary.id.clone(),
+        }
+    }
+
+    /// Whether a paste created at created_at with this id is listed after the cursor
+    pub fn is_before(&self, created_at: Option<i64>, id: &str) -> bool {
+        (created_at.unwrap_or(0), id) < (self.created_at, self.id.as_str())
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "{}:{}", self.created_at, self.id)
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = IOError;
+
+    fn from_str(cursor: &str) -> Result<Cursor, IOError> {
+        cursor
+            .split_once(':')
+            .and_then(|(created_at, id)| {
+                Some(Cursor {
+                    created_at: created_at.parse().ok()?,
+                    id: id.to_owned(),
+                })
+            })
+            .ok_or_else(|| IOError(format!("Invalid cursor: {}", cursor)))
+    }
+}
+
+/// Selection of the pastes for a page of the listings
+#[derive(Clone, Debug, Default)]
+pub struct ListFilter {
+    pub limit: u32,
+    /// Only the pastes after this one
+    pub cursor: Option<Cursor>,
+    /// Only the pastes created at or after this timestamp
+    pub since: Option<i64>,
+    pub language: Option<String>,
+}
+
+impl ListFilter {
+    pub fn matches(&self, meta: &PasteMeta) -> bool {
+        self.cursor
+            .as_ref()
+            .is_none_or(|cursor| cursor.is_before(meta.created_at, &meta.id))
+            && self
+                .since
+                .is_none_or(|since| meta.created_at.unwrap_or(0) >= since)
+            && self
+                .language
+                .as_ref()
+                .is_none_or(|language| meta.language.as_ref() == Some(language))
+    }
+}
+
+/// A page of the listings, without the content of the pastes nor the private pastes. The preview
+/// is left out for the guarded pastes.
+pub async fn list_pastes(
+    pool: &ReadPool,
+    filter: &ListFilter,
+) -> Result<Vec<PasteSummary>, IOError> {
+    let cursor = filter.cursor.as_ref();
+    let rows = sqlx::query(&format!(
+        "SELECT entries.id, created_at, size,
+        views_left IS NULL AND read_password_hash IS NULL AS shown, {}
+        FROM entries
+        WHERE (expires_at IS NULL OR expires_at > ?1) AND NOT private
+        AND (?2 IS NULL OR (COALESCE(created_at, 0), entries.id) < (?2, ?3))
+        AND (?4 IS NULL OR COALESCE(created_at, 0) >= ?4)
+        AND (?5 IS NULL OR language = ?5)
+        ORDER BY COALESCE(created_at, 0) DESC, entries.id DESC
+        LIMIT ?6",
+        PASTE_COLUMNS
+    ))
+    .bind(now())
+    .bind(cursor.map(|cursor| cursor.created_at))
+    .bind(cursor.map(|cursor| cursor.id.as_str()))
+    .bind(filter.since)
+    .bind(filter.language.as_deref())
+    .bind(filter.limit)
+    .fetch_all(&pool.0)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            let preview = match row.get("shown") {
+                true => preview(&paste_from_row(row)?.data),
+                false => None,
+            };
+            Ok(PasteSummary {
+                id: row.get("id"),
+                created_at: row.get("created_at"),
+                size: row.get("size"),
+                language: row.get("language"),
+                preview,
+            })
+        })
+        .collect()
+}
+
+/// What a share link opens: the page of the paste, its raw content or its content as a QR code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareMode {
+    #[default]
+    Page,
+    Raw,
+    Qr,
+}
+
+/// A link opening a paste to anyone holding it, until it expires, runs out of views or is
+/// revoked. Its signed form is in the URL, the store keeps its views left.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ShareLink {
+    /// Random id of the link, to revoke it
+    pub id: String,
+    pub paste_id: String,
+    pub mode: ShareMode,
+    /// Unix timestamp after which the link does not open the paste anymore
+    pub expires_at: i64,
+    /// Number of views through the link, unlimited if None
+    pub max_views: Option<u32>,
+}
+
+pub async fn add_link(pool: &WritePool, link: &ShareLink) -> Result<(), IOError> {
+    sqlx::query(
+        "INSERT INTO share_links (id, paste_id, expires_at, views_left) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&link.id)
+    .bind(&link.paste_id)
+    .bind(link.expires_at)
+    .bind(link.max_views)
+    .execute(&pool.0)
+    .await?;
+    Ok(())
+}
+
+/// Count a view through a share link of a paste. Returns false if it was revoked, has expired
+/// or has no views left.
+pub async fn use_link(pool: &WritePool, paste_id: &str, id: &str) -> Result<bool, IOError> {
+    let result = sqlx::query(
+        "UPDATE share_links SET views_left = views_left - 1
+        WHERE id = ? AND paste_id = ? AND expires_at > ?
+        AND (views_left IS NULL OR views_left > 0)",
+    )
+    .bind(id)
+    .bind(paste_id)
+    .bind(now())
+    .execute(&pool.0)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Revoke a share link of a paste. Returns false if there was none.
+pub async fn revoke_link(pool: &WritePool, paste_id: &str, id: &str) -> Result<bool, IOError> {
+    let result = sqlx::query("DELETE FROM share_links WHERE id = ? AND paste_id = ?")
+        .bind(id)
+        .bind(paste_id)
+        .execute(&pool.0)
+        .await?;
+    Ok(result.rows_affected() == 1)
 }
 
 #[derive(Debug)]
-pub struct IOError(String);
+pub struct IOError(pub String);
 
 impl Display for IOError {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
@@ -227,9 +1214,16 @@ impl From<sqlx::error::Error> for IOError {
     }
 }
 
+impl From<std::io::Error> for IOError {
+    fn from(e: std::io::Error) -> IOError {
+        IOError(format!("Filesystem Error: {}", e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{thread_rng, Rng};
 
     #[async_test]
     async fn simple_workflow() {
@@ -245,17 +1239,709 @@ mod tests {
 
         let data = String::from("SOME_DATA");
 
-        let id = store_paste(&write_pool, 4, 2048, data.clone())
-            .await
-            .unwrap();
-        assert_eq!(get_paste(&read_pool, &id).await.unwrap().unwrap(), data);
+        let id = store_paste(
+            &write_pool,
+            4,
+            2048,
+            data.clone().into_bytes(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_paste(&read_pool, &id).await.unwrap().unwrap().text(),
+            Some(data.as_str())
+        );
         assert_eq!(
             get_all_paste(&read_pool).await.unwrap(),
             vec![(id.clone(), data.clone())]
         );
-        assert_eq!(delete_paste(&write_pool, &id).await.unwrap(), id);
-        assert!(delete_paste(&write_pool, &id).await.is_err());
+        assert!(delete_paste(&write_pool, &id).await.unwrap());
+        assert!(!delete_paste(&write_pool, &id).await.unwrap());
         assert!(get_paste(&read_pool, &id).await.unwrap().is_none());
         assert_eq!(get_all_paste(&read_pool).await.unwrap(), vec![]);
     }
+
+    #[async_test]
+    async fn max_entries() {
+        let uri_shared_memory = "file:max_entries?mode=memory&cache=shared";
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        write_pool.init().await.unwrap();
+        let read_pool = ReadPool::new(uri_shared_memory, 1).await.unwrap();
+
+        let mut ids = Vec::new();
+        for data in ["first", "second", "third"].iter() {
+            let id = store_paste(
+                &write_pool,
+                4,
+                2,
+                data.as_bytes().to_vec(),
+                &Default::default(),
+            )
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+        // The oldest one makes room for the new one, which is always kept
+        assert!(get_paste(&read_pool, &ids[0]).await.unwrap().is_none());
+        assert!(get_paste(&read_pool, &ids[1]).await.unwrap().is_some());
+        assert!(get_paste(&read_pool, &ids[2]).await.unwrap().is_some());
+        assert_eq!(id_usage(&read_pool).await.unwrap().total(), 2);
+    }
+
+    #[async_test]
+    async fn expiration() {
+        let uri_shared_memory = "file:expiration?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let options = PasteOptions {
+            ttl: Some(Ttl(Duration::from_secs(3600))),
+            ..Default::default()
+        };
+        let alive = store_paste(&write_pool, 4, 2048, b"alive".to_vec(), &options)
+            .await
+            .unwrap();
+        let options = PasteOptions {
+            ttl: Some(Ttl(Duration::ZERO)),
+            ..Default::default()
+        };
+        let expired = store_paste_given_id(
+            &write_pool,
+            "expired".to_owned(),
+            b"expired".to_vec(),
+            &options,
+            0,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            get_paste(&read_pool, &alive).await.unwrap().unwrap().text(),
+            Some("alive")
+        );
+        // Expired entries are hidden even before the janitor removes them
+        assert!(get_paste(&read_pool, &expired).await.unwrap().is_none());
+        assert_eq!(
+            get_all_paste(&read_pool).await.unwrap(),
+            vec![(alive.clone(), "alive".to_owned())]
+        );
+
+        assert_eq!(purge_expired(&write_pool).await.unwrap(), 1);
+        assert_eq!(purge_expired(&write_pool).await.unwrap(), 0);
+        assert!(!delete_paste(&write_pool, &expired).await.unwrap());
+        assert!(delete_paste(&write_pool, &alive).await.unwrap());
+    }
+
+    #[async_test]
+    async fn view_limit() {
+        let uri_shared_memory = "file:view_limit?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let unlimited = store_paste(
+            &write_pool,
+            4,
+            2048,
+            b"unlimited".to_vec(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+        let options = PasteOptions {
+            max_views: Some(3),
+            ..Default::default()
+        };
+        let limited = store_paste(&write_pool, 4, 2048, b"limited".to_vec(), &options)
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            assert!(read_paste(&read_pool, &write_pool, &unlimited)
+                .await
+                .unwrap()
+                .is_some());
+        }
+
+        // Peeking at the content does not count as a view
+        assert!(get_paste(&read_pool, &limited).await.unwrap().is_some());
+
+        // Concurrent readers share the 3 views
+        let readers = (0..10).map(|_| read_paste(&read_pool, &write_pool, &limited));
+        let results = rocket::futures::future::join_all(readers).await;
+        let served = results
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(Option::is_some)
+            .count();
+        assert_eq!(served, 3);
+
+        // The entry is gone after its last view
+        assert!(get_paste(&read_pool, &limited).await.unwrap().is_none());
+        assert!(!delete_paste(&write_pool, &limited).await.unwrap());
+    }
+
+    #[async_test]
+    async fn metadata() {
+        let uri_shared_memory = "file:metadata?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let options = PasteOptions {
+            language: Some("rs".to_owned()),
+            mime_type: Some("text/plain".to_owned()),
+            creator: Some("ci".to_owned()),
+            filename: Some("main.rs".to_owned()),
+            ..Default::default()
+        };
+        let id = store_paste(&write_pool, 4, 2048, b"fn main() {}".to_vec(), &options)
+            .await
+            .unwrap();
+
+        let meta = get_paste_meta(&read_pool, &id).await.unwrap().unwrap();
+        assert_eq!(meta.id, id);
+        assert_eq!(meta.size, 12);
+        assert_eq!(meta.language.as_deref(), Some("rs"));
+        assert_eq!(meta.mime_type.as_deref(), Some("text/plain"));
+        assert_eq!(meta.creator.as_deref(), Some("ci"));
+        assert_eq!(meta.filename.as_deref(), Some("main.rs"));
+        assert!(meta.created_at.is_some());
+        assert_eq!(meta.created_at, meta.updated_at);
+        assert_eq!(meta.expires_at, None);
+        assert_eq!(meta.views_left, None);
+
+        // Overwriting a paste keeps its creation date
+        sqlx::query("UPDATE entries SET created_at = 1000 WHERE id = ?")
+            .bind(&id)
+            .execute(&write_pool.0)
+            .await
+            .unwrap();
+        store_paste_given_id(
+            &write_pool,
+            id.clone(),
+            "€".as_bytes().to_vec(),
+            &PasteOptions::default(),
+            0,
+        )
+        .await
+        .unwrap();
+        let meta = get_paste_meta(&read_pool, &id).await.unwrap().unwrap();
+        assert_eq!(meta.created_at, Some(1000));
+        assert!(meta.updated_at > Some(1000));
+        assert_eq!(meta.size, 3);
+        assert_eq!(meta.language, None);
+
+        assert!(get_paste_meta(&read_pool, "none").await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn binary_content() {
+        let uri_shared_memory = "file:binary_content?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let png = b"\x89PNG\r\n\x1a\n\x00\xff".to_vec();
+        let options = PasteOptions {
+            mime_type: Some("image/png".to_owned()),
+            ..Default::default()
+        };
+        let binary = store_paste(&write_pool, 4, 2048, png.clone(), &options)
+            .await
+            .unwrap();
+        let text = store_paste(
+            &write_pool,
+            4,
+            2048,
+            b"text".to_vec(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let paste = get_paste(&read_pool, &binary).await.unwrap().unwrap();
+        assert_eq!(paste.data, png);
+        assert_eq!(paste.text(), None);
+        assert_eq!(paste.mime_type.as_deref(), Some("image/png"));
+        let paste = get_paste(&read_pool, &text).await.unwrap().unwrap();
+        assert_eq!(paste.text(), Some("text"));
+
+        let types: Vec<String> = sqlx::query(
+            "SELECT typeof(contents.data) FROM entries
+                JOIN contents ON contents.hash = entries.content_hash ORDER BY internal_id",
+        )
+        .fetch_all(&read_pool.0)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+        assert_eq!(types, vec!["blob", "text"]);
+
+        assert_eq!(
+            get_paste_meta(&read_pool, &binary)
+                .await
+                .unwrap()
+                .unwrap()
+                .size,
+            png.len() as i64
+        );
+    }
+
+    #[async_test]
+    async fn compression() {
+        let uri_shared_memory = "file:compression?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let log = "2024-01-01 12:00:00 INFO everything is fine\n".repeat(1000);
+        let large = store_paste(
+            &write_pool,
+            4,
+            2048,
+            log.clone().into_bytes(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+        let small = store_paste(
+            &write_pool,
+            4,
+            2048,
+            b"https://example.com".to_vec(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+        // Random data does not compress, it is kept as-is
+        let random: Vec<u8> = (0..COMPRESSION_THRESHOLD * 2)
+            .map(|_| thread_rng().gen())
+            .collect();
+        let incompressible = store_paste_given_id(
+            &write_pool,
+            "random".to_owned(),
+            random.clone(),
+            &PasteOptions::default(),
+            0,
+        )
+        .await
+        .unwrap();
+
+        let stored = |id: String| {
+            let read_pool = &read_pool;
+            async move {
+                let row = sqlx::query(
+                    "SELECT COALESCE(contents.codec, entries.codec),
+                    COALESCE(length(CAST(contents.data AS BLOB)), length(CAST(entries.data AS BLOB))),
+                    size
+                    FROM entries LEFT JOIN contents ON contents.hash = entries.content_hash
+                    WHERE id = ?",
+                )
+                .bind(id)
+                .fetch_one(&read_pool.0)
+                .await
+                .unwrap();
+                (
+                    row.get::<Option<String>, usize>(0),
+                    row.get::<i64, usize>(1),
+                    row.get::<i64, usize>(2),
+                )
+            }
+        };
+        let (codec, length, size) = stored(large.clone()).await;
+        assert_eq!(codec.as_deref(), Some("zstd"));
+        assert!(length * 10 < size, "{} {}", length, size);
+        assert_eq!(size, log.len() as i64);
+        assert_eq!(stored(small.clone()).await.0, None);
+        assert_eq!(stored(incompressible.clone()).await.0, None);
+
+        // Decompression is transparent
+        assert_eq!(
+            get_paste(&read_pool, &large).await.unwrap().unwrap().text(),
+            Some(log.as_str())
+        );
+        assert_eq!(
+            read_paste(&read_pool, &write_pool, &large)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some(log.as_str())
+        );
+        assert_eq!(
+            get_paste(&read_pool, &incompressible)
+                .await
+                .unwrap()
+                .unwrap()
+                .data,
+            random
+        );
+        assert!(get_all_paste(&read_pool)
+            .await
+            .unwrap()
+            .contains(&(large.clone(), log.clone())));
+
+        // Pastes stored before the compression are compressed by recompress
+        sqlx::query("UPDATE entries SET data = ?, codec = NULL, content_hash = NULL WHERE id = ?")
+            .bind(&log)
+            .bind(&large)
+            .execute(&write_pool.0)
+            .await
+            .unwrap();
+        assert_eq!(stored(large.clone()).await.0, None);
+        let (compressed, saved) = recompress(&write_pool).await.unwrap();
+        assert_eq!(compressed, 1);
+        assert!(saved > 0);
+        assert_eq!(stored(large.clone()).await.0.as_deref(), Some("zstd"));
+        assert_eq!(
+            get_paste(&read_pool, &large).await.unwrap().unwrap().text(),
+            Some(log.as_str())
+        );
+        assert_eq!(recompress(&write_pool).await.unwrap(), (0, 0));
+    }
+
+    #[async_test]
+    async fn deduplication() {
+        let uri_shared_memory = "file:deduplication?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let contents = || async {
+            sqlx::query("SELECT refcount FROM contents ORDER BY refcount")
+                .fetch_all(&read_pool.0)
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| row.get::<i64, usize>(0))
+                .collect::<Vec<_>>()
+        };
+
+        let options = PasteOptions {
+            creator: Some("ci".to_owned()),
+            ..Default::default()
+        };
+        let first = store_paste(&write_pool, 4, 2048, b"trace".to_vec(), &options)
+            .await
+            .unwrap();
+        let second = store_paste(&write_pool, 4, 2048, b"trace".to_vec(), &options)
+            .await
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(contents().await, vec![2]);
+        assert_eq!(
+            get_paste(&read_pool, &second)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("trace")
+        );
+
+        // Replacing a paste releases its previous content
+        store_paste_given_id(&write_pool, second.clone(), b"other".to_vec(), &options, 0)
+            .await
+            .unwrap();
+        assert_eq!(contents().await, vec![1, 1]);
+        store_paste_given_id(&write_pool, second.clone(), b"trace".to_vec(), &options, 0)
+            .await
+            .unwrap();
+        assert_eq!(contents().await, vec![2]);
+
+        // The content is deleted with the last paste referencing it
+        assert!(delete_paste(&write_pool, &first).await.unwrap());
+        assert_eq!(contents().await, vec![1]);
+        assert_eq!(
+            get_paste(&read_pool, &second)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("trace")
+        );
+        let burnt = PasteOptions {
+            max_views: Some(1),
+            ..Default::default()
+        };
+        let burnt = store_paste(&write_pool, 4, 2048, b"trace".to_vec(), &burnt)
+            .await
+            .unwrap();
+        assert_eq!(contents().await, vec![2]);
+        assert!(read_paste(&read_pool, &write_pool, &burnt)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(contents().await, vec![1]);
+        assert!(delete_paste(&write_pool, &second).await.unwrap());
+        assert_eq!(contents().await, Vec::<i64>::new());
+    }
+
+    #[async_test]
+    async fn revisions() {
+        let uri_shared_memory = "file:revisions?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let options = PasteOptions::default();
+        let id = "notes".to_owned();
+        for content in ["first", "second", "third"] {
+            store_paste_given_id(&write_pool, id.clone(), content.into(), &options, 2)
+                .await
+                .unwrap();
+        }
+        let text = |revision| {
+            let read_pool = &read_pool;
+            async move {
+                get_revision(read_pool, "notes", revision)
+                    .await
+                    .unwrap()
+                    .map(|paste| paste.text().unwrap().to_owned())
+            }
+        };
+        assert_eq!(text(1).await.as_deref(), Some("first"));
+        assert_eq!(text(2).await.as_deref(), Some("second"));
+        // The current revision is read with read_paste
+        assert_eq!(text(3).await, None);
+        let meta = get_paste_meta(&read_pool, &id).await.unwrap().unwrap();
+        assert_eq!(meta.revision, 3);
+        let history = get_history(&read_pool, &id).await.unwrap().unwrap();
+        assert_eq!(
+            history.iter().map(|r| r.revision).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(history[0].size, 5);
+
+        // Only the last max_revisions are kept
+        store_paste_given_id(&write_pool, id.clone(), b"fourth".to_vec(), &options, 2)
+            .await
+            .unwrap();
+        assert_eq!(text(1).await, None);
+        assert_eq!(text(3).await.as_deref(), Some("third"));
+
+        // Content stored before the deduplication is moved to the contents table
+        let legacy = "legacy".to_owned();
+        store_paste_given_id(&write_pool, legacy.clone(), b"old".to_vec(), &options, 2)
+            .await
+            .unwrap();
+        sqlx::query(
+            "DELETE FROM contents WHERE hash = (SELECT content_hash FROM entries WHERE id = ?)",
+        )
+        .bind(&legacy)
+        .execute(&write_pool.0)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE entries SET data = 'old', content_hash = NULL WHERE id = ?")
+            .bind(&legacy)
+            .execute(&write_pool.0)
+            .await
+            .unwrap();
+        store_paste_given_id(&write_pool, legacy.clone(), b"new".to_vec(), &options, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_revision(&read_pool, &legacy, 1)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("old")
+        );
+
+        // Deleting a paste deletes its revisions and releases their content
+        assert!(delete_paste(&write_pool, &id).await.unwrap());
+        assert!(delete_paste(&write_pool, &legacy).await.unwrap());
+        assert!(get_history(&read_pool, &id).await.unwrap().is_none());
+        let left: i64 = sqlx::query("SELECT COUNT(*) FROM revisions")
+            .fetch_one(&read_pool.0)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(left, 0);
+        let left: i64 = sqlx::query("SELECT COUNT(*) FROM contents")
+            .fetch_one(&read_pool.0)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(left, 0);
+    }
+
+    #[async_test]
+    async fn search_index() {
+        let uri_shared_memory = "file:search_index?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let ids = |query: &'static str| {
+            let read_pool = &read_pool;
+            async move {
+                search(read_pool, query, 10)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|result| result.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let options = PasteOptions::default();
+        let log = store_paste(
+            &write_pool,
+            4,
+            2048,
+            b"error: connection refused <localhost:5432>".to_vec(),
+            &options,
+        )
+        .await
+        .unwrap();
+        store_paste(&write_pool, 4, 2048, b"\x00\xffbinary".to_vec(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(ids("refused").await, vec![log.clone()]);
+        // Any fragment, whatever the case
+        assert_eq!(ids("NNECTION").await, vec![log.clone()]);
+        assert_eq!(ids("refused connection").await, vec![log.clone()]);
+        assert!(ids("refused other").await.is_empty());
+        assert!(ids("binary").await.is_empty());
+        // The query syntax of FTS5 is not interpreted
+        assert_eq!(ids("<localhost:5432>").await, vec![log.clone()]);
+        assert!(ids("\"refused OR").await.is_empty());
+        assert!(ids("  ").await.is_empty());
+
+        let results = search(&read_pool, "localhost", 10).await.unwrap();
+        assert_eq!(
+            results[0].snippet,
+            "error: connection refused &lt;<mark>localhost</mark>:5432&gt;"
+        );
+
+        // Overwritten, expired and deleted entries are not found anymore
+        store_paste_given_id(&write_pool, log.clone(), b"fixed".to_vec(), &options, 10)
+            .await
+            .unwrap();
+        assert!(ids("refused").await.is_empty());
+        assert_eq!(ids("fixed").await, vec![log.clone()]);
+        let expired = PasteOptions {
+            ttl: Some(Ttl(Duration::ZERO)),
+            ..Default::default()
+        };
+        store_paste(&write_pool, 4, 2048, b"fixed too".to_vec(), &expired)
+            .await
+            .unwrap();
+        assert_eq!(ids("fixed").await, vec![log.clone()]);
+        assert!(delete_paste(&write_pool, &log).await.unwrap());
+        assert!(ids("fixed").await.is_empty());
+
+        // The index can be rebuilt from the compressed content
+        let large = "a compressed paste ".repeat(COMPRESSION_THRESHOLD);
+        let id = store_paste(&write_pool, 4, 2048, large.into_bytes(), &options)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM entries_search")
+            .execute(&write_pool.0)
+            .await
+            .unwrap();
+        assert!(ids("compressed").await.is_empty());
+        assert_eq!(rebuild_search_index(&write_pool).await.unwrap(), 3);
+        assert_eq!(ids("compressed").await, vec![id]);
+        let results = search(&read_pool, "COMPRESSED", 10).await.unwrap();
+        assert!(results[0]
+            .snippet
+            .starts_with("a <mark>compressed</mark> paste a"));
+
+        // The index does not keep a copy of the content
+        let stored: Option<String> = sqlx::query("SELECT content FROM entries_search")
+            .fetch_one(&read_pool.0)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(stored, None);
+    }
+
+    #[async_test]
+    async fn duplicates() {
+        let uri_shared_memory = "file:duplicates?mode=memory&cache=shared";
+
+        let write_pool = WritePool::new(uri_shared_memory).await.unwrap();
+        assert!(write_pool.init().await.is_ok());
+
+        let read_pool = ReadPool::new(uri_shared_memory, 10).await.unwrap();
+
+        let options = PasteOptions {
+            creator: Some("ci".to_owned()),
+            ttl: Some(Ttl(Duration::from_secs(3600))),
+            ..Default::default()
+        };
+        let id = store_paste(&write_pool, 4, 2048, b"config".to_vec(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            find_duplicate(&read_pool, b"config", &options)
+                .await
+                .unwrap(),
+            Some(id.clone())
+        );
+        let shorter = PasteOptions {
+            ttl: Some(Ttl(Duration::from_secs(60))),
+            ..options.clone()
+        };
+        assert_eq!(
+            find_duplicate(&read_pool, b"config", &shorter)
+                .await
+                .unwrap(),
+            Some(id.clone())
+        );
+
+        // Whoever uploads it
+        let anonymous = PasteOptions {
+            creator: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            find_duplicate(&read_pool, b"config", &anonymous)
+                .await
+                .unwrap(),
+            Some(id.clone())
+        );
+
+        // Not for another content, or a paste that would expire too soon
+        assert_eq!(
+            find_duplicate(&read_pool, b"other", &options)
+                .await
+                .unwrap(),
+            None
+        );
+        let forever = PasteOptions {
+            ttl: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            find_duplicate(&read_pool, b"config", &forever)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+/// Languages are file extensions given to the highlighter. They end up in the URL of the paste,
+/// so we only accept a conservative set of characters.
+pub fn is_valid_language(language: &str) -> bool {
+    !language.is_empty()
+        && language.len() <= 32
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '_')
+}
+
+/// Language of a paste declared by an API client with the `X-Language` header.
+///
+/// A missing header is not an error, an invalid one is rejected with a 400.
+pub struct LanguageHeader(pub Option<String>);
+
+#[derive(Debug)]
+pub struct LanguageError;
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for LanguageHeader {
+    type Error = LanguageError;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Language") {
+            None => Outcome::Success(LanguageHeader(None)),
+            Some(language) if is_valid_language(language) => {
+                Outcome::Success(LanguageHeader(Some(language.to_owned())))
+            }
+            Some(language) => {
+                warn!("[LANGUAGE] Invalid language {}", language);
+                Outcome::Error((Status::BadRequest, LanguageError))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_language;
+
+    #[test]
+    fn valid_languages() {
+        for language in ["rs", "c++", "tar-gz", "py3", "some_ext"] {
+            assert!(is_valid_language(language), "{}", language);
+        }
+        for language in ["", "a b", "c#", "../etc", "a.b", "ü", &"a".repeat(33)] {
+            assert!(!is_valid_language(language), "{}", language);
+        }
+    }
+}
@@ -4,20 +4,42 @@ extern crate rocket;
 #[macro_use]
 extern crate log;
 
+mod api;
 mod auth;
 mod config;
 mod get;
 mod highlight;
+mod ids;
 mod io;
 mod isplaintextrequest;
+mod language;
+mod migrations;
+mod mimetype;
+mod openapi;
+mod private;
+mod readpassword;
+mod share;
+mod store;
+mod tailscale;
+mod ttl;
+mod viewlimit;
+mod wantsjson;
 mod write;
 
 use auth::AuthKey;
-use config::BibinConfig;
+use config::{BibinConfig, Storage};
 use highlight::Highlighter;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::form;
+use rocket::fs::TempFile;
 use rocket::response::Redirect;
+use share::ShareKey;
+use std::time::Duration;
+use tailscale::LocalApi;
+use ttl::Ttl;
 
-use io::{ReadPool, WritePool};
+use io::WritePool;
 
 #[derive(Responder)]
 pub enum HtmlOrPlain {
@@ -26,6 +48,9 @@ pub enum HtmlOrPlain {
 
     #[response(content_type = "plain")]
     Plain(String),
+
+    #[response(content_type = "json")]
+    Json(String),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -41,67 +66,200 @@ pub enum RedirectOrContent {
 
     #[response(content_type = "plain")]
     Plain(String),
+
+    #[response(content_type = "json")]
+    Json(String),
+
+    /// Content served with the type given by its author
+    Binary(mimetype::RawContent),
+
+    PasswordRequired(readpassword::PasswordRequired),
 }
 
-#[derive(FromForm, Clone)]
-pub struct IndexForm {
+#[derive(FromForm)]
+pub struct IndexForm<'r> {
+    #[field(default = String::new())]
     val: String,
-    password: AuthKey,
+    /// Uploaded file, replaces `val` when present. Not an `Option` to be able to tell a missing
+    /// file from a file larger than the upload limit
+    file: form::Result<'r, TempFile<'r>>,
+    /// Optional for the tailnet members behind `tailscale serve`
+    password: Option<AuthKey>,
+    expire: Option<Ttl>,
+    burn: bool,
+    views: Option<u32>,
+    lang: Option<String>,
+    /// Password needed to read the paste, empty for a public paste
+    read_password: Option<String>,
+    /// Unchecked boxes are not sent: the default of the key applies then
+    private: Option<bool>,
 }
 
-#[rocket::launch]
-async fn rocket() -> rocket::Rocket<rocket::Build> {
-    let highlighter = Highlighter::new();
-
-    let rkt = rocket::Rocket::build();
+#[rocket::main]
+async fn main() {
+    let command = std::env::args().nth(1);
+    match command.as_deref() {
+        None => {
+            // Errors are reported by Rocket when they are dropped
+            let _ = rocket().await.launch().await;
+        }
+        Some("recompress") => recompress().await,
+        Some("hash-password") => hash_password(),
+        Some(command) => {
+            eprintln!(
+                "Unknown command {}. Usage: bibin [recompress|hash-password]",
+                command
+            );
+            std::process::exit(1);
+        }
+    }
+}
 
-    // I would like to use the ADHoc helpers instead, but I need to configure the database before
-    // starting rocket. I prefer to not register Pools that are in a non-working state, and then
-    // read the config and init them.
-    // With the current system the pools are either created and working or don't exist.
-    let config = match rkt.figment().extract::<BibinConfig>() {
+fn read_config(figment: &Figment) -> BibinConfig {
+    match figment.extract::<BibinConfig>() {
         Err(e) => {
             rocket::config::pretty_print_error(e);
             panic!("Configuration error");
         }
         Ok(config) => config,
-    };
+    }
+}
+
+/// Print the password_hash of the password read on the standard input
+fn hash_password() {
+    eprint!("Password: ");
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("Error when reading the password: {}", e);
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("The password is empty");
+        std::process::exit(1);
+    }
+    match auth::hash_password(password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("Error when hashing the password: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compress the pastes of the database stored before the compression was introduced
+async fn recompress() {
+    let config = read_config(&rocket::Config::figment());
+    if config.storage != Storage::Sqlite {
+        eprintln!("Only the sqlite storage compresses the pastes");
+        std::process::exit(1);
+    }
 
     let write_pool = WritePool::new(&config.database_file)
         .await
-        .expect("Error when creating the writing pool");
-
+        .expect("Error when opening the database");
     write_pool
         .init()
         .await
         .expect("Error during initialization");
 
-    let read_pool = ReadPool::new(&config.database_file, config.database_connections)
+    match io::recompress(&write_pool).await {
+        Ok((compressed, saved)) => {
+            println!("Compressed {} pastes, saving {} bytes", compressed, saved)
+        }
+        Err(e) => {
+            eprintln!("Error when compressing the pastes: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Every route of bibin, described in `openapi.rs`
+fn routes() -> Vec<rocket::Route> {
+    routes![
+        get::index,
+        write::submit,
+        write::submit_with_key,
+        write::submit_raw,
+        write::submit_raw_with_key,
+        get::get_item,
+        get::unlock,
+        get::get_qr,
+        get::all_entries,
+        get::get_item_raw,
+        get::get_meta,
+        get::get_history,
+        get::get_diff,
+        get::search,
+        get::list_pastes,
+        get::pastes_page,
+        write::delete,
+        api::create,
+        api::read,
+        api::update,
+        api::delete,
+        api::keyspace,
+        api::create_link,
+        api::revoke_link,
+        openapi::openapi
+    ]
+}
+
+async fn rocket() -> rocket::Rocket<rocket::Build> {
+    let highlighter = Highlighter::new();
+
+    let figment = rocket::Config::figment();
+
+    // I would like to use the ADHoc helpers instead, but I need to configure the storage before
+    // starting rocket. I prefer to not register a store that is in a non-working state, and then
+    // read the config and init it.
+    // With the current system the store is either opened and working or doesn't exist.
+    let config = read_config(&figment);
+
+    let store = store::open(&config)
         .await
-        .expect("Error when creating the reading pool");
+        .expect("Error when opening the storage");
+
+    let janitor_store = store.clone();
+    let janitor_period = Duration::from_secs(config.janitor_interval);
 
-    // 16 is the ID field size in the db
-    if config.id_length > 16 {
-        panic!("The maximum ID size is 16");
+    // The ids are not limited in length since the private pastes, but there is no empty one
+    if config.id_length == 0 {
+        panic!("The minimum ID size is 1");
     }
 
-    rkt.mount(
-        "/",
-        routes![
-            get::index,
-            write::submit,
-            write::submit_with_key,
-            write::submit_raw,
-            write::submit_raw_with_key,
-            get::get_item,
-            get::get_qr,
-            get::all_entries,
-            get::get_item_raw,
-            write::delete
-        ],
-    )
-    .manage(config)
-    .manage(highlighter)
-    .manage(read_pool)
-    .manage(write_pool)
+    let local_api = config.tailscale_socket.as_ref().map(LocalApi::new);
+    let share_key = ShareKey::new(config.secret_key.as_ref());
+
+    let rocket = rocket::custom(config.apply_limits(figment))
+        .mount("/", routes())
+        .register("/", catchers![write::payload_too_large])
+        .attach(AdHoc::try_on_ignite("Password check", |rocket| async {
+            let release = rocket.figment().profile() != rocket::Config::DEBUG_PROFILE;
+            let checked = rocket
+                .state::<BibinConfig>()
+                .map(|c| c.check_secrets(release));
+            match checked {
+                Some(Err(e)) => {
+                    error!("{}", e);
+                    Err(rocket)
+                }
+                _ => Ok(rocket),
+            }
+        }))
+        .attach(AdHoc::on_liftoff("Expired entries janitor", move |_| {
+            Box::pin(async move {
+                rocket::tokio::spawn(store::janitor(janitor_store, janitor_period));
+            })
+        }))
+        .manage(config)
+        .manage(highlighter)
+        .manage(share_key)
+        .manage(store);
+
+    // Without it, the tailnet members are only known from the headers of a trusted proxy
+    match local_api {
+        Some(local_api) => rocket.manage(local_api),
+        None => rocket,
+    }
 }
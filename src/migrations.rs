@@ -0,0 +1,510 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Executor, Row, Sqlite};
+
+use crate::io::{IOError, WritePool};
+
+/// A single change of the database schema
+enum Step {
+    Sql(&'static str),
+}
+
+use Step::Sql;
+
+/// Every version of the schema, in order. The version of a database, stored in its
+/// `user_version` pragma, is the number of migrations applied to it.
+///
+/// Released migrations must never be modified: add a new one instead.
+const MIGRATIONS: &[&[Step]] = &[
+    // 1: Initial schema
+    &[
+        Sql("CREATE TABLE IF NOT EXISTS entries (
+            internal_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            id VARCHAR(16) UNIQUE,
+            data TEXT NOT NULL
+        )"),
+        Sql("CREATE UNIQUE INDEX IF NOT EXISTS idx_id ON entries(id)"),
+    ],
+    // 2: Unix timestamp after which the entry is not served anymore, NULL if it never expires
+    &[
+        Sql("ALTER TABLE entries ADD COLUMN expires_at INTEGER"),
+        Sql("CREATE INDEX IF NOT EXISTS idx_expires_at ON entries(expires_at)"),
+    ],
+    // 3: Number of views before the entry is deleted, NULL if it can be read indefinitely
+    &[Sql("ALTER TABLE entries ADD COLUMN views_left INTEGER")],
+    // 4: Metadata. Timestamps are unknown for the existing entries, but the size can be computed
+    &[
+        Sql("ALTER TABLE entries ADD COLUMN created_at INTEGER"),
+        Sql("ALTER TABLE entries ADD COLUMN updated_at INTEGER"),
+        Sql("ALTER TABLE entries ADD COLUMN size INTEGER NOT NULL DEFAULT 0"),
+        Sql("ALTER TABLE entries ADD COLUMN language TEXT"),
+        Sql("ALTER TABLE entries ADD COLUMN mime_type TEXT"),
+        Sql("ALTER TABLE entries ADD COLUMN creator TEXT"),
+        Sql("UPDATE entries SET size = length(CAST(data AS BLOB))"),
+    ],
+    // 5: Name of the uploaded file
+    &[Sql("ALTER TABLE entries ADD COLUMN filename TEXT")],
+    // 6: Compression of the content, NULL when it is stored as-is
+    &[Sql("ALTER TABLE entries ADD COLUMN codec TEXT")],
+    // 7: Deduplication. New entries reference their content by hash, the existing ones keep their
+    // data inline. The triggers keep count of the entries referencing each content and delete the
+    // contents that are not referenced anymore.
+    &[
+        Sql("CREATE TABLE contents (
+            hash TEXT PRIMARY KEY,
+            data BLOB NOT NULL,
+            codec TEXT,
+            refcount INTEGER NOT NULL DEFAULT 0
+        )"),
+        Sql("ALTER TABLE entries ADD COLUMN content_hash TEXT"),
+        Sql("CREATE INDEX idx_content_hash ON entries(content_hash)"),
+        Sql("CREATE TRIGGER contents_reference AFTER INSERT ON entries
+            WHEN new.content_hash IS NOT NULL
+            BEGIN
+                UPDATE contents SET refcount = refcount + 1 WHERE hash = new.content_hash;
+            END"),
+        Sql("CREATE TRIGGER contents_release AFTER DELETE ON entries
+            WHEN old.content_hash IS NOT NULL
+            BEGIN
+                UPDATE contents SET refcount = refcount - 1 WHERE hash = old.content_hash;
+                DELETE FROM contents WHERE hash = old.content_hash AND refcount <= 0;
+            END"),
+        Sql(
+            "CREATE TRIGGER contents_change AFTER UPDATE OF content_hash ON entries
+            WHEN old.content_hash IS NOT new.content_hash
+            BEGIN
+                UPDATE contents SET refcount = refcount + 1 WHERE hash = new.content_hash;
+                UPDATE contents SET refcount = refcount - 1 WHERE hash = old.content_hash;
+                DELETE FROM contents WHERE hash = old.content_hash AND refcount <= 0;
+            END",
+        ),
+    ],
+    // 8: Revisions. Overwriting an entry increments its revision and moves the previous one to
+    // the revisions table, which also references its content.
+    &[
+        Sql("ALTER TABLE entries ADD COLUMN revision INTEGER NOT NULL DEFAULT 1"),
+        Sql("CREATE TABLE revisions (
+            id TEXT NOT NULL,
+            revision INTEGER NOT NULL,
+            content_hash TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            language TEXT,
+            mime_type TEXT,
+            creator TEXT,
+            filename TEXT,
+            created_at INTEGER,
+            PRIMARY KEY (id, revision)
+        )"),
+        Sql(
+            "CREATE TRIGGER revisions_reference AFTER INSERT ON revisions
+            BEGIN
+                UPDATE contents SET refcount = refcount + 1 WHERE hash = new.content_hash;
+            END",
+        ),
+        Sql("CREATE TRIGGER revisions_release AFTER DELETE ON revisions
+            BEGIN
+                UPDATE contents SET refcount = refcount - 1 WHERE hash = old.content_hash;
+                DELETE FROM contents WHERE hash = old.content_hash AND refcount <= 0;
+            END"),
+        Sql("CREATE TRIGGER entries_revisions AFTER DELETE ON entries
+            BEGIN
+                DELETE FROM revisions WHERE id = old.id;
+            END"),
+    ],
+    // 9: Search index of the text content, filled by io::rebuild_search_index and kept up to date
+    // by io. The trigram tokenizer finds any fragment of at least 3 characters. The index is
+    // contentless: the content stays compressed in the entries, only its tokens are added.
+    &[
+        Sql("CREATE VIRTUAL TABLE entries_search USING fts5(
+            content, tokenize = 'trigram', content = '', contentless_delete = 1
+        )"),
+        Sql(
+            "CREATE TRIGGER entries_search_delete AFTER DELETE ON entries
+            BEGIN
+                DELETE FROM entries_search WHERE rowid = old.internal_id;
+            END",
+        ),
+        Sql(
+            "CREATE TRIGGER entries_search_update AFTER UPDATE OF internal_id ON entries
+            BEGIN
+                DELETE FROM entries_search WHERE rowid = old.internal_id;
+            END",
+        ),
+    ],
+    // 10: Ownership. The identity that created the entry and the hash of its edit token can manage
+    // it; NULL for the existing entries, which only the admins can manage.
+    &[
+        Sql("ALTER TABLE entries ADD COLUMN owner TEXT"),
+        Sql("ALTER TABLE entries ADD COLUMN token_hash TEXT"),
+    ],
+    // 11: Read passwords, as Argon2 hashes. NULL for the public entries.
+    &[Sql(
+        "ALTER TABLE entries ADD COLUMN read_password_hash TEXT",
+    )],
+    // 12: Private entries, and ids longer than 16 characters for them. SQLite cannot change the
+    // type of a column, so the table is rebuilt, along with its indexes and triggers. The contents
+    // keep their reference counts: copying the entries does not fire the triggers, nor does
+    // dropping the old table.
+    &[
+        Sql("CREATE TABLE entries_new (
+            internal_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            id TEXT UNIQUE,
+            data TEXT NOT NULL,
+            expires_at INTEGER,
+            views_left INTEGER,
+            created_at INTEGER,
+            updated_at INTEGER,
+            size INTEGER NOT NULL DEFAULT 0,
+            language TEXT,
+            mime_type TEXT,
+            creator TEXT,
+            filename TEXT,
+            codec TEXT,
+            content_hash TEXT,
+            revision INTEGER NOT NULL DEFAULT 1,
+            owner TEXT,
+            token_hash TEXT,
+            read_password_hash TEXT,
+            private INTEGER NOT NULL DEFAULT 0
+        )"),
+        Sql("INSERT INTO entries_new
+            (internal_id, id, data, expires_at, views_left, created_at, updated_at, size,
+            language, mime_type, creator, filename, codec, content_hash, revision, owner,
+            token_hash, read_password_hash)
+            SELECT internal_id, id, data, expires_at, views_left, created_at, updated_at, size,
+            language, mime_type, creator, filename, codec, content_hash, revision, owner,
+            token_hash, read_password_hash
+            FROM entries"),
+        Sql("DROP TABLE entries"),
+        Sql("ALTER TABLE entries_new RENAME TO entries"),
+        Sql("CREATE UNIQUE INDEX idx_id ON entries(id)"),
+        Sql("CREATE INDEX idx_expires_at ON entries(expires_at)"),
+        Sql("CREATE INDEX idx_content_hash ON entries(content_hash)"),
+        Sql("CREATE TRIGGER contents_reference AFTER INSERT ON entries
+            WHEN new.content_hash IS NOT NULL
+            BEGIN
+                UPDATE contents SET refcount = refcount + 1 WHERE hash = new.content_hash;
+            END"),
+        Sql("CREATE TRIGGER contents_release AFTER DELETE ON entries
+            WHEN old.content_hash IS NOT NULL
+            BEGIN
+                UPDATE contents SET refcount = refcount - 1 WHERE hash = old.content_hash;
+                DELETE FROM contents WHERE hash = old.content_hash AND refcount <= 0;
+            END"),
+        Sql(
+            "CREATE TRIGGER contents_change AFTER UPDATE OF content_hash ON entries
+            WHEN old.content_hash IS NOT new.content_hash
+            BEGIN
+                UPDATE contents SET refcount = refcount + 1 WHERE hash = new.content_hash;
+                UPDATE contents SET refcount = refcount - 1 WHERE hash = old.content_hash;
+                DELETE FROM contents WHERE hash = old.content_hash AND refcount <= 0;
+            END",
+        ),
+        Sql("CREATE TRIGGER entries_revisions AFTER DELETE ON entries
+            BEGIN
+                DELETE FROM revisions WHERE id = old.id;
+            END"),
+        Sql(
+            "CREATE TRIGGER entries_search_delete AFTER DELETE ON entries
+            BEGIN
+                DELETE FROM entries_search WHERE rowid = old.internal_id;
+            END",
+        ),
+        Sql(
+            "CREATE TRIGGER entries_search_update AFTER UPDATE OF internal_id ON entries
+            BEGIN
+                DELETE FROM entries_search WHERE rowid = old.internal_id;
+            END",
+        ),
+    ],
+    // 13: Share links, with their views left (NULL if unlimited). Revoking a link deletes it; they
+    // go with their entry, so that a new entry under the same id is not opened by them. Replaying
+    // it after rebuilding the entries only brings the trigger back.
+    &[
+        Sql("CREATE TABLE IF NOT EXISTS share_links (
+            id TEXT PRIMARY KEY,
+            paste_id TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            views_left INTEGER
+        )"),
+        Sql("CREATE INDEX IF NOT EXISTS idx_share_links_paste_id ON share_links(paste_id)"),
+        Sql(
+            "CREATE TRIGGER IF NOT EXISTS entries_share_links AFTER DELETE ON entries
+            BEGIN
+                DELETE FROM share_links WHERE paste_id = old.id;
+            END",
+        ),
+    ],
+];
+
+/// Version creating the search index, which must then be filled from the existing entries
+pub const SEARCH_INDEX_VERSION: u32 = 9;
+
+/// Version of the schema created by this version of bibin
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+pub async fn schema_version(cnx: &mut PoolConnection<Sqlite>) -> Result<u32, IOError> {
+    Ok(cnx
+        .fetch_one("PRAGMA user_version")
+        .await?
+        .get::<u32, usize>(0))
+}
+
+/// Apply the missing migrations to the database, each one in its own transaction.
+///
+/// Returns the number of migrations applied.
+pub async fn migrate(pool: &WritePool) -> Result<u32, IOError> {
+    let mut cnx = pool.0.acquire().await?;
+    let version = schema_version(&mut cnx).await?;
+
+    if version > latest_version() {
+        return Err(IOError(format!(
+            "The database schema (version {}) is more recent than this version of bibin (version {})",
+            version,
+            latest_version()
+        )));
+    }
+
+    for (index, steps) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let target = index as u32 + 1;
+        info!(
+            "[MIGRATION] Upgrading the database schema to version {}",
+            target
+        );
+
+        let mut transaction = cnx.begin().await?;
+        for step in steps.iter() {
+            let Sql(statement) = step;
+            transaction.execute(*statement).await?;
+        }
+        // PRAGMA does not support bound parameters
+        transaction
+            .execute(format!("PRAGMA user_version = {}", target).as_str())
+            .await?;
+        transaction.commit().await?;
+    }
+
+    Ok(latest_version() - version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{
+        add_link, delete_paste, get_paste, get_paste_meta, get_revision, now, search, store_paste,
+        store_paste_given_id, use_link, PasteOptions, ReadPool, ShareLink, ShareMode,
+    };
+    use tempfile::NamedTempFile;
+
+    async fn columns(pool: &WritePool) -> Vec<String> {
+        sqlx::query("SELECT name FROM pragma_table_info('entries')")
+            .fetch_all(&pool.0)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    #[async_test]
+    async fn new_database() {
+        let temp = NamedTempFile::new().unwrap();
+        let file_name = temp.path().to_str().unwrap();
+        let pool = WritePool::new(file_name).await.unwrap();
+
+        assert_eq!(migrate(&pool).await.unwrap(), latest_version());
+        assert_eq!(
+            schema_version(&mut pool.0.acquire().await.unwrap())
+                .await
+                .unwrap(),
+            latest_version()
+        );
+        // Running the migrations again does nothing
+        assert_eq!(migrate(&pool).await.unwrap(), 0);
+    }
+
+    #[async_test]
+    async fn database_from_the_unversioned_schema() {
+        let temp = NamedTempFile::new().unwrap();
+        let file_name = temp.path().to_str().unwrap();
+        let pool = WritePool::new(file_name).await.unwrap();
+
+        // Schema and content of a database created before the migrations were introduced
+        let mut cnx = pool.0.acquire().await.unwrap();
+        cnx.execute(
+            "CREATE TABLE IF NOT EXISTS entries (
+            internal_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            id VARCHAR(16) UNIQUE,
+            data TEXT NOT NULL
+        )",
+        )
+        .await
+        .unwrap();
+        cnx.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_id ON entries(id)")
+            .await
+            .unwrap();
+        cnx.execute("INSERT INTO entries (id, data) VALUES ('abcd', 'old data')")
+            .await
+            .unwrap();
+        drop(cnx);
+
+        pool.init().await.unwrap();
+        assert_eq!(
+            schema_version(&mut pool.0.acquire().await.unwrap())
+                .await
+                .unwrap(),
+            latest_version()
+        );
+        let columns = columns(&pool).await;
+        assert!(columns.contains(&"expires_at".to_owned()));
+        assert!(columns.contains(&"views_left".to_owned()));
+
+        let read_pool = ReadPool::new(file_name, 1).await.unwrap();
+        assert_eq!(
+            get_paste(&read_pool, "abcd").await.unwrap().unwrap().text(),
+            Some("old data")
+        );
+        // The existing entries are indexed for the search
+        assert_eq!(search(&read_pool, "old", 10).await.unwrap().len(), 1);
+        let meta = get_paste_meta(&read_pool, "abcd").await.unwrap().unwrap();
+        assert_eq!(meta.size, 8);
+        assert_eq!(meta.created_at, None);
+        let id = store_paste(
+            &pool,
+            4,
+            100,
+            b"new data".to_vec(),
+            &PasteOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_paste(&read_pool, &id).await.unwrap().unwrap().text(),
+            Some("new data")
+        );
+    }
+
+    #[async_test]
+    async fn entries_rebuilt_for_the_private_ids() {
+        let temp = NamedTempFile::new().unwrap();
+        let file_name = temp.path().to_str().unwrap();
+        let pool = WritePool::new(file_name).await.unwrap();
+        pool.init().await.unwrap();
+
+        // Two entries sharing their content, one of them with a revision
+        let options = PasteOptions::default();
+        let first = store_paste(&pool, 4, 100, b"shared data".to_vec(), &options)
+            .await
+            .unwrap();
+        let second = store_paste(&pool, 4, 100, b"shared data".to_vec(), &options)
+            .await
+            .unwrap();
+        store_paste_given_id(&pool, second.clone(), b"new data".to_vec(), &options, 5)
+            .await
+            .unwrap();
+
+        // Rebuild the table with its content
+        let mut cnx = pool.0.acquire().await.unwrap();
+        cnx.execute("PRAGMA user_version = 11").await.unwrap();
+        drop(cnx);
+        assert_eq!(migrate(&pool).await.unwrap(), latest_version() - 11);
+
+        let read_pool = ReadPool::new(file_name, 1).await.unwrap();
+        assert!(delete_paste(&pool, &first).await.unwrap());
+        // The content is still referenced by the revision, and the triggers are back
+        assert_eq!(
+            get_revision(&read_pool, &second, 1)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("shared data")
+        );
+        assert_eq!(search(&read_pool, "new data", 10).await.unwrap().len(), 1);
+        assert!(delete_paste(&pool, &second).await.unwrap());
+        assert_eq!(search(&read_pool, "data", 10).await.unwrap().len(), 0);
+        let contents: i64 = sqlx::query("SELECT count(*) FROM contents")
+            .fetch_one(&pool.0)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(contents, 0);
+
+        let private = PasteOptions {
+            private: true,
+            ..Default::default()
+        };
+        let id = store_paste(&pool, 4, 100, b"secret".to_vec(), &private)
+            .await
+            .unwrap();
+        assert_eq!(id.len(), crate::ids::PRIVATE_ID_LENGTH);
+        assert!(
+            get_paste_meta(&read_pool, &id)
+                .await
+                .unwrap()
+                .unwrap()
+                .private
+        );
+    }
+
+    #[async_test]
+    async fn share_links_added() {
+        let temp = NamedTempFile::new().unwrap();
+        let file_name = temp.path().to_str().unwrap();
+        let pool = WritePool::new(file_name).await.unwrap();
+        pool.init().await.unwrap();
+        let id = store_paste(&pool, 4, 100, b"shared".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+
+        // Back to the schema before the share links
+        let mut cnx = pool.0.acquire().await.unwrap();
+        cnx.execute("DROP TRIGGER entries_share_links")
+            .await
+            .unwrap();
+        cnx.execute("DROP TABLE share_links").await.unwrap();
+        cnx.execute("PRAGMA user_version = 12").await.unwrap();
+        drop(cnx);
+        assert_eq!(migrate(&pool).await.unwrap(), 1);
+
+        let link = ShareLink {
+            id: "link".to_owned(),
+            paste_id: id.clone(),
+            mode: ShareMode::Page,
+            expires_at: now() + 60,
+            max_views: Some(1),
+        };
+        add_link(&pool, &link).await.unwrap();
+        assert!(use_link(&pool, &id, "link").await.unwrap());
+        assert!(!use_link(&pool, &id, "link").await.unwrap());
+
+        // The links go with their entry
+        let unlimited = ShareLink {
+            id: "unlimited".to_owned(),
+            max_views: None,
+            ..link
+        };
+        add_link(&pool, &unlimited).await.unwrap();
+        assert!(delete_paste(&pool, &id).await.unwrap());
+        let links: i64 = sqlx::query("SELECT count(*) FROM share_links")
+            .fetch_one(&pool.0)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(links, 0);
+    }
+
+    #[async_test]
+    async fn database_from_the_future() {
+        let temp = NamedTempFile::new().unwrap();
+        let file_name = temp.path().to_str().unwrap();
+        let pool = WritePool::new(file_name).await.unwrap();
+
+        pool.0
+            .execute(format!("PRAGMA user_version = {}", latest_version() + 1).as_str())
+            .await
+            .unwrap();
+        assert!(migrate(&pool).await.is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use rocket::http::{ContentType, Header};
+
+use crate::io::Paste;
+
+pub const TEXT: &str = "text/plain; charset=utf-8";
+pub const BINARY: &str = "application/octet-stream";
+
+/// Guess the type of a content uploaded without a meaningful Content-Type header, from the magic
+/// numbers of a few common formats.
+pub fn sniff(data: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x7fELF", "application/x-elf"),
+    ];
+
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return mime_type;
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(_) => TEXT,
+        Err(_) => BINARY,
+    }
+}
+
+pub fn is_image(mime_type: &str) -> bool {
+    mime_type.starts_with("image/") && !is_active(mime_type)
+}
+
+/// Types that a browser would render as a document able to run scripts in our origin
+fn is_active(mime_type: &str) -> bool {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "text/html"
+        || essence == "application/javascript"
+        || essence == "text/javascript"
+        || essence.ends_with("xml")
+}
+
+/// Content type used to serve a paste as-is: the type given by its author, except for active
+/// content that is served as plain text.
+pub fn content_type(paste: &Paste) -> ContentType {
+    let fallback = || match paste.text() {
+        Some(_) => ContentType::Plain,
+        None => ContentType::Binary,
+    };
+
+    match paste.mime_type.as_deref() {
+        Some(mime_type) if paste.text().is_some() && mime_type.starts_with("text/") => {
+            ContentType::Plain
+        }
+        Some(mime_type) if !is_active(mime_type) => {
+            ContentType::parse_flexible(mime_type).unwrap_or_else(fallback)
+        }
+        _ => fallback(),
+    }
+}
+
+/// A paste served as-is. Browsers must not guess another type than the one given here, and only
+/// show the text and the images: anything else is downloaded.
+#[derive(Responder)]
+pub struct RawContent {
+    data: Vec<u8>,
+    content_type: ContentType,
+    nosniff: Header<'static>,
+    disposition: Header<'static>,
+}
+
+impl RawContent {
+    pub fn new(paste: Paste) -> RawContent {
+        let content_type = content_type(&paste);
+        let disposition = if content_type.top() == "text" || content_type.top() == "image" {
+            "inline".to_owned()
+        } else {
+            // The names of the uploaded files are sanitized, but may not be ASCII
+            match paste
+                .filename
+                .filter(|name| name.chars().all(|c| c.is_ascii_graphic() || c == ' '))
+            {
+                Some(name) => format!("attachment; filename=\"{}\"", name),
+                None => "attachment".to_owned(),
+            }
+        };
+        RawContent {
+            data: paste.data,
+            content_type,
+            nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+            disposition: Header::new("Content-Disposition", disposition),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paste(data: &[u8], mime_type: Option<&str>) -> Paste {
+        Paste {
+            data: data.to_vec(),
+            language: None,
+            mime_type: mime_type.map(str::to_owned),
+            filename: None,
+        }
+    }
+
+    #[test]
+    fn sniff_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), "image/jpeg");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"hello world"), TEXT);
+        assert_eq!(sniff(b""), TEXT);
+        assert_eq!(sniff(b"\x00\xff\xfe"), BINARY);
+    }
+
+    #[test]
+    fn served_content_types() {
+        assert_eq!(content_type(&paste(b"hello", None)), ContentType::Plain);
+        assert_eq!(content_type(&paste(b"\xff", None)), ContentType::Binary);
+        assert_eq!(
+            content_type(&paste(b"\x89PNG", Some("image/png"))),
+            ContentType::PNG
+        );
+        assert_eq!(
+            content_type(&paste(b"fn main() {}", Some("text/x-rust"))),
+            ContentType::Plain
+        );
+        assert_eq!(
+            content_type(&paste(b"<script></script>", Some("text/html"))),
+            ContentType::Plain
+        );
+        assert_eq!(
+            content_type(&paste(b"<svg></svg>", Some("image/svg+xml"))),
+            ContentType::Plain
+        );
+        assert_eq!(
+            content_type(&paste(b"\xff<svg>", Some("image/svg+xml"))),
+            ContentType::Binary
+        );
+        assert!(is_image("image/png"));
+        assert!(!is_image("image/svg+xml"));
+        assert!(!is_image("text/plain"));
+    }
+}
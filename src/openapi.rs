@@ -0,0 +1,663 @@
+use rocket::http::Method;
+use rocket::response::content::RawJson;
+use serde_json::{json, Map, Value};
+
+/// Description of one route for the OpenAPI document
+struct Operation {
+    method: Method,
+    /// Path with the parameters between braces, as OpenAPI writes them
+    path: &'static str,
+    summary: &'static str,
+    /// Whether the password is required, with one of the `auth.rs` schemes
+    auth: bool,
+    /// (name, description) of the query parameters
+    query: &'static [(&'static str, &'static str)],
+    /// (name, description) of the optional headers
+    headers: &'static [(&'static str, &'static str)],
+    /// Media types accepted in the body
+    body: &'static [&'static str],
+    /// (status, description, media type) of the responses
+    responses: &'static [(u16, &'static str, &'static str)],
+}
+
+const HTML: &str = "text/html";
+const PLAIN: &str = "text/plain";
+const JSON: &str = "application/json";
+const ANY: &str = "*/*";
+
+const UNAUTHORIZED: (u16, &str, &str) = (401, "Missing or invalid password", PLAIN);
+const NOT_FOUND: (u16, &str, &str) = (404, "No such paste, or expired", PLAIN);
+const PROTECTED: (u16, &str, &str) = (401, "Missing or wrong read password", PLAIN);
+const SHARE_REFUSED: (u16, &str, &str) = (
+    403,
+    "Share link forged, expired, revoked or out of views",
+    PLAIN,
+);
+const TOO_LARGE: (u16, &str, &str) = (413, "Larger than max_paste_size", PLAIN);
+
+const RAW_HEADERS: &[(&str, &str)] = &[
+    ("X-Expire", "Time to live of the paste: 30m, 1h, 7d..."),
+    (
+        "X-Burn-After-Reading",
+        "Delete the paste after its first view",
+    ),
+    ("X-Max-Views", "Delete the paste after this many views"),
+    (
+        "X-Language",
+        "Language of the paste, used for its highlighting",
+    ),
+    ("X-Read-Password", "Password needed to read the paste"),
+    (
+        "X-Private",
+        "1 for a private paste, with a long id and out of the listings, 0 for a public one",
+    ),
+];
+const EDIT_TOKEN: &[(&str, &str)] = &[(
+    "X-Edit-Token",
+    "Edit token of the paste, given in the answer to its creation, instead of a key",
+)];
+const RAW_HEADERS_WITH_TOKEN: &[(&str, &str)] = &[
+    ("X-Expire", "Time to live of the paste: 30m, 1h, 7d..."),
+    (
+        "X-Burn-After-Reading",
+        "Delete the paste after its first view",
+    ),
+    ("X-Max-Views", "Delete the paste after this many views"),
+    (
+        "X-Language",
+        "Language of the paste, used for its highlighting",
+    ),
+    ("X-Read-Password", "Password needed to read the paste"),
+    (
+        "X-Private",
+        "1 for a private paste, with a long id and out of the listings, 0 for a public one",
+    ),
+    EDIT_TOKEN[0],
+];
+const READ_PASSWORD: &[(&str, &str)] = &[(
+    "X-Read-Password",
+    "Password of a protected paste, also accepted as the password of the Basic authentication",
+)];
+const SHARE: &[(&str, &str)] = &[(
+    "share",
+    "Signed share link, opening the paste without its read password",
+)];
+const LIST_QUERY: &[(&str, &str)] = &[
+    (
+        "limit",
+        "Number of pastes per page, 50 by default, 500 at most",
+    ),
+    ("cursor", "next_cursor of the previous page"),
+    ("since", "Only the pastes created after this Unix timestamp"),
+    ("lang", "Only the pastes in this language"),
+];
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: Method::Get,
+        path: "/",
+        summary: "Editor for browsers, usage for curl",
+        auth: false,
+        query: &[],
+        headers: &[],
+        body: &[],
+        responses: &[(200, "Index page", HTML), (200, "Usage", PLAIN)],
+    },
+    Operation {
+        method: Method::Post,
+        path: "/",
+        summary: "Create a paste from the form of the index page",
+        auth: false,
+        query: &[],
+        headers: &[],
+        body: &["multipart/form-data", "application/x-www-form-urlencoded"],
+        responses: &[
+            (303, "Redirection to the new paste", ANY),
+            (200, "The new paste", JSON),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Post,
+        path: "/{key}",
+        summary: "Create or replace the paste with this id from a form",
+        auth: false,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &["multipart/form-data", "application/x-www-form-urlencoded"],
+        responses: &[
+            (303, "Redirection to the paste", ANY),
+            (200, "The paste", JSON),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Put,
+        path: "/",
+        summary: "Create a paste from the body of the request",
+        auth: true,
+        query: &[],
+        headers: RAW_HEADERS,
+        body: &[ANY],
+        responses: &[
+            (200, "URL of the new paste", PLAIN),
+            (200, "The new paste", JSON),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Put,
+        path: "/{key}",
+        summary: "Create or replace the paste with this id from the body of the request",
+        auth: true,
+        query: &[],
+        headers: RAW_HEADERS_WITH_TOKEN,
+        body: &[ANY],
+        responses: &[
+            (200, "URL of the paste", PLAIN),
+            (200, "The paste", JSON),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{key}",
+        summary: "A paste, highlighted for browsers. `<id>@<n>` is a revision, the .url, .qr \
+                  and .b64 extensions redirect, show a QR code or encode the content",
+        auth: false,
+        query: SHARE,
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[
+            (200, "The paste", HTML),
+            (200, "Content of the paste", PLAIN),
+            (200, "The paste and its content", JSON),
+            (303, "Redirection to the URL in the paste", ANY),
+            (401, "Prompt for the read password", HTML),
+            PROTECTED,
+            SHARE_REFUSED,
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Post,
+        path: "/{key}/unlock",
+        summary: "Read a protected paste with the password typed in its prompt",
+        auth: false,
+        query: &[],
+        headers: &[],
+        body: &["application/x-www-form-urlencoded"],
+        responses: &[
+            (200, "The paste", HTML),
+            (200, "Content of the paste", PLAIN),
+            (303, "Redirection to the URL in the paste", ANY),
+            (401, "Prompt for the read password", HTML),
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{name}/qr",
+        summary: "QR code of the URL of a paste",
+        auth: false,
+        query: SHARE,
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[
+            (200, "QR code", "image/png"),
+            PROTECTED,
+            SHARE_REFUSED,
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/all_entries",
+        summary: "Ids and contents of every paste",
+        auth: true,
+        query: &[],
+        headers: &[],
+        body: &[],
+        responses: &[(200, "Pastes", PLAIN), UNAUTHORIZED],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{key}/raw",
+        summary: "Content of a paste, with its MIME type",
+        auth: false,
+        query: SHARE,
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[
+            (200, "Content of the paste", ANY),
+            PROTECTED,
+            SHARE_REFUSED,
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{key}/meta",
+        summary: "Metadata of a paste",
+        auth: false,
+        query: &[],
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[(200, "Metadata", JSON), PROTECTED, NOT_FOUND],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{key}/history",
+        summary: "Kept revisions of a paste, oldest first",
+        auth: false,
+        query: &[],
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[(200, "Revisions", JSON), PROTECTED, NOT_FOUND],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/{old}/diff/{new}",
+        summary: "Differences between two text pastes or revisions",
+        auth: false,
+        query: &[],
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[
+            (200, "Highlighted differences", HTML),
+            (200, "Unified diff", PLAIN),
+            PROTECTED,
+            (403, "One of the pastes is view-limited", PLAIN),
+            NOT_FOUND,
+            (415, "One of the pastes is not text", PLAIN),
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/search",
+        summary: "Text pastes containing every word of the query",
+        auth: true,
+        query: &[("q", "Words to look for, at least 3 characters each")],
+        headers: &[],
+        body: &[],
+        responses: &[
+            (200, "Results", HTML),
+            (200, "Ids and extracts", JSON),
+            UNAUTHORIZED,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/pastes",
+        summary: "Pastes without their content, most recent first",
+        auth: true,
+        query: LIST_QUERY,
+        headers: &[],
+        body: &[],
+        responses: &[
+            (200, "Page of pastes and next_cursor", JSON),
+            (400, "Invalid cursor", PLAIN),
+            UNAUTHORIZED,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/pastes",
+        summary: "Pastes without their content, most recent first, for browsers",
+        auth: true,
+        query: LIST_QUERY,
+        headers: &[],
+        body: &[],
+        responses: &[
+            (200, "Page of pastes", HTML),
+            (400, "Invalid cursor", PLAIN),
+            UNAUTHORIZED,
+        ],
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/{id}",
+        summary: "Delete a paste",
+        auth: true,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &[],
+        responses: &[
+            (200, "Confirmation", PLAIN),
+            (200, "Id of the deleted paste", JSON),
+            UNAUTHORIZED,
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/pastes",
+        summary: "Create a paste",
+        auth: true,
+        query: &[],
+        headers: &[],
+        body: &[JSON],
+        responses: &[
+            (201, "The new paste", JSON),
+            (400, "Invalid paste", PLAIN),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/pastes/{id}",
+        summary: "Metadata and URLs of a paste, without counting a view",
+        auth: false,
+        query: &[],
+        headers: READ_PASSWORD,
+        body: &[],
+        responses: &[(200, "The paste", JSON), PROTECTED, NOT_FOUND],
+    },
+    Operation {
+        method: Method::Put,
+        path: "/api/v1/pastes/{id}",
+        summary: "Create or replace the paste with this id",
+        auth: true,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &[JSON],
+        responses: &[
+            (200, "The paste", JSON),
+            (400, "Invalid paste", PLAIN),
+            UNAUTHORIZED,
+            TOO_LARGE,
+        ],
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/api/v1/pastes/{id}",
+        summary: "Delete a paste",
+        auth: true,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &[],
+        responses: &[(204, "Deleted", ANY), UNAUTHORIZED, NOT_FOUND],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/keyspace",
+        summary: "Utilisation of the keyspace of the short ids, for the admins",
+        auth: true,
+        query: &[],
+        headers: &[],
+        body: &[],
+        responses: &[(200, "Ids taken at each length", JSON), UNAUTHORIZED],
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/pastes/{id}/links",
+        summary: "Mint a signed link opening the paste, even with a read password, until it \
+                  expires",
+        auth: true,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &[JSON],
+        responses: &[
+            (201, "The link, with its URL", JSON),
+            (400, "Invalid link", PLAIN),
+            UNAUTHORIZED,
+            NOT_FOUND,
+        ],
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/api/v1/pastes/{id}/links/{link}",
+        summary: "Revoke a share link",
+        auth: true,
+        query: &[],
+        headers: EDIT_TOKEN,
+        body: &[],
+        responses: &[(204, "Revoked", ANY), UNAUTHORIZED, NOT_FOUND],
+    },
+    Operation {
+        method: Method::Get,
+        path: "/openapi.json",
+        summary: "This document",
+        auth: false,
+        query: &[],
+        headers: &[],
+        body: &[],
+        responses: &[(200, "OpenAPI document", JSON)],
+    },
+];
+
+fn parameter(name: &str, location: &str, description: &str, required: bool) -> Value {
+    json!({
+        "name": name,
+        "in": location,
+        "description": description,
+        "required": required,
+        "schema": { "type": "string" },
+    })
+}
+
+/// Schema of the JSON bodies, named after their component, `{}` (anything) for the other media
+/// types
+fn schema(media_type: &str, body: Option<&str>) -> Value {
+    match media_type {
+        JSON => match body {
+            Some(name) => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+            None => json!({}),
+        },
+        PLAIN | HTML => json!({ "type": "string" }),
+        _ => json!({ "type": "string", "format": "binary" }),
+    }
+}
+
+impl Operation {
+    fn to_json(&self) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| match name {
+                "link" => parameter(name, "path", "Id of a share link", true),
+                _ => parameter(name, "path", "Id of a paste, with an extension", true),
+            })
+            .collect();
+        parameters.extend(
+            self.query
+                .iter()
+                .map(|(name, description)| parameter(name, "query", description, false)),
+        );
+        parameters.extend(
+            self.headers
+                .iter()
+                .map(|(name, description)| parameter(name, "header", description, false)),
+        );
+
+        // Several responses with the same status differ by their media type
+        let mut responses = Map::new();
+        for (status, description, media_type) in self.responses {
+            let response = responses
+                .entry(status.to_string())
+                .or_insert_with(|| json!({ "description": description, "content": {} }));
+            response["content"][*media_type] = json!({ "schema": schema(media_type, None) });
+        }
+
+        let mut operation = json!({
+            "summary": self.summary,
+            "parameters": parameters,
+            "responses": responses,
+        });
+        if !self.body.is_empty() {
+            // The share links are the only JSON bodies that are not pastes
+            let name = if self.path.ends_with("/links") {
+                "NewLink"
+            } else {
+                "NewPaste"
+            };
+            let content: Map<String, Value> = self
+                .body
+                .iter()
+                .map(|media_type| {
+                    (
+                        media_type.to_string(),
+                        json!({ "schema": schema(media_type, Some(name)) }),
+                    )
+                })
+                .collect();
+            operation["requestBody"] = json!({ "required": true, "content": content });
+        }
+        if self.auth {
+            operation["security"] = json!([{ "apiKey": [] }, { "basic": [] }]);
+            operation["responses"]["403"] = json!({
+                "description": "The key does not have the scope needed, or the paste is not \
+                                its own",
+                "content": { PLAIN: { "schema": schema(PLAIN, None) } },
+            });
+        }
+        operation
+    }
+}
+
+/// OpenAPI 3 description of the HTTP interface of bibin
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for operation in OPERATIONS {
+        let path = paths
+            .entry(operation.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        path[operation.method.as_str().to_lowercase()] = operation.to_json();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "bibin",
+            "description": "Paste bin and URL shortener",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "apiKey": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "X-API-Key",
+                    "description": "The password of bibin, or one of the named keys",
+                },
+                "basic": {
+                    "type": "http",
+                    "scheme": "basic",
+                    "description": "The password of bibin, or one of the named keys. The user name \
+                                    is recorded as the creator with the password",
+                },
+            },
+            "schemas": {
+                "NewPaste": {
+                    "type": "object",
+                    "required": ["content"],
+                    "properties": {
+                        "content": { "type": "string" },
+                        "encoding": { "type": "string", "enum": ["utf8", "base64"] },
+                        "language": { "type": "string" },
+                        "mime_type": { "type": "string" },
+                        "filename": { "type": "string" },
+                        "expire": { "type": "string", "example": "1d" },
+                        "max_views": { "type": "integer", "minimum": 1 },
+                        "read_password": { "type": "string" },
+                        "private": { "type": "boolean" },
+                    },
+                },
+                "NewLink": {
+                    "type": "object",
+                    "properties": {
+                        "mode": { "type": "string", "enum": ["page", "raw", "qr"] },
+                        "expire": { "type": "string", "example": "1d" },
+                        "max_views": { "type": "integer", "minimum": 1 },
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[get("/openapi.json")]
+pub fn openapi() -> RawJson<String> {
+    RawJson(document().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    use super::{document, openapi};
+
+    /// (method, path) of the mounted routes, with the parameters written the OpenAPI way
+    fn mounted_routes() -> BTreeSet<(String, String)> {
+        crate::routes()
+            .iter()
+            .map(|route| {
+                let path = route
+                    .uri
+                    .path()
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix('<') {
+                        Some(name) => format!("{{{}}}", name.trim_end_matches('>')),
+                        None => segment.to_owned(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (route.method.as_str().to_lowercase(), path)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_document_describes_the_mounted_routes() {
+        let document = document();
+        let documented: BTreeSet<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, operations)| {
+                operations
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| (method.clone(), path.clone()))
+            })
+            .collect();
+        assert_eq!(documented, mounted_routes());
+    }
+
+    #[test]
+    fn test_security_schemes() {
+        let document = document();
+        let schemes = &document["components"]["securitySchemes"];
+        assert_eq!(schemes["apiKey"]["name"], "X-API-Key");
+        assert_eq!(schemes["basic"]["scheme"], "basic");
+
+        let all_entries = &document["paths"]["/all_entries"]["get"];
+        assert_eq!(all_entries["security"][0]["apiKey"], serde_json::json!([]));
+        assert!(document["paths"]["/{key}/raw"]["get"]
+            .get("security")
+            .is_none());
+    }
+
+    #[test]
+    fn test_openapi_route() {
+        let rocket = rocket::build().mount("/", routes![openapi]);
+        let client = Client::untracked(rocket).unwrap();
+        let response = client.get("/openapi.json").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["openapi"], "3.0.3");
+    }
+}
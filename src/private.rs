@@ -0,0 +1,27 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+/// Visibility requested by an API client with the `X-Private` header: `1` or `true` for a private
+/// paste, `0` or `false` for a public one. Without the header, the default of the key applies.
+pub struct PrivateHeader(pub Option<bool>);
+
+#[derive(Debug)]
+pub struct PrivateError;
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for PrivateHeader {
+    type Error = PrivateError;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Private") {
+            None => Outcome::Success(PrivateHeader(None)),
+            Some("1") | Some("true") => Outcome::Success(PrivateHeader(Some(true))),
+            Some("0") | Some("false") => Outcome::Success(PrivateHeader(Some(false))),
+            Some(_) => {
+                warn!("[PRIVATE] Invalid X-Private header");
+                Outcome::Error((Status::BadRequest, PrivateError))
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use base64::{engine::general_purpose, Engine as _};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::auth;
+
+/// Password protecting a new paste, given by an API client in the `X-Read-Password` header.
+/// Only the hash of the password is stored.
+pub struct ReadPasswordHeader(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ReadPasswordHeader {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        let password = request
+            .headers()
+            .get_one("X-Read-Password")
+            .filter(|password| !password.is_empty())
+            .map(str::to_owned);
+        Outcome::Success(ReadPasswordHeader(password))
+    }
+}
+
+/// Password given by a reader: the `X-Read-Password` header, or the password of the Basic
+/// authentication so that browsers and curl's `-u` work too
+pub struct ReadPassword(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ReadPassword {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(password) = request.headers().get_one("X-Read-Password") {
+            return Outcome::Success(ReadPassword(Some(password.to_owned())));
+        }
+        let password = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|token| general_purpose::STANDARD.decode(token).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| Some(decoded.split_once(':')?.1.to_owned()));
+        Outcome::Success(ReadPassword(password))
+    }
+}
+
+/// Hash of the password protecting a new paste, None for a public paste
+pub fn hash(password: Option<&str>) -> Result<Option<String>, Status> {
+    match password.filter(|password| !password.is_empty()) {
+        None => Ok(None),
+        Some(password) => auth::hash_password(password).map(Some).map_err(|e| {
+            error!("[READ_PASSWORD] Error when hashing the password: {}", e);
+            Status::InternalServerError
+        }),
+    }
+}
+
+/// Wrong passwords tried on a paste before the next attempts are refused unchecked
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+/// How long the failed attempts on a paste are remembered, from the first one
+const FAILED_ATTEMPTS_WINDOW: Duration = Duration::from_secs(60);
+
+/// Failed attempts on each paste since the start of their window. Each attempt costs an Argon2
+/// verification, and guessing must stay slow.
+fn failed_attempts() -> std::sync::MutexGuard<'static, HashMap<String, (u32, Instant)>> {
+    static FAILED: OnceLock<Mutex<HashMap<String, (u32, Instant)>>> = OnceLock::new();
+    FAILED
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether the password opens the paste `id` protected by this hash. Public pastes are open to
+/// all. Answers a 429 once too many wrong passwords were tried on the paste.
+pub async fn opens(id: &str, hash: Option<&str>, password: Option<&str>) -> Result<bool, Status> {
+    let (hash, password) = match (hash, password) {
+        (None, _) => return Ok(true),
+        (Some(_), None) => return Ok(false),
+        (Some(hash), Some(password)) => (hash, password),
+    };
+    let throttled = failed_attempts().get(id).is_some_and(|(count, start)| {
+        *count >= MAX_FAILED_ATTEMPTS && start.elapsed() < FAILED_ATTEMPTS_WINDOW
+    });
+    if throttled {
+        warn!("[READ_PASSWORD] Too many wrong passwords for {}", id);
+        return Err(Status::TooManyRequests);
+    }
+    if auth::check_password(password, hash).await {
+        return Ok(true);
+    }
+    let mut failed = failed_attempts();
+    failed.retain(|_, (_, start)| start.elapsed() < FAILED_ATTEMPTS_WINDOW);
+    failed.entry(id.to_owned()).or_insert((0, Instant::now())).0 += 1;
+    Ok(false)
+}
+
+/// Answer to a reader of a protected paste without its password: a form for the browsers, a
+/// Basic authentication challenge for the others. The browsers would show their own dialog
+/// instead of the form if they were challenged too.
+pub struct PasswordRequired {
+    /// The prompt, already rendered
+    pub html: Option<String>,
+}
+
+impl<'r> Responder<'r, 'static> for PasswordRequired {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(Status::Unauthorized);
+        match self.html {
+            Some(html) => response
+                .header(ContentType::HTML)
+                .sized_body(html.len(), Cursor::new(html)),
+            None => {
+                let message = "This paste is protected by a password\n";
+                response
+                    .header(ContentType::Plain)
+                    .header(Header::new(
+                        "WWW-Authenticate",
+                        r#"Basic realm="bibin", charset="UTF-8""#,
+                    ))
+                    .sized_body(message.len(), Cursor::new(message))
+            }
+        };
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::opens;
+    use crate::auth::hash_password;
+    use rocket::http::Status;
+
+    #[rocket::async_test]
+    async fn test_opens() {
+        let hash = hash_password("secret").unwrap();
+        let opens = |hash, password| opens("test_opens", hash, password);
+        assert_eq!(opens(None, None).await, Ok(true));
+        assert_eq!(opens(None, Some("anything")).await, Ok(true));
+        assert_eq!(opens(Some(&hash), Some("secret")).await, Ok(true));
+        assert_eq!(opens(Some(&hash), Some("Secret")).await, Ok(false));
+        assert_eq!(opens(Some(&hash), None).await, Ok(false));
+    }
+
+    #[rocket::async_test]
+    async fn test_failed_attempts() {
+        let hash = hash_password("secret").unwrap();
+        for _ in 0..super::MAX_FAILED_ATTEMPTS {
+            let opened = opens("test_failed_attempts", Some(&hash), Some("guess")).await;
+            assert_eq!(opened, Ok(false));
+        }
+        let opened = opens("test_failed_attempts", Some(&hash), Some("secret")).await;
+        assert_eq!(opened, Err(Status::TooManyRequests));
+        let opened = opens("other", Some(&hash), Some("secret")).await;
+        assert_eq!(opened, Ok(true), "Only the guessed paste is refused");
+    }
+}
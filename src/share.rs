@@ -0,0 +1,169 @@
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rocket::config::SecretKey;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use sha2::Sha256;
+
+use crate::io::{now, ShareLink, ShareMode};
+use crate::store::PasteStore;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key signing the share links: the `secret_key` of the configuration, like Rocket's own
+pub struct ShareKey(Vec<u8>);
+
+impl ShareKey {
+    /// Without a secret key, the links are signed with a random key and do not survive a restart.
+    /// Only in debug: like Rocket, bibin refuses to start in release without a secret key.
+    pub fn new(secret_key: Option<&SecretKey>) -> ShareKey {
+        match secret_key.filter(|key| key.is_provided()) {
+            Some(key) => ShareKey(master_key(key)),
+            None => {
+                // Warned about by BibinConfig::check_secrets, once the logger is set up
+                let mut key = vec![0; 64];
+                OsRng.fill_bytes(&mut key);
+                ShareKey(key)
+            }
+        }
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        // HMAC takes keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts any key");
+        mac.update(payload);
+        mac
+    }
+
+    /// Token of a link in the URLs: the link in JSON, then its signature, in URL-safe base64
+    pub fn sign(&self, link: &ShareLink) -> String {
+        let payload = serde_json::to_vec(link).expect("A link can be serialized");
+        let signature = self.mac(&payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            general_purpose::URL_SAFE_NO_PAD.encode(&payload),
+            general_purpose::URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// The link of a token, if it was signed with this key
+    pub fn verify(&self, token: &str) -> Option<ShareLink> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = general_purpose::URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = general_purpose::URL_SAFE_NO_PAD.decode(signature).ok()?;
+        // In constant time
+        self.mac(&payload).verify_slice(&signature).ok()?;
+        serde_json::from_slice(&payload).ok()
+    }
+}
+
+/// The bytes of a secret key: Rocket only hands them out through its configuration
+fn master_key(key: &SecretKey) -> Vec<u8> {
+    let config = rocket::Config {
+        secret_key: key.clone(),
+        ..rocket::Config::default()
+    };
+    Figment::from(config)
+        .extract_inner(rocket::Config::SECRET_KEY)
+        .expect("The configuration holds its secret key")
+}
+
+/// Share link given in the `share` query parameter of a paste. Its signature and expiration are
+/// checked here; whether it was revoked or still has views when it is used.
+pub struct SharedLink(pub Option<ShareLink>);
+
+#[derive(Debug)]
+pub enum ShareError {
+    Invalid,
+    Expired,
+}
+
+impl SharedLink {
+    /// Whether the link opens the paste designated by the key of the URL in this mode. Counts a
+    /// view of the link.
+    pub async fn opens(
+        &self,
+        store: &dyn PasteStore,
+        key: &str,
+        mode: ShareMode,
+    ) -> Result<bool, Status> {
+        let link = match &self.0 {
+            Some(link) if link.paste_id == key && link.mode == mode => link,
+            _ => return Ok(false),
+        };
+        store.use_link(&link.paste_id, &link.id).await.map_err(|e| {
+            error!("[SHARE] Error when counting a view of {}: {}", link.id, e);
+            Status::InternalServerError
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for SharedLink {
+    type Error = ShareError;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match request.query_value::<&str>("share") {
+            None => return Outcome::Success(SharedLink(None)),
+            Some(token) => token.unwrap_or_default(),
+        };
+        let key = match request.rocket().state::<ShareKey>() {
+            Some(key) => key,
+            None => return Outcome::Error((Status::Forbidden, ShareError::Invalid)),
+        };
+        match key.verify(token) {
+            None => {
+                warn!("[SHARE] Invalid share link");
+                Outcome::Error((Status::Forbidden, ShareError::Invalid))
+            }
+            Some(link) if link.expires_at <= now() => {
+                Outcome::Error((Status::Forbidden, ShareError::Expired))
+            }
+            Some(link) => Outcome::Success(SharedLink(Some(link))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShareKey;
+    use crate::io::{ShareLink, ShareMode};
+    use rocket::config::SecretKey;
+
+    #[test]
+    fn test_signature() {
+        let key = ShareKey::new(Some(&SecretKey::from(&[1; 64])));
+        assert_eq!(key.0, vec![1; 64]);
+        let another = ShareKey::new(Some(&SecretKey::from(&[2; 64])));
+        // A zero key is no key
+        assert_ne!(
+            ShareKey::new(Some(&SecretKey::from(&[0; 64]))).0,
+            vec![0; 64]
+        );
+
+        let link = ShareLink {
+            id: "link".to_owned(),
+            paste_id: "abcd".to_owned(),
+            mode: ShareMode::Raw,
+            expires_at: 1234,
+            max_views: Some(3),
+        };
+        let token = key.sign(&link);
+        assert_eq!(key.verify(&token), Some(link.clone()));
+        assert_eq!(another.verify(&token), None);
+
+        // Changing the link breaks the signature
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = another.sign(&ShareLink {
+            max_views: None,
+            ..link
+        });
+        let (payload, _) = forged.split_once('.').unwrap();
+        assert_eq!(key.verify(&format!("{}.{}", payload, signature)), None);
+        assert_eq!(key.verify("garbage"), None);
+    }
+}
@@ -0,0 +1,790 @@
+mod filesystem;
+mod memory;
+mod sqlite;
+
+pub use filesystem::FilesystemStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{BibinConfig, Storage};
+use crate::ids::IdUsage;
+use crate::io::{
+    expiration_date, find_words, now, preview, snippet_around, IOError, ListFilter, Paste,
+    PasteMeta, PasteOptions, PasteSummary, Revision, SearchResult, ShareLink,
+};
+
+/// Where the pastes are kept. The routes only go through this trait, so that the backend can be
+/// chosen in the configuration.
+///
+/// Expired pastes are never returned, even before they are evicted.
+#[rocket::async_trait]
+pub trait PasteStore: Send + Sync {
+    /// Stores a paste under a new random id and returns it
+    async fn store(&self, content: Vec<u8>, options: &PasteOptions) -> Result<String, IOError>;
+
+    /// Stores a paste under the given id. The existing one becomes its previous revision, the new
+    /// one keeps its creation date.
+    async fn store_with_id(
+        &self,
+        id: String,
+        content: Vec<u8>,
+        options: &PasteOptions,
+    ) -> Result<String, IOError>;
+
+    /// Content of a paste, to show it to a reader. This counts as a view: a paste is deleted
+    /// after its last allowed view.
+    async fn get(&self, id: &str) -> Result<Option<Paste>, IOError>;
+
+    /// Metadata of a paste. This does not count as a view.
+    async fn meta(&self, id: &str) -> Result<Option<PasteMeta>, IOError>;
+
+    /// The revisions kept of a paste, oldest first, ending with the current one
+    async fn history(&self, id: &str) -> Result<Option<Vec<Revision>>, IOError>;
+
+    /// Content of a revision of a paste. Reading the current revision counts as a view, like get.
+    async fn get_revision(&self, id: &str, revision: u32) -> Result<Option<Paste>, IOError>;
+
+    /// Deletes a paste. Returns false if there was none.
+    async fn delete(&self, id: &str) -> Result<bool, IOError>;
+
+    /// Id and content of every paste, except the private and guarded ones. Binary content is
+    /// converted lossily to text.
+    async fn list(&self) -> Result<Vec<(String, String)>, IOError>;
+
+    /// A page of the pastes matching the filter, without their content
+    async fn list_page(&self, filter: &ListFilter) -> Result<Vec<PasteSummary>, IOError>;
+
+    /// Pastes whose text contains every word of the query, ignoring the case, best matches first.
+    /// Binary, private and guarded pastes are never found.
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>, IOError>;
+
+    /// Deletes the expired pastes and returns how many were removed
+    async fn evict(&self) -> Result<u64, IOError>;
+
+    /// How many ids of each length are taken, to choose the length of the new ones
+    async fn id_usage(&self) -> Result<IdUsage, IOError>;
+
+    /// Records a new share link. Links are deleted with their paste, and evicted once expired.
+    async fn add_link(&self, link: &ShareLink) -> Result<(), IOError>;
+
+    /// Counts a view through a share link of a paste. Returns false if it was revoked, has
+    /// expired or has no views left.
+    async fn use_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError>;
+
+    /// Revokes a share link of a paste. Returns false if there was none.
+    async fn revoke_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError>;
+
+    /// Id of an existing paste with the same content, whoever stored it, that can be served
+    /// instead of storing a new one with these options. The most recent one is preferred.
+    async fn find_duplicate(
+        &self,
+        content: &[u8],
+        options: &PasteOptions,
+    ) -> Result<Option<String>, IOError>;
+}
+
+/// Open the backend selected in the configuration
+pub async fn open(config: &BibinConfig) -> Result<Arc<dyn PasteStore>, IOError> {
+    Ok(match config.storage {
+        Storage::Sqlite => Arc::new(
+            SqliteStore::open(
+                &config.database_file,
+                config.database_connections,
+                config.id_length,
+                config.max_entries,
+                config.max_revisions,
+            )
+            .await?,
+        ),
+        Storage::Filesystem => Arc::new(
+            FilesystemStore::open(
+                &config.storage_directory,
+                config.id_length,
+                config.max_entries,
+                config.max_revisions,
+            )
+            .await?,
+        ),
+        Storage::Memory => Arc::new(MemoryStore::new(
+            config.id_length,
+            config.max_entries,
+            config.max_revisions,
+        )),
+    })
+}
+
+/// Periodically evict the expired pastes. Never returns.
+pub async fn janitor(store: Arc<dyn PasteStore>, period: Duration) {
+    let mut interval = rocket::tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match store.evict().await {
+            Ok(0) => (),
+            Ok(removed) => info!("[JANITOR] Removed {} expired entries", removed),
+            Err(e) => warn!("[JANITOR] Error when purging expired entries: {}", e),
+        }
+    }
+}
+
+/// Metadata of a new paste, for the backends that do not compute it themselves. A paste
+/// replacing a previous one is its next revision, and keeps its owner and edit token, and its
+/// read password unless it is given a new one. It stays private or public.
+fn new_meta(
+    id: &str,
+    content: &[u8],
+    options: &PasteOptions,
+    previous: Option<&PasteMeta>,
+) -> PasteMeta {
+    let now = now();
+    PasteMeta {
+        id: id.to_owned(),
+        created_at: previous.and_then(|meta| meta.created_at).or(Some(now)),
+        updated_at: Some(now),
+        size: content.len() as i64,
+        language: options.language.clone(),
+        mime_type: options.mime_type.clone(),
+        creator: options.creator.clone(),
+        filename: options.filename.clone(),
+        expires_at: expiration_date(options.ttl),
+        views_left: options.max_views,
+        revision: previous.map_or(1, |meta| meta.revision + 1),
+        owner: match previous {
+            Some(meta) => meta.owner.clone(),
+            None => options.owner.clone(),
+        },
+        token_hash: match previous {
+            Some(meta) => meta.token_hash.clone(),
+            None => options.token_hash.clone(),
+        },
+        read_password_hash: options
+            .read_password_hash
+            .clone()
+            .or_else(|| previous.and_then(|meta| meta.read_password_hash.clone())),
+        private: previous.map_or(options.private, |meta| meta.private),
+    }
+}
+
+fn revision_from_meta(meta: &PasteMeta) -> Revision {
+    Revision {
+        revision: meta.revision,
+        created_at: meta.updated_at.or(meta.created_at),
+        size: meta.size,
+        language: meta.language.clone(),
+        mime_type: meta.mime_type.clone(),
+        creator: meta.creator.clone(),
+        filename: meta.filename.clone(),
+    }
+}
+
+/// The metadata of a page of the listings, for the backends that do not select it themselves
+fn select_page(metas: impl Iterator<Item = PasteMeta>, filter: &ListFilter) -> Vec<PasteMeta> {
+    let mut selected: Vec<PasteMeta> = metas
+        .filter(|meta| !is_expired(meta) && !meta.private && filter.matches(meta))
+        .collect();
+    selected.sort_unstable_by(|a, b| {
+        (b.created_at.unwrap_or(0), &b.id).cmp(&(a.created_at.unwrap_or(0), &a.id))
+    });
+    selected.truncate(filter.limit as usize);
+    selected
+}
+
+fn summary(meta: PasteMeta, data: &[u8]) -> PasteSummary {
+    PasteSummary {
+        preview: preview(data).filter(|_| !meta.is_guarded()),
+        id: meta.id,
+        created_at: meta.created_at,
+        size: meta.size,
+        language: meta.language,
+    }
+}
+
+/// Search for the backends without an index: a paste matches when its text contains every word
+/// of the query, ignoring the case of ASCII letters
+fn scan(id: &str, data: &[u8], query: &str) -> Option<SearchResult> {
+    let text = std::str::from_utf8(data).ok()?;
+    let (start, length) = find_words(text, query)?;
+    Some(SearchResult {
+        id: id.to_owned(),
+        snippet: snippet_around(text, start, length),
+    })
+}
+
+/// Numbers of the previous revisions kept of a paste at this revision, oldest first
+fn kept_revisions(current: u32, max_revisions: u32) -> std::ops::Range<u32> {
+    current.saturating_sub(max_revisions).max(1)..current
+}
+
+/// Whether this paste can be served instead of a new one of this size stored with these options,
+/// for the backends that compare the contents themselves: see `io::find_duplicate`
+fn may_serve_for(meta: &PasteMeta, size: usize, options: &PasteOptions) -> bool {
+    let expires_at = expiration_date(options.ttl);
+    !is_expired(meta)
+        && !meta.is_guarded()
+        && meta.private == options.private
+        && meta.size == size as i64
+        && meta
+            .expires_at
+            .is_none_or(|date| expires_at.is_some_and(|new| date >= new))
+}
+
+fn is_expired(meta: &PasteMeta) -> bool {
+    meta.expires_at
+        .is_some_and(|expires_at| expires_at <= now())
+}
+
+/// A share link, for the backends that do not compute its views left themselves
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct LinkRecord {
+    paste_id: String,
+    expires_at: i64,
+    views_left: Option<u32>,
+}
+
+impl LinkRecord {
+    fn new(link: &ShareLink) -> LinkRecord {
+        LinkRecord {
+            paste_id: link.paste_id.clone(),
+            expires_at: link.expires_at,
+            views_left: link.max_views,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at <= now()
+    }
+
+    /// Counts a view, if the link has not expired and has views left
+    fn use_view(&mut self) -> bool {
+        if self.is_expired() || self.views_left == Some(0) {
+            return false;
+        }
+        self.views_left = self.views_left.map(|views| views - 1);
+        true
+    }
+}
+
+fn paste_from_meta(data: Vec<u8>, meta: &PasteMeta) -> Paste {
+    Paste {
+        data,
+        language: meta.language.clone(),
+        mime_type: meta.mime_type.clone(),
+        filename: meta.filename.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ttl::Ttl;
+    use tempfile::TempDir;
+
+    /// Behaviour expected from every backend
+    async fn check_store(store: &dyn PasteStore) {
+        let id = store
+            .store(b"some data".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(id.len(), 4);
+        assert_eq!(
+            store.get(&id).await.unwrap().unwrap().text(),
+            Some("some data")
+        );
+        assert_eq!(
+            store.list().await.unwrap(),
+            vec![(id.clone(), "some data".to_owned())]
+        );
+        let results = store.search("DATA some", 10).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, id);
+        assert!(results[0].snippet.contains("<mark>data</mark>"));
+        assert!(store.search("some other", 10).await.unwrap().is_empty());
+
+        let options = PasteOptions {
+            language: Some("rs".to_owned()),
+            mime_type: Some("text/plain".to_owned()),
+            creator: Some("ci".to_owned()),
+            filename: Some("main.rs".to_owned()),
+            ..Default::default()
+        };
+        let named = store
+            .store_with_id("named".to_owned(), b"\x00\xff".to_vec(), &options)
+            .await
+            .unwrap();
+        assert_eq!(named, "named");
+        let paste = store.get("named").await.unwrap().unwrap();
+        assert_eq!(paste.data, b"\x00\xff");
+        assert_eq!(paste.language.as_deref(), Some("rs"));
+        assert_eq!(paste.filename.as_deref(), Some("main.rs"));
+        let meta = store.meta("named").await.unwrap().unwrap();
+        assert_eq!(meta.id, "named");
+        assert_eq!(meta.size, 2);
+        assert_eq!(meta.creator.as_deref(), Some("ci"));
+        assert!(meta.created_at.is_some());
+
+        // Overwriting keeps the creation date
+        store
+            .store_with_id(
+                "named".to_owned(),
+                b"new".to_vec(),
+                &PasteOptions::default(),
+            )
+            .await
+            .unwrap();
+        let new_meta = store.meta("named").await.unwrap().unwrap();
+        assert_eq!(new_meta.created_at, meta.created_at);
+        assert_eq!(new_meta.size, 3);
+        assert_eq!(new_meta.language, None);
+
+        // The previous content is kept as a revision, up to two of them in these tests
+        assert_eq!(new_meta.revision, 2);
+        let history = store.history("named").await.unwrap().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].revision, 1);
+        assert_eq!(history[0].language.as_deref(), Some("rs"));
+        assert_eq!(history[1].size, 3);
+        let first = store.get_revision("named", 1).await.unwrap().unwrap();
+        assert_eq!(first.data, b"\x00\xff");
+        assert_eq!(first.filename.as_deref(), Some("main.rs"));
+        for content in ["third", "fourth"] {
+            store
+                .store_with_id("named".to_owned(), content.into(), &PasteOptions::default())
+                .await
+                .unwrap();
+        }
+        let history = store.history("named").await.unwrap().unwrap();
+        assert_eq!(
+            history.iter().map(|r| r.revision).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert!(store.get_revision("named", 1).await.unwrap().is_none());
+        assert_eq!(
+            store
+                .get_revision("named", 3)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("third")
+        );
+        assert_eq!(
+            store
+                .get_revision("named", 4)
+                .await
+                .unwrap()
+                .unwrap()
+                .text(),
+            Some("fourth")
+        );
+        assert!(store.get_revision("named", 5).await.unwrap().is_none());
+        assert!(store.history("none").await.unwrap().is_none());
+
+        // View limits
+        let options = PasteOptions {
+            max_views: Some(2),
+            ..Default::default()
+        };
+        let limited = store.store(b"limited".to_vec(), &options).await.unwrap();
+        assert_eq!(
+            store.meta(&limited).await.unwrap().unwrap().views_left,
+            Some(2)
+        );
+        assert!(store.get(&limited).await.unwrap().is_some());
+        assert!(store.get(&limited).await.unwrap().is_some());
+        assert!(store.get(&limited).await.unwrap().is_none());
+        assert!(store.meta(&limited).await.unwrap().is_none());
+
+        // Expiration
+        let options = PasteOptions {
+            ttl: Some(Ttl(Duration::ZERO)),
+            ..Default::default()
+        };
+        let expired = store.store(b"expired".to_vec(), &options).await.unwrap();
+        assert!(store.get(&expired).await.unwrap().is_none());
+        assert!(store.meta(&expired).await.unwrap().is_none());
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        assert_eq!(store.evict().await.unwrap(), 1);
+        assert_eq!(store.evict().await.unwrap(), 0);
+
+        assert!(store.delete(&id).await.unwrap());
+        assert!(!store.delete(&id).await.unwrap());
+        assert!(store.get(&id).await.unwrap().is_none());
+        assert!(store.delete("named").await.unwrap());
+        assert_eq!(store.list().await.unwrap(), vec![]);
+
+        // Paginated listing, most recent first then by id
+        for (id, content, language) in [
+            ("list-a", &b"fn main() {}"[..], Some("rs")),
+            ("list-b", b"\x00\xff", None),
+            ("list-c", b"hello", None),
+        ] {
+            let options = PasteOptions {
+                language: language.map(str::to_owned),
+                ..Default::default()
+            };
+            store
+                .store_with_id(id.to_owned(), content.to_vec(), &options)
+                .await
+                .unwrap();
+        }
+        let ids = |page: Vec<PasteSummary>| page.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        let mut filter = ListFilter {
+            limit: 2,
+            ..Default::default()
+        };
+        let page = store.list_page(&filter).await.unwrap();
+        assert_eq!(page[0].preview.as_deref(), Some("hello"));
+        assert_eq!(page[1].preview, None);
+        assert_eq!(page[1].size, 2);
+        filter.cursor = page.last().map(crate::io::Cursor::of);
+        assert_eq!(ids(page), vec!["list-c", "list-b"]);
+        assert_eq!(ids(store.list_page(&filter).await.unwrap()), vec!["list-a"]);
+        let filter = ListFilter {
+            limit: 10,
+            language: Some("rs".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(ids(store.list_page(&filter).await.unwrap()), vec!["list-a"]);
+        let filter = ListFilter {
+            limit: 10,
+            since: Some(now() + 60),
+            ..Default::default()
+        };
+        assert!(store.list_page(&filter).await.unwrap().is_empty());
+
+        // Nothing of the content of the guarded pastes is shown in the listings
+        for options in [
+            PasteOptions {
+                max_views: Some(2),
+                ..Default::default()
+            },
+            PasteOptions {
+                read_password_hash: Some("hash".to_owned()),
+                ..Default::default()
+            },
+        ] {
+            let guarded = store.store(b"guarded".to_vec(), &options).await.unwrap();
+            let filter = ListFilter {
+                limit: 10,
+                ..Default::default()
+            };
+            let page = store.list_page(&filter).await.unwrap();
+            let summary = page.iter().find(|p| p.id == guarded).unwrap();
+            assert_eq!(summary.preview, None);
+            assert!(store.search("guarded", 10).await.unwrap().is_empty());
+            let listed = store
+                .list()
+                .await
+                .unwrap()
+                .iter()
+                .any(|(id, _)| *id == guarded);
+            assert!(!listed);
+            assert!(store.delete(&guarded).await.unwrap());
+        }
+
+        // Private pastes have long ids, and are only found by their id
+        let options = PasteOptions {
+            private: true,
+            ..Default::default()
+        };
+        let private = store
+            .store(b"hello private".to_vec(), &options)
+            .await
+            .unwrap();
+        assert_eq!(private.len(), crate::ids::PRIVATE_ID_LENGTH);
+        assert!(store.get(&private).await.unwrap().is_some());
+        assert!(store.meta(&private).await.unwrap().unwrap().private);
+        let filter = ListFilter {
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(store.list_page(&filter).await.unwrap().len(), 3);
+        assert!(store
+            .list()
+            .await
+            .unwrap()
+            .iter()
+            .all(|(id, _)| *id != private));
+        assert!(store.search("private", 10).await.unwrap().is_empty());
+    }
+
+    /// Share links: counted views, revocation, and gone with their paste
+    async fn check_links(store: &dyn PasteStore) {
+        let id = store
+            .store(b"shared".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        let link = ShareLink {
+            id: "link".to_owned(),
+            paste_id: id.clone(),
+            mode: crate::io::ShareMode::Page,
+            expires_at: now() + 60,
+            max_views: Some(2),
+        };
+        store.add_link(&link).await.unwrap();
+        let unlimited = ShareLink {
+            id: "unlimited".to_owned(),
+            max_views: None,
+            ..link.clone()
+        };
+        store.add_link(&unlimited).await.unwrap();
+        let expired = ShareLink {
+            id: "expired".to_owned(),
+            expires_at: now() - 1,
+            ..link.clone()
+        };
+        store.add_link(&expired).await.unwrap();
+
+        assert!(store.use_link(&id, "link").await.unwrap());
+        assert!(store.use_link(&id, "link").await.unwrap());
+        assert!(!store.use_link(&id, "link").await.unwrap());
+        assert!(!store.use_link(&id, "expired").await.unwrap());
+        assert!(!store.use_link("other", "unlimited").await.unwrap());
+        for _ in 0..3 {
+            assert!(store.use_link(&id, "unlimited").await.unwrap());
+        }
+
+        assert!(store.revoke_link(&id, "unlimited").await.unwrap());
+        assert!(!store.revoke_link(&id, "unlimited").await.unwrap());
+        assert!(!store.use_link(&id, "unlimited").await.unwrap());
+
+        // A new paste under the same id is not opened by the links of the previous one
+        store.add_link(&unlimited).await.unwrap();
+        assert!(store.delete(&id).await.unwrap());
+        store
+            .store_with_id(id.clone(), b"other".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        assert!(!store.use_link(&id, "unlimited").await.unwrap());
+    }
+
+    async fn check_duplicates(store: &dyn PasteStore) {
+        let options = PasteOptions {
+            creator: Some("ci".to_owned()),
+            ttl: Some(Ttl(Duration::from_secs(3600))),
+            ..Default::default()
+        };
+        let id = store.store(b"duplicated".to_vec(), &options).await.unwrap();
+        let burnt = PasteOptions {
+            max_views: Some(1),
+            ..options.clone()
+        };
+        store.store(b"duplicated".to_vec(), &burnt).await.unwrap();
+
+        // Whoever uploads it, as long as it does not expire before the new paste would
+        let anonymous = PasteOptions {
+            creator: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            store
+                .find_duplicate(b"duplicated", &anonymous)
+                .await
+                .unwrap(),
+            Some(id)
+        );
+        let forever = PasteOptions {
+            ttl: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            store.find_duplicate(b"duplicated", &forever).await.unwrap(),
+            None
+        );
+        let private = PasteOptions {
+            private: true,
+            ..options.clone()
+        };
+        assert_eq!(
+            store.find_duplicate(b"duplicated", &private).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            store.find_duplicate(b"duplicatee", &options).await.unwrap(),
+            None
+        );
+    }
+
+    /// Ids of one character: the 29 of them are soon taken, the new pastes get longer ids and
+    /// none of the previous ones is lost
+    async fn check_tiny_keyspace(store: &dyn PasteStore) {
+        let mut ids = Vec::new();
+        for i in 0..100 {
+            let content = format!("paste {}", i).into_bytes();
+            let id = store
+                .store(content, &PasteOptions::default())
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        for (i, id) in ids.iter().enumerate() {
+            let paste = store.meta(id).await.unwrap().unwrap();
+            assert_eq!(paste.size, format!("paste {}", i).len() as i64);
+        }
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), 100);
+
+        let usage = store.id_usage().await.unwrap();
+        assert_eq!(usage.total(), 100);
+        assert!(usage.used(1) <= 15);
+        assert!(ids.iter().all(|id| id.len() == 1 || id.len() == 2));
+        assert_eq!(usage.length_for(1), 2);
+        assert_eq!(usage.report(1).lengths.len(), 2);
+    }
+
+    #[async_test]
+    async fn sqlite_store() {
+        let store = SqliteStore::open("file:sqlite_store?mode=memory&cache=shared", 1, 4, 100, 2)
+            .await
+            .unwrap();
+        check_store(&store).await;
+        check_links(&store).await;
+        check_duplicates(&store).await;
+
+        let tiny = SqliteStore::open("file:tiny_keyspace?mode=memory&cache=shared", 1, 1, 1000, 2)
+            .await
+            .unwrap();
+        check_tiny_keyspace(&tiny).await;
+    }
+
+    #[async_test]
+    async fn memory_store() {
+        check_store(&MemoryStore::new(4, 100, 2)).await;
+        check_links(&MemoryStore::new(4, 100, 2)).await;
+        check_duplicates(&MemoryStore::new(4, 100, 2)).await;
+        check_tiny_keyspace(&MemoryStore::new(1, 1000, 2)).await;
+    }
+
+    #[async_test]
+    async fn filesystem_store() {
+        let directory = TempDir::new().unwrap();
+        let store = FilesystemStore::open(directory.path().to_str().unwrap(), 4, 100, 2)
+            .await
+            .unwrap();
+        check_store(&store).await;
+        check_links(&store).await;
+        check_duplicates(&store).await;
+
+        let tiny_directory = TempDir::new().unwrap();
+        let tiny = FilesystemStore::open(tiny_directory.path().to_str().unwrap(), 1, 1000, 2)
+            .await
+            .unwrap();
+        check_tiny_keyspace(&tiny).await;
+
+        // One file for the content, one for the metadata, both easy to inspect
+        store
+            .store_with_id(
+                "abc".to_owned(),
+                b"hello".to_vec(),
+                &PasteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(directory.path().join("abc")).unwrap(),
+            b"hello"
+        );
+        let meta: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(directory.path().join("abc.json")).unwrap())
+                .unwrap();
+        assert_eq!(meta["size"], 5);
+
+        // Revisions are kept next to the paste, and deleted with it
+        store
+            .store_with_id(
+                "abc".to_owned(),
+                b"world".to_vec(),
+                &PasteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(directory.path().join("abc@1")).unwrap(),
+            b"hello"
+        );
+        assert!(store.delete("abc").await.unwrap());
+        assert!(!directory.path().join("abc@1.json").exists());
+
+        // With fewer revisions kept, every revision that is not kept anymore is removed
+        for content in ["one", "two", "three"] {
+            store
+                .store_with_id(
+                    "abc".to_owned(),
+                    content.as_bytes().to_vec(),
+                    &PasteOptions::default(),
+                )
+                .await
+                .unwrap();
+        }
+        let fewer = FilesystemStore::open(directory.path().to_str().unwrap(), 4, 100, 0)
+            .await
+            .unwrap();
+        fewer
+            .store_with_id("abc".to_owned(), b"four".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        assert!(!directory.path().join("abc@1").exists());
+        assert!(!directory.path().join("abc@2.json").exists());
+        assert!(!directory.path().join("abc@3").exists());
+        assert_eq!(
+            std::fs::read(directory.path().join("abc")).unwrap(),
+            b"four"
+        );
+
+        // A reader never sees the content of one revision with the metadata of another
+        let language = |language: &str| PasteOptions {
+            language: Some(language.to_owned()),
+            ..Default::default()
+        };
+        let writer = async {
+            for i in 0..20 {
+                let (content, options) = if i % 2 == 0 {
+                    (b"rust".to_vec(), language("rs"))
+                } else {
+                    (b"python".to_vec(), language("py"))
+                };
+                store
+                    .store_with_id("race".to_owned(), content, &options)
+                    .await
+                    .unwrap();
+            }
+        };
+        let reader = async {
+            for _ in 0..50 {
+                if let Some(paste) = store.get("race").await.unwrap() {
+                    let expected = match paste.text() {
+                        Some("rust") => "rs",
+                        _ => "py",
+                    };
+                    assert_eq!(paste.language.as_deref(), Some(expected));
+                }
+            }
+        };
+        rocket::tokio::join!(writer, reader);
+
+        // Ids never escape the directory
+        assert!(store
+            .store_with_id("..".to_owned(), b"".to_vec(), &PasteOptions::default())
+            .await
+            .is_err());
+        assert!(store.get("../abc").await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn open_from_config() {
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "A", "prefix": "/", "storage": "memory" }"#,
+        )
+        .unwrap();
+        let store = open(&config).await.unwrap();
+        let id = store
+            .store(b"data".to_vec(), &PasteOptions::default())
+            .await
+            .unwrap();
+        assert!(store.get(&id).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use rocket::tokio::fs;
+use rocket::tokio::sync::RwLock;
+
+use crate::ids::{self, IdUsage};
+use crate::io::{
+    IOError, ListFilter, Paste, PasteMeta, PasteOptions, PasteSummary, Revision, SearchResult,
+    ShareLink,
+};
+
+use super::{
+    is_expired, kept_revisions, may_serve_for, new_meta, paste_from_meta, revision_from_meta, scan,
+    select_page, summary, LinkRecord, PasteStore,
+};
+
+/// Extension of the file holding the metadata of a paste, next to the file holding its content
+const META_EXTENSION: &str = "json";
+
+/// Extension of the file holding the share links of a paste. With it, the name is not a valid id.
+const LINKS_EXTENSION: &str = "links.json";
+
+/// Pastes kept in a directory, one file per paste named after its id, with its metadata in a
+/// JSON file next to it. The directory can be inspected, backed up or synced with the usual tools,
+/// which is why the content is never compressed, unlike in the database.
+///
+/// The previous revisions of a paste are kept the same way, as `<id>@<revision>`. These names are
+/// not valid ids, so they are never mistaken for pastes.
+pub struct FilesystemStore {
+    directory: PathBuf,
+    id_length: usize,
+    max_entries: i32,
+    max_revisions: u32,
+    /// Held exclusively by the operations modifying the directory, so that they do not interleave,
+    /// and shared by the ones reading a paste: the content and the metadata of a paste are two
+    /// files, a reader must not see one revision of the first and another of the second.
+    lock: RwLock<()>,
+}
+
+/// Ids are used as file names: anything that could escape the directory, or be mistaken for a
+/// metadata or temporary file, is refused.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl FilesystemStore {
+    pub async fn open(
+        directory: &str,
+        id_length: usize,
+        max_entries: i32,
+        max_revisions: u32,
+    ) -> Result<FilesystemStore, IOError> {
+        fs::create_dir_all(directory).await?;
+        Ok(FilesystemStore {
+            directory: PathBuf::from(directory),
+            id_length,
+            max_entries,
+            max_revisions,
+            lock: RwLock::new(()),
+        })
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.directory.join(id)
+    }
+
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", id, META_EXTENSION))
+    }
+
+    fn links_path(&self, id: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", id, LINKS_EXTENSION))
+    }
+
+    /// Share links of a paste that have not expired, by id
+    async fn read_links(&self, id: &str) -> Result<HashMap<String, LinkRecord>, IOError> {
+        let mut links: HashMap<String, LinkRecord> = match fs::read(self.links_path(id)).await {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| IOError(format!("Invalid share links for {}: {}", id, e)))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        links.retain(|_, link| !link.is_expired());
+        Ok(links)
+    }
+
+    /// Must be called with the lock held
+    async fn write_links(
+        &self,
+        id: &str,
+        links: &HashMap<String, LinkRecord>,
+    ) -> Result<(), IOError> {
+        if links.is_empty() {
+            remove_if_exists(self.links_path(id)).await?;
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(links)
+            .map_err(|e| IOError(format!("Cannot serialize the share links: {}", e)))?;
+        self.write_file(self.links_path(id), &json).await
+    }
+
+    /// Metadata of a paste, expired or not. A paste exists as long as its metadata file does.
+    async fn read_meta(&self, id: &str) -> Result<Option<PasteMeta>, IOError> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        self.read_meta_file(self.meta_path(id), id).await
+    }
+
+    async fn read_meta_file(&self, path: PathBuf, id: &str) -> Result<Option<PasteMeta>, IOError> {
+        match fs::read(path).await {
+            Ok(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|e| IOError(format!("Invalid metadata for {}: {}", id, e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_data(&self, id: &str) -> Result<Option<Vec<u8>>, IOError> {
+        self.read_data_file(self.data_path(id)).await
+    }
+
+    async fn read_data_file(&self, path: PathBuf) -> Result<Option<Vec<u8>>, IOError> {
+        match fs::read(path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace a file atomically, readers see either the old or the new content
+    async fn write_file(&self, path: PathBuf, content: &[u8]) -> Result<(), IOError> {
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, content).await?;
+        fs::rename(&temporary, &path).await?;
+        Ok(())
+    }
+
+    async fn write_meta(&self, meta: &PasteMeta) -> Result<(), IOError> {
+        self.write_meta_file(self.meta_path(&meta.id), meta).await
+    }
+
+    async fn write_meta_file(&self, path: PathBuf, meta: &PasteMeta) -> Result<(), IOError> {
+        let json = serde_json::to_vec_pretty(meta)
+            .map_err(|e| IOError(format!("Cannot serialize the metadata: {}", e)))?;
+        self.write_file(path, &json).await
+    }
+
+    /// Name of the files of a previous revision of a paste
+    fn revision_name(id: &str, revision: u32) -> String {
+        format!("{}@{}", id, revision)
+    }
+
+    /// Keep a copy of the current revision of a paste. The paste itself is left in place until
+    /// the next revision replaces it. Must be called with the lock held.
+    async fn archive(&self, previous: &PasteMeta) -> Result<(), IOError> {
+        let name = Self::revision_name(&previous.id, previous.revision);
+        let path = self.data_path(&name);
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::copy(self.data_path(&previous.id), &temporary).await?;
+        fs::rename(&temporary, &path).await?;
+        self.write_meta_file(self.meta_path(&name), previous).await
+    }
+
+    /// Remove the revisions of a paste that are not kept once it is at this revision, including
+    /// the ones left over by a larger max_revisions. Must be called with the lock held.
+    async fn prune(&self, id: &str, current: u32) -> Result<(), IOError> {
+        for revision in 1..kept_revisions(current, self.max_revisions).start {
+            let name = Self::revision_name(id, revision);
+            remove_if_exists(self.meta_path(&name)).await?;
+            remove_if_exists(self.data_path(&name)).await?;
+        }
+        Ok(())
+    }
+
+    /// Must be called with the lock held
+    async fn write_paste(&self, content: &[u8], meta: &PasteMeta) -> Result<(), IOError> {
+        self.write_file(self.data_path(&meta.id), content).await?;
+        self.write_meta(meta).await
+    }
+
+    /// Must be called with the lock held
+    async fn remove(&self, id: &str) -> Result<bool, IOError> {
+        let meta = match self.read_meta(id).await? {
+            Some(meta) => meta,
+            None => return Ok(false),
+        };
+        // Without its metadata the paste does not exist anymore, remove it first
+        if !remove_if_exists(self.meta_path(id)).await? {
+            return Ok(false);
+        }
+        remove_if_exists(self.data_path(id)).await?;
+        remove_if_exists(self.links_path(id)).await?;
+        for revision in 1..meta.revision {
+            let name = Self::revision_name(id, revision);
+            remove_if_exists(self.meta_path(&name)).await?;
+            remove_if_exists(self.data_path(&name)).await?;
+        }
+        Ok(true)
+    }
+
+    /// Id of every paste in the directory, expired or not
+    async fn all_ids(&self) -> Result<Vec<String>, IOError> {
+        let mut result = Vec::new();
+        let mut entries = fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let id = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(META_EXTENSION))
+                .and_then(|name| name.strip_suffix('.'));
+            match id {
+                // Revisions are not valid ids
+                Some(id) if is_valid_id(id) => result.push(id.to_owned()),
+                _ => continue,
+            }
+        }
+        Ok(result)
+    }
+
+    /// Metadata of every paste in the directory, expired or not
+    async fn all_meta(&self) -> Result<Vec<PasteMeta>, IOError> {
+        let mut result = Vec::new();
+        for id in self.all_ids().await? {
+            if let Some(meta) = self.read_meta(&id).await? {
+                result.push(meta);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Must be called with the lock held
+    async fn remove_old(&self) -> Result<usize, IOError> {
+        let mut metas = self.all_meta().await?;
+        let excess = metas
+            .len()
+            .saturating_sub(usize::try_from(self.max_entries).unwrap_or(0));
+        metas.sort_unstable_by_key(|meta| meta.created_at);
+        for meta in metas.iter().take(excess) {
+            self.remove(&meta.id).await?;
+        }
+        Ok(excess)
+    }
+}
+
+#[rocket::async_trait]
+impl PasteStore for FilesystemStore {
+    async fn store(&self, content: Vec<u8>, options: &PasteOptions) -> Result<String, IOError> {
+        let _lock = self.lock.write().await;
+        let taken = self.all_ids().await?;
+        let usage: IdUsage = taken.iter().map(String::as_str).collect();
+        // The directory is read anyway to count the pastes against max_entries
+        for id in ids::candidates(options, usage.length_for(self.id_length)) {
+            if self.read_meta(&id).await?.is_none() {
+                self.write_paste(&content, &new_meta(&id, &content, options, None))
+                    .await?;
+                if usage.total() >= u64::try_from(self.max_entries).unwrap_or(0) {
+                    let removed = self.remove_old().await?;
+                    info!("[ID] Removed {} entries over max_entries", removed);
+                }
+                return Ok(id);
+            }
+        }
+        Err(IOError("No free id left".to_owned()))
+    }
+
+    async fn store_with_id(
+        &self,
+        id: String,
+        content: Vec<u8>,
+        options: &PasteOptions,
+    ) -> Result<String, IOError> {
+        if !is_valid_id(&id) {
+            return Err(IOError(format!("Invalid id: {:?}", id)));
+        }
+        let _lock = self.lock.write().await;
+        let previous = match self.read_meta(&id).await? {
+            Some(meta) if !is_expired(&meta) => Some(meta),
+            Some(_) => {
+                // Expired, but not evicted yet: its revisions go with it
+                self.remove(&id).await?;
+                None
+            }
+            None => None,
+        };
+        if let Some(previous) = &previous {
+            if self.max_revisions > 0 {
+                self.archive(previous).await?;
+            }
+            self.prune(&id, previous.revision + 1).await?;
+        }
+        // Each file is replaced through a temporary one, the paste is never left without content
+        self.write_paste(
+            &content,
+            &new_meta(&id, &content, options, previous.as_ref()),
+        )
+        .await?;
+        Ok(id)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Paste>, IOError> {
+        {
+            let _lock = self.lock.read().await;
+            let meta = match self.read_meta(id).await? {
+                Some(meta) if !is_expired(&meta) => meta,
+                _ => return Ok(None),
+            };
+            if meta.views_left.is_none() {
+                return Ok(self
+                    .read_data(id)
+                    .await?
+                    .map(|data| paste_from_meta(data, &meta)));
+            }
+        }
+
+        // Read the view counter again under the lock: concurrent readers must not see more
+        // views than allowed
+        let _lock = self.lock.write().await;
+        let mut meta = match self.read_meta(id).await? {
+            Some(meta) if !is_expired(&meta) => meta,
+            _ => return Ok(None),
+        };
+        let data = match self.read_data(id).await? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        match meta.views_left {
+            Some(0) => return Ok(None),
+            Some(1) => {
+                info!("[READ_PASTE] Last view of {}, deleting it", id);
+                self.remove(id).await?;
+            }
+            Some(views) => {
+                meta.views_left = Some(views - 1);
+                self.write_meta(&meta).await?;
+            }
+            None => (),
+        }
+        Ok(Some(paste_from_meta(data, &meta)))
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<PasteMeta>, IOError> {
+        Ok(self.read_meta(id).await?.filter(|meta| !is_expired(meta)))
+    }
+
+    async fn history(&self, id: &str) -> Result<Option<Vec<Revision>>, IOError> {
+        let _lock = self.lock.read().await;
+        let current = match self.meta(id).await? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        let mut history = Vec::new();
+        for revision in kept_revisions(current.revision, self.max_revisions) {
+            let path = self.meta_path(&Self::revision_name(id, revision));
+            if let Some(meta) = self.read_meta_file(path, id).await? {
+                history.push(revision_from_meta(&meta));
+            }
+        }
+        history.push(revision_from_meta(&current));
+        Ok(Some(history))
+    }
+
+    async fn get_revision(&self, id: &str, revision: u32) -> Result<Option<Paste>, IOError> {
+        let lock = self.lock.read().await;
+        let current = match self.meta(id).await? {
+            Some(meta) => meta.revision,
+            None => return Ok(None),
+        };
+        if revision == current {
+            // get takes the lock itself
+            drop(lock);
+            return self.get(id).await;
+        }
+        if !kept_revisions(current, self.max_revisions).contains(&revision) {
+            return Ok(None);
+        }
+        let name = Self::revision_name(id, revision);
+        let meta = match self.read_meta_file(self.meta_path(&name), id).await? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        Ok(self
+            .read_data_file(self.data_path(&name))
+            .await?
+            .map(|data| paste_from_meta(data, &meta)))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, IOError> {
+        if !is_valid_id(id) {
+            return Ok(false);
+        }
+        let _lock = self.lock.write().await;
+        self.remove(id).await
+    }
+
+    async fn list(&self) -> Result<Vec<(String, String)>, IOError> {
+        let _lock = self.lock.read().await;
+        let mut result = Vec::new();
+        for meta in self.all_meta().await? {
+            if is_expired(&meta) || meta.private || meta.is_guarded() {
+                continue;
+            }
+            if let Some(data) = self.read_data(&meta.id).await? {
+                result.push((meta.id, String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+        Ok(result)
+    }
+
+    async fn list_page(&self, filter: &ListFilter) -> Result<Vec<PasteSummary>, IOError> {
+        let _lock = self.lock.read().await;
+        let mut result = Vec::new();
+        for meta in select_page(self.all_meta().await?.into_iter(), filter) {
+            if let Some(data) = self.read_data(&meta.id).await? {
+                result.push(summary(meta, &data));
+            }
+        }
+        Ok(result)
+    }
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>, IOError> {
+        let _lock = self.lock.read().await;
+        let mut metas = self.all_meta().await?;
+        // Most recent first
+        metas.sort_unstable_by_key(|meta| std::cmp::Reverse(meta.updated_at));
+        let mut results = Vec::new();
+        for meta in metas {
+            if results.len() >= limit as usize {
+                break;
+            }
+            if is_expired(&meta) || meta.private || meta.is_guarded() {
+                continue;
+            }
+            if let Some(data) = self.read_data(&meta.id).await? {
+                results.extend(scan(&meta.id, &data, query));
+            }
+        }
+        Ok(results)
+    }
+
+    async fn evict(&self) -> Result<u64, IOError> {
+        let _lock = self.lock.write().await;
+        let mut removed = 0;
+        for meta in self.all_meta().await? {
+            if is_expired(&meta) && self.remove(&meta.id).await? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn id_usage(&self) -> Result<IdUsage, IOError> {
+        let ids = self.all_ids().await?;
+        Ok(ids.iter().map(String::as_str).collect())
+    }
+
+    async fn add_link(&self, link: &ShareLink) -> Result<(), IOError> {
+        if !is_valid_id(&link.paste_id) {
+            return Err(IOError(format!("Invalid id: {:?}", link.paste_id)));
+        }
+        let _lock = self.lock.write().await;
+        if self.read_meta(&link.paste_id).await?.is_none() {
+            return Err(IOError(format!("No paste {}", link.paste_id)));
+        }
+        let mut links = self.read_links(&link.paste_id).await?;
+        links.insert(link.id.clone(), LinkRecord::new(link));
+        self.write_links(&link.paste_id, &links).await
+    }
+
+    async fn use_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        if !is_valid_id(paste_id) {
+            return Ok(false);
+        }
+        let _lock = self.lock.write().await;
+        let mut links = self.read_links(paste_id).await?;
+        let used = links.get_mut(id).is_some_and(LinkRecord::use_view);
+        if used {
+            self.write_links(paste_id, &links).await?;
+        }
+        Ok(used)
+    }
+
+    async fn revoke_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        if !is_valid_id(paste_id) {
+            return Ok(false);
+        }
+        let _lock = self.lock.write().await;
+        let mut links = self.read_links(paste_id).await?;
+        if links.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.write_links(paste_id, &links).await?;
+        Ok(true)
+    }
+
+    async fn find_duplicate(
+        &self,
+        content: &[u8],
+        options: &PasteOptions,
+    ) -> Result<Option<String>, IOError> {
+        let _lock = self.lock.read().await;
+        let mut metas = self.all_meta().await?;
+        metas.retain(|meta| may_serve_for(meta, content.len(), options));
+        // Most recent first, only the pastes of the same size are read
+        metas.sort_unstable_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+        for meta in metas {
+            if self.read_data(&meta.id).await?.as_deref() == Some(content) {
+                return Ok(Some(meta.id));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Returns false if there was no such file
+async fn remove_if_exists(path: PathBuf) -> Result<bool, IOError> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
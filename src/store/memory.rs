@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use crate::ids::{self, IdUsage, UsageCache};
+use crate::io::{
+    IOError, ListFilter, Paste, PasteMeta, PasteOptions, PasteSummary, Revision, SearchResult,
+    ShareLink,
+};
+
+use super::{
+    is_expired, may_serve_for, new_meta, paste_from_meta, revision_from_meta, scan, select_page,
+    summary, LinkRecord, PasteStore,
+};
+
+struct Entry {
+    data: Vec<u8>,
+    meta: PasteMeta,
+    /// Insertion order, the oldest entries are removed first when the store is full
+    sequence: u64,
+    /// Content and metadata of the previous revisions, oldest first
+    revisions: Vec<(Vec<u8>, PasteMeta)>,
+    /// Share links, by id
+    links: HashMap<String, LinkRecord>,
+}
+
+#[derive(Default)]
+struct Entries {
+    pastes: HashMap<String, Entry>,
+    next_sequence: u64,
+}
+
+impl Entries {
+    fn insert(
+        &mut self,
+        id: &str,
+        content: Vec<u8>,
+        meta: PasteMeta,
+        revisions: Vec<(Vec<u8>, PasteMeta)>,
+        links: HashMap<String, LinkRecord>,
+    ) {
+        self.next_sequence += 1;
+        self.pastes.insert(
+            id.to_owned(),
+            Entry {
+                data: content,
+                meta,
+                sequence: self.next_sequence,
+                revisions,
+                links,
+            },
+        );
+    }
+
+    /// The entry under this id, if it has not expired
+    fn alive(&self, id: &str) -> Option<&Entry> {
+        self.pastes.get(id).filter(|entry| !is_expired(&entry.meta))
+    }
+
+    fn remove_old(&mut self, max_entries: usize) -> usize {
+        let excess = self.pastes.len().saturating_sub(max_entries);
+        let mut sequences: Vec<(u64, String)> = self
+            .pastes
+            .iter()
+            .map(|(id, entry)| (entry.sequence, id.clone()))
+            .collect();
+        sequences.sort_unstable();
+        for (_, id) in sequences.iter().take(excess) {
+            self.pastes.remove(id);
+        }
+        excess
+    }
+}
+
+/// Pastes kept in memory, lost when bibin stops. Mostly useful for tests.
+pub struct MemoryStore {
+    id_length: usize,
+    max_entries: usize,
+    max_revisions: usize,
+    entries: Mutex<Entries>,
+    taken: UsageCache,
+}
+
+impl MemoryStore {
+    pub fn new(id_length: usize, max_entries: i32, max_revisions: u32) -> MemoryStore {
+        MemoryStore {
+            id_length,
+            max_entries: usize::try_from(max_entries).unwrap_or(0),
+            max_revisions: usize::try_from(max_revisions).unwrap_or(usize::MAX),
+            entries: Mutex::new(Entries::default()),
+            taken: UsageCache::default(),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // The entries are always left in a consistent state, even by a panicking thread
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[rocket::async_trait]
+impl PasteStore for MemoryStore {
+    async fn store(&self, content: Vec<u8>, options: &PasteOptions) -> Result<String, IOError> {
+        let mut entries = self.entries();
+        let usage = match self.taken.get() {
+            Some(usage) => usage,
+            None => {
+                let usage: IdUsage = entries.pastes.keys().map(String::as_str).collect();
+                self.taken.set(usage.clone());
+                usage
+            }
+        };
+        for id in ids::candidates(options, usage.length_for(self.id_length)) {
+            if !entries.pastes.contains_key(&id) {
+                self.taken.add(&id);
+                let meta = new_meta(&id, &content, options, None);
+                entries.insert(&id, content, meta, Vec::new(), HashMap::new());
+                let removed = entries.remove_old(self.max_entries);
+                if removed > 0 {
+                    info!("[ID] Removed {} entries over max_entries", removed);
+                }
+                return Ok(id);
+            }
+            self.taken.forget();
+        }
+        Err(IOError("No free id left".to_owned()))
+    }
+
+    async fn store_with_id(
+        &self,
+        id: String,
+        content: Vec<u8>,
+        options: &PasteOptions,
+    ) -> Result<String, IOError> {
+        let mut entries = self.entries();
+        let previous = match entries.pastes.remove(&id) {
+            Some(entry) if !is_expired(&entry.meta) => Some(entry),
+            _ => None,
+        };
+        let meta = new_meta(&id, &content, options, previous.as_ref().map(|e| &e.meta));
+        let mut revisions = Vec::new();
+        let mut links = HashMap::new();
+        if let Some(previous) = previous {
+            revisions = previous.revisions;
+            revisions.push((previous.data, previous.meta));
+            let excess = revisions.len().saturating_sub(self.max_revisions);
+            revisions.drain(..excess);
+            links = previous.links;
+        }
+        entries.insert(&id, content, meta, revisions, links);
+        Ok(id)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Paste>, IOError> {
+        let mut entries = self.entries();
+        let views_left = match entries.alive(id) {
+            None => return Ok(None),
+            Some(entry) => entry.meta.views_left,
+        };
+
+        match views_left {
+            None => Ok(entries
+                .alive(id)
+                .map(|e| paste_from_meta(e.data.clone(), &e.meta))),
+            Some(0) => Ok(None),
+            Some(1) => {
+                info!("[READ_PASTE] Last view of {}, deleting it", id);
+                Ok(entries
+                    .pastes
+                    .remove(id)
+                    .map(|e| paste_from_meta(e.data, &e.meta)))
+            }
+            Some(views) => Ok(entries.pastes.get_mut(id).map(|entry| {
+                entry.meta.views_left = Some(views - 1);
+                paste_from_meta(entry.data.clone(), &entry.meta)
+            })),
+        }
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<PasteMeta>, IOError> {
+        Ok(self.entries().alive(id).map(|entry| entry.meta.clone()))
+    }
+
+    async fn history(&self, id: &str) -> Result<Option<Vec<Revision>>, IOError> {
+        Ok(self.entries().alive(id).map(|entry| {
+            entry
+                .revisions
+                .iter()
+                .map(|(_, meta)| meta)
+                .chain(std::iter::once(&entry.meta))
+                .map(revision_from_meta)
+                .collect()
+        }))
+    }
+
+    async fn get_revision(&self, id: &str, revision: u32) -> Result<Option<Paste>, IOError> {
+        let current = match self.entries().alive(id) {
+            None => return Ok(None),
+            Some(entry) => entry.meta.revision,
+        };
+        if revision == current {
+            return self.get(id).await;
+        }
+        Ok(self.entries().alive(id).and_then(|entry| {
+            entry
+                .revisions
+                .iter()
+                .find(|(_, meta)| meta.revision == revision)
+                .map(|(data, meta)| paste_from_meta(data.clone(), meta))
+        }))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, IOError> {
+        Ok(self.entries().pastes.remove(id).is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<(String, String)>, IOError> {
+        Ok(self
+            .entries()
+            .pastes
+            .iter()
+            .filter(|(_, entry)| {
+                !is_expired(&entry.meta) && !entry.meta.private && !entry.meta.is_guarded()
+            })
+            .map(|(id, entry)| {
+                (
+                    id.clone(),
+                    String::from_utf8_lossy(&entry.data).into_owned(),
+                )
+            })
+            .collect())
+    }
+
+    async fn list_page(&self, filter: &ListFilter) -> Result<Vec<PasteSummary>, IOError> {
+        let entries = self.entries();
+        let page = select_page(
+            entries.pastes.values().map(|entry| entry.meta.clone()),
+            filter,
+        );
+        Ok(page
+            .into_iter()
+            .map(|meta| {
+                let data = &entries.pastes[&meta.id].data;
+                summary(meta, data)
+            })
+            .collect())
+    }
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>, IOError> {
+        let entries = self.entries();
+        let mut alive: Vec<_> = entries
+            .pastes
+            .iter()
+            .filter(|(_, entry)| {
+                !is_expired(&entry.meta) && !entry.meta.private && !entry.meta.is_guarded()
+            })
+            .collect();
+        // Most recent first
+        alive.sort_unstable_by_key(|(_, entry)| std::cmp::Reverse(entry.sequence));
+        Ok(alive
+            .into_iter()
+            .filter_map(|(id, entry)| scan(id, &entry.data, query))
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn evict(&self) -> Result<u64, IOError> {
+        let mut entries = self.entries();
+        let before = entries.pastes.len();
+        entries.pastes.retain(|_, entry| !is_expired(&entry.meta));
+        for entry in entries.pastes.values_mut() {
+            entry.links.retain(|_, link| !link.is_expired());
+        }
+        Ok((before - entries.pastes.len()) as u64)
+    }
+
+    async fn id_usage(&self) -> Result<IdUsage, IOError> {
+        Ok(self.entries().pastes.keys().map(String::as_str).collect())
+    }
+
+    async fn add_link(&self, link: &ShareLink) -> Result<(), IOError> {
+        match self.entries().pastes.get_mut(&link.paste_id) {
+            Some(entry) => {
+                entry.links.insert(link.id.clone(), LinkRecord::new(link));
+                Ok(())
+            }
+            None => Err(IOError(format!("No paste {}", link.paste_id))),
+        }
+    }
+
+    async fn use_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        Ok(self
+            .entries()
+            .pastes
+            .get_mut(paste_id)
+            .and_then(|entry| entry.links.get_mut(id))
+            .is_some_and(LinkRecord::use_view))
+    }
+
+    async fn revoke_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        Ok(self
+            .entries()
+            .pastes
+            .get_mut(paste_id)
+            .and_then(|entry| entry.links.remove(id))
+            .is_some())
+    }
+
+    async fn find_duplicate(
+        &self,
+        content: &[u8],
+        options: &PasteOptions,
+    ) -> Result<Option<String>, IOError> {
+        Ok(self
+            .entries()
+            .pastes
+            .values()
+            .filter(|entry| may_serve_for(&entry.meta, content.len(), options))
+            .filter(|entry| entry.data == content)
+            .max_by_key(|entry| entry.sequence)
+            .map(|entry| entry.meta.id.clone()))
+    }
+}
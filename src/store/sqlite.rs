@@ -0,0 +1,132 @@
+use crate::io::{
+    add_link, delete_paste, find_duplicate, get_all_paste, get_history, get_paste_meta,
+    get_revision, id_usage, list_pastes, purge_expired, read_paste, revoke_link, search,
+    store_paste, store_paste_given_id, use_link, IOError, ListFilter, Paste, PasteMeta,
+    PasteOptions, PasteSummary, ReadPool, Revision, SearchResult, ShareLink, WritePool,
+};
+
+use crate::ids::IdUsage;
+
+use super::PasteStore;
+
+/// Pastes kept in a SQLite database: a single connection for the writes, a pool of read-only
+/// connections for the reads.
+pub struct SqliteStore {
+    write_pool: WritePool,
+    read_pool: ReadPool,
+    id_length: usize,
+    max_entries: i32,
+    max_revisions: u32,
+}
+
+impl SqliteStore {
+    pub async fn open(
+        file_name: &str,
+        max_connections: u32,
+        id_length: usize,
+        max_entries: i32,
+        max_revisions: u32,
+    ) -> Result<SqliteStore, IOError> {
+        let write_pool = WritePool::new(file_name).await?;
+        // The schema must be up to date before the read-only connections are opened
+        write_pool.init().await?;
+        let read_pool = ReadPool::new(file_name, max_connections).await?;
+
+        Ok(SqliteStore {
+            write_pool,
+            read_pool,
+            id_length,
+            max_entries,
+            max_revisions,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl PasteStore for SqliteStore {
+    async fn store(&self, content: Vec<u8>, options: &PasteOptions) -> Result<String, IOError> {
+        store_paste(
+            &self.write_pool,
+            self.id_length,
+            self.max_entries,
+            content,
+            options,
+        )
+        .await
+    }
+
+    async fn store_with_id(
+        &self,
+        id: String,
+        content: Vec<u8>,
+        options: &PasteOptions,
+    ) -> Result<String, IOError> {
+        store_paste_given_id(&self.write_pool, id, content, options, self.max_revisions).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Paste>, IOError> {
+        read_paste(&self.read_pool, &self.write_pool, id).await
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<PasteMeta>, IOError> {
+        get_paste_meta(&self.read_pool, id).await
+    }
+
+    async fn history(&self, id: &str) -> Result<Option<Vec<Revision>>, IOError> {
+        get_history(&self.read_pool, id).await
+    }
+
+    async fn get_revision(&self, id: &str, revision: u32) -> Result<Option<Paste>, IOError> {
+        if let Some(paste) = get_revision(&self.read_pool, id, revision).await? {
+            return Ok(Some(paste));
+        }
+        match self.meta(id).await? {
+            Some(meta) if meta.revision == revision => self.get(id).await,
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, IOError> {
+        delete_paste(&self.write_pool, id).await
+    }
+
+    async fn list(&self) -> Result<Vec<(String, String)>, IOError> {
+        get_all_paste(&self.read_pool).await
+    }
+
+    async fn list_page(&self, filter: &ListFilter) -> Result<Vec<PasteSummary>, IOError> {
+        list_pastes(&self.read_pool, filter).await
+    }
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>, IOError> {
+        search(&self.read_pool, query, limit).await
+    }
+
+    async fn evict(&self) -> Result<u64, IOError> {
+        purge_expired(&self.write_pool).await
+    }
+
+    async fn id_usage(&self) -> Result<IdUsage, IOError> {
+        id_usage(&self.read_pool).await
+    }
+
+    async fn add_link(&self, link: &ShareLink) -> Result<(), IOError> {
+        add_link(&self.write_pool, link).await
+    }
+
+    async fn use_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        use_link(&self.write_pool, paste_id, id).await
+    }
+
+    async fn revoke_link(&self, paste_id: &str, id: &str) -> Result<bool, IOError> {
+        revoke_link(&self.write_pool, paste_id, id).await
+    }
+
+    async fn find_duplicate(
+        &self,
+        content: &[u8],
+        options: &PasteOptions,
+    ) -> Result<Option<String>, IOError> {
+        find_duplicate(&self.read_pool, content, options).await
+    }
+}
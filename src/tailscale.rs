@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::http::RawStr;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::UnixStream;
+use rocket::tokio::time::timeout;
+use serde::Deserialize;
+
+use crate::auth::TailscaleLogin;
+
+/// How long the answers of tailscaled are reused
+const WHOIS_CACHE_DURATION: Duration = Duration::from_secs(30);
+/// tailscaled answers from memory, a slow answer means it is stuck
+const WHOIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WhoIs {
+    node: Node,
+    user_profile: Option<UserProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    name: String,
+    /// Null for the nodes of a user
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct UserProfile {
+    login_name: String,
+}
+
+impl WhoIs {
+    /// Tagged nodes do not belong to a user: they are known by their name and their tags
+    fn into_login(self) -> Option<TailscaleLogin> {
+        match self.node.tags {
+            Some(tags) if !tags.is_empty() => Some(TailscaleLogin::new(
+                self.node.name.trim_end_matches('.').to_owned(),
+                tags,
+            )),
+            _ => self
+                .user_profile
+                .map(|profile| TailscaleLogin::new(profile.login_name, Vec::new())),
+        }
+    }
+}
+
+/// Client of the LocalAPI of tailscaled, to know which tailnet member is connecting
+pub struct LocalApi {
+    socket: PathBuf,
+    cache: Mutex<HashMap<IpAddr, (Instant, Option<TailscaleLogin>)>>,
+}
+
+impl LocalApi {
+    pub fn new(socket: impl Into<PathBuf>) -> LocalApi {
+        LocalApi {
+            socket: socket.into(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tailnet member or tagged node behind this address. None for the other peers, and when
+    /// tailscaled cannot be reached.
+    pub async fn whois(&self, remote: SocketAddr) -> Option<TailscaleLogin> {
+        let ip = remote.ip().to_canonical();
+        if let Some((at, login)) = self.cache().get(&ip) {
+            if at.elapsed() < WHOIS_CACHE_DURATION {
+                return login.clone();
+            }
+        }
+
+        let login = match timeout(WHOIS_TIMEOUT, self.query(remote)).await {
+            Ok(Ok(login)) => login,
+            // Not cached, tailscaled may be restarting
+            Ok(Err(e)) => {
+                warn!("[TAILSCALE] WhoIs of {} failed: {}", remote, e);
+                return None;
+            }
+            Err(_) => {
+                warn!("[TAILSCALE] WhoIs of {} timed out", remote);
+                return None;
+            }
+        };
+
+        let mut cache = self.cache();
+        cache.retain(|_, (at, _)| at.elapsed() < WHOIS_CACHE_DURATION);
+        cache.insert(ip, (Instant::now(), login.clone()));
+        login
+    }
+
+    fn cache(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<IpAddr, (Instant, Option<TailscaleLogin>)>> {
+        // A cache is always consistent, even if a thread panicked while holding it
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn query(&self, remote: SocketAddr) -> std::io::Result<Option<TailscaleLogin>> {
+        let mut stream = UnixStream::connect(&self.socket).await?;
+        // HTTP/1.0 so that the answer is neither chunked nor kept alive
+        let request = format!(
+            "GET /localapi/v0/whois?addr={} HTTP/1.0\r\nHost: local-tailscaled.sock\r\n\r\n",
+            RawStr::new(&remote.to_string()).percent_encode()
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_owned());
+        let response = String::from_utf8(response).map_err(|_| invalid("Not UTF-8"))?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| invalid("No end of the headers"))?;
+        let status = head.split(' ').nth(1).ok_or_else(|| invalid("No status"))?;
+        match status {
+            "200" => {
+                let whois: WhoIs = serde_json::from_str(body)
+                    .map_err(|e| invalid(&format!("Invalid WhoIs: {}", e)))?;
+                Ok(whois.into_login())
+            }
+            // Not a peer of the tailnet
+            "404" => Ok(None),
+            _ => Err(invalid(&format!("Status {}: {}", status, body.trim()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::UnixListener;
+    use rocket::State;
+    use tempfile::TempDir;
+
+    use super::LocalApi;
+    use crate::auth::Credentials;
+    use crate::config::BibinConfig;
+
+    const ALICE: &str = r#"{
+        "Node": { "Name": "laptop.tail1234.ts.net.", "Tags": null },
+        "UserProfile": { "LoginName": "alice@example.com", "DisplayName": "Alice" }
+    }"#;
+    const CI: &str = r#"{
+        "Node": { "Name": "ci-runner.tail1234.ts.net.", "Tags": ["tag:ci"] },
+        "UserProfile": { "LoginName": "tagged-devices" }
+    }"#;
+
+    /// Fake LocalAPI on a temporary Unix socket: 100.64.0.1 is alice, 100.64.0.2 a tagged CI
+    /// runner, the other peers are unknown. Returns the number of requests served.
+    fn fake_local_api(dir: &TempDir) -> (std::path::PathBuf, Arc<AtomicUsize>) {
+        let path = dir.path().join("tailscaled.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        rocket::tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                served.fetch_add(1, Ordering::SeqCst);
+                let mut buffer = vec![0; 1024];
+                let read = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).into_owned();
+                let (status, body) = if request.contains("addr=100.64.0.1:") {
+                    ("200 OK", ALICE)
+                } else if request.contains("addr=100.64.0.2:") {
+                    ("200 OK", CI)
+                } else {
+                    ("404 Not Found", "no match for IP:port\n")
+                };
+                let response = format!(
+                    "HTTP/1.0 {}\r\nContent-Type: application/json\r\n\r\n{}",
+                    status, body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (path, requests)
+    }
+
+    #[rocket::async_test]
+    async fn test_whois() {
+        let dir = TempDir::new().unwrap();
+        let (path, requests) = fake_local_api(&dir);
+        let local_api = LocalApi::new(path);
+
+        let alice = local_api
+            .whois("100.64.0.1:50000".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(alice.login(), "alice@example.com");
+        let ci = local_api
+            .whois("100.64.0.2:50000".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(ci.login(), "ci-runner.tail1234.ts.net");
+        assert!(local_api
+            .whois("100.64.0.3:50000".parse().unwrap())
+            .await
+            .is_none());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // Cached, whatever the port, even for the unknown peers
+        local_api
+            .whois("100.64.0.1:50001".parse().unwrap())
+            .await
+            .unwrap();
+        local_api.whois("100.64.0.3:50001".parse().unwrap()).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // Without tailscaled
+        let local_api = LocalApi::new(dir.path().join("missing.sock"));
+        assert!(local_api
+            .whois("100.64.0.1:50000".parse().unwrap())
+            .await
+            .is_none());
+    }
+
+    /// Dummy endpoint that returns the identity of the request
+    #[get("/tests/identity")]
+    async fn tests_identity(credentials: Credentials, config: &State<BibinConfig>) -> String {
+        match credentials.identify(config).await {
+            Some(identity) => format!("{:?}", identity),
+            None => "unknown".to_owned(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_whois_identity() {
+        let dir = TempDir::new().unwrap();
+        let (path, _) = fake_local_api(&dir);
+        let config = serde_json::from_str::<BibinConfig>(
+            r#"{ "password": "main", "prefix": "/", "tailscale_users": [
+                { "login": "alice@example.com", "scopes": ["create", "list"] },
+                { "login": "tag:ci", "scopes": ["create"] }
+            ] }"#,
+        )
+        .unwrap();
+        let rocket = rocket::build()
+            .manage(config)
+            .manage(LocalApi::new(path))
+            .mount("/", routes![tests_identity]);
+        let client = Client::untracked(rocket).await.unwrap();
+
+        let identity = |remote: &str| {
+            client
+                .get("/tests/identity")
+                .remote(remote.parse().unwrap())
+                .dispatch()
+        };
+
+        let response = identity("100.64.0.1:50000").await;
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"Identity { name: Some("alice@example.com"), owner: Some("tailscale:alice@example.com"), scopes: [Create, List], private: false }"#
+        );
+        let response = identity("100.64.0.2:50000").await;
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"Identity { name: Some("ci-runner.tail1234.ts.net"), owner: Some("tailscale:ci-runner.tail1234.ts.net"), scopes: [Create], private: false }"#
+        );
+        let response = identity("100.64.0.3:50000").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
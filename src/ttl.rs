@@ -0,0 +1,134 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rocket::form;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use serde::{Deserialize, Deserializer};
+
+/// Lifetime of a paste.
+///
+/// Parsed from a number followed by an optional unit: `s` (default), `m`, `h`, `d` or `w`,
+/// so `90`, `30m`, `1h` and `7d` are all valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ttl(pub Duration);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TtlError(String);
+
+impl Display for TtlError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "Invalid TTL: {}", self.0)
+    }
+}
+
+impl FromStr for Ttl {
+    type Err = TtlError;
+
+    fn from_str(value: &str) -> Result<Ttl, TtlError> {
+        let value = value.trim();
+        let split = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (amount, unit) = value.split_at(split);
+
+        let amount: u64 = amount.parse().map_err(|_| TtlError(value.to_owned()))?;
+        let multiplier = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(TtlError(value.to_owned())),
+        };
+
+        amount
+            .checked_mul(multiplier)
+            .map(|secs| Ttl(Duration::from_secs(secs)))
+            .ok_or_else(|| TtlError(value.to_owned()))
+    }
+}
+
+impl<'v> rocket::form::FromFormField<'v> for Ttl {
+    fn from_value(value: form::ValueField<'v>) -> form::Result<'v, Self> {
+        value
+            .value
+            .parse()
+            .map_err(|e: TtlError| form::Error::validation(e.to_string()).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for Ttl {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Ttl, D::Error> {
+        // Environment variables such as ROCKET_MAX_TTL=3600 are parsed as integers
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawTtl {
+            Seconds(u64),
+            Text(String),
+        }
+
+        match RawTtl::deserialize(deserializer)? {
+            RawTtl::Seconds(secs) => Ok(Ttl(Duration::from_secs(secs))),
+            RawTtl::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// TTL requested by an API client with the `X-Expire` header.
+///
+/// A missing header is not an error, an invalid one is rejected with a 400.
+pub struct ExpireHeader(pub Option<Ttl>);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ExpireHeader {
+    type Error = TtlError;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Expire").map(str::parse) {
+            None => Outcome::Success(ExpireHeader(None)),
+            Some(Ok(ttl)) => Outcome::Success(ExpireHeader(Some(ttl))),
+            Some(Err(e)) => {
+                warn!("[TTL] {}", e);
+                Outcome::Error((Status::BadRequest, e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ttl;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_ttl() {
+        assert_eq!("90".parse(), Ok(Ttl(Duration::from_secs(90))));
+        assert_eq!("90s".parse(), Ok(Ttl(Duration::from_secs(90))));
+        assert_eq!("30m".parse(), Ok(Ttl(Duration::from_secs(30 * 60))));
+        assert_eq!("1h".parse(), Ok(Ttl(Duration::from_secs(3600))));
+        assert_eq!("7d".parse(), Ok(Ttl(Duration::from_secs(7 * 86400))));
+        assert_eq!("2w".parse(), Ok(Ttl(Duration::from_secs(14 * 86400))));
+
+        assert!("".parse::<Ttl>().is_err());
+        assert!("h".parse::<Ttl>().is_err());
+        assert!("1y".parse::<Ttl>().is_err());
+        assert!("-1h".parse::<Ttl>().is_err());
+        assert!("99999999999999999999w".parse::<Ttl>().is_err());
+    }
+
+    #[test]
+    fn deserialize_ttl() {
+        assert_eq!(
+            serde_json::from_str::<Ttl>("3600").unwrap(),
+            Ttl(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            serde_json::from_str::<Ttl>(r#""1d""#).unwrap(),
+            Ttl(Duration::from_secs(86400))
+        );
+        assert!(serde_json::from_str::<Ttl>(r#""soon""#).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+/// View limit requested by an API client.
+///
+/// `X-Burn-After-Reading` deletes the paste after its first view, `X-Max-Views: N` after N views.
+/// Missing headers are not an error, invalid ones are rejected with a 400.
+pub struct ViewLimitHeader(pub Option<u32>);
+
+#[derive(Debug)]
+pub struct ViewLimitError;
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ViewLimitHeader {
+    type Error = ViewLimitError;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        if request.headers().contains("X-Burn-After-Reading") {
+            return Outcome::Success(ViewLimitHeader(Some(1)));
+        }
+
+        match request.headers().get_one("X-Max-Views").map(str::parse) {
+            None => Outcome::Success(ViewLimitHeader(None)),
+            Some(Ok(views)) if views > 0 => Outcome::Success(ViewLimitHeader(Some(views))),
+            _ => {
+                warn!("[VIEW_LIMIT] Invalid X-Max-Views header");
+                Outcome::Error((Status::BadRequest, ViewLimitError))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket;
+    use rocket::http::Header;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    use super::ViewLimitHeader;
+
+    #[get("/tests/viewlimit")]
+    fn tests_viewlimit(limit: ViewLimitHeader) -> String {
+        format!("{:?}", limit.0)
+    }
+
+    #[test]
+    fn test_headers() {
+        let client = Client::debug_with(routes![tests_viewlimit]).unwrap();
+
+        let response = client.get(uri!(tests_viewlimit)).dispatch();
+        assert_eq!(response.into_string().unwrap(), "None");
+
+        let response = client
+            .get(uri!(tests_viewlimit))
+            .header(Header::new("X-Burn-After-Reading", "1"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "Some(1)");
+
+        let response = client
+            .get(uri!(tests_viewlimit))
+            .header(Header::new("X-Max-Views", "5"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "Some(5)");
+
+        for invalid in ["0", "-1", "many"] {
+            let response = client
+                .get(uri!(tests_viewlimit))
+                .header(Header::new("X-Max-Views", invalid))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+        }
+    }
+}
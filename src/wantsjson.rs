@@ -0,0 +1,69 @@
+use std::ops::Deref;
+
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use async_trait::async_trait;
+
+/// Holds whether the client asked for a JSON response with its Accept header, in which case the
+/// routes answer with the same objects as the JSON API instead of text or HTML.
+pub struct WantsJson(pub bool);
+
+impl Deref for WantsJson {
+    type Target = bool;
+
+    fn deref(&self) -> &bool {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<'a> FromRequest<'a> for WantsJson {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<WantsJson, ()> {
+        let json = request
+            .accept()
+            .is_some_and(|accept| accept.preferred().is_json());
+        Outcome::Success(WantsJson(json))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    use super::WantsJson;
+
+    #[get("/tests/json")]
+    fn tests_json(json: WantsJson) -> &'static str {
+        if json.0 {
+            "json"
+        } else {
+            "other"
+        }
+    }
+
+    #[test]
+    fn test_accept_header() {
+        let client = Client::debug_with(routes![tests_json]).unwrap();
+
+        let response = client.get(uri!(tests_json)).dispatch();
+        assert_eq!(response.into_string().unwrap(), "other");
+
+        for (accept, expected) in [
+            ("application/json", "json"),
+            ("application/json, text/plain;q=0.5", "json"),
+            ("text/html,application/xhtml+xml,*/*;q=0.8", "other"),
+            ("*/*", "other"),
+        ] {
+            let response = client
+                .get(uri!(tests_json))
+                .header(Header::new("Accept", accept))
+                .dispatch();
+            assert_eq!(response.into_string().unwrap(), expected, "{}", accept);
+        }
+    }
+}
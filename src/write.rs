@@ -1,37 +1,342 @@
-use crate::auth;
-use crate::io::{delete_paste, store_paste, store_paste_given_id, WritePool};
-use rocket::data::ToByteUnit;
+use askama::Template;
+
+use crate::auth::{self, Credentials, EditToken, Identity, Scope, TailscaleLogin};
+use crate::io::PasteOptions;
+use rocket::data::ByteUnit;
 use rocket::form::Form;
-use rocket::http::Status;
-use rocket::response::Redirect;
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Redirect, Responder};
 use rocket::tokio::io::AsyncReadExt;
 use rocket::uri;
 use rocket::Data;
+use rocket::Request;
 use rocket::State;
+use std::path::Path;
+use std::sync::Arc;
 
+use crate::api;
 use crate::config::BibinConfig;
 use crate::get;
-use crate::IndexForm;
+use crate::isplaintextrequest::IsPlaintextRequest;
+use crate::language::{is_valid_language, LanguageHeader};
+use crate::mimetype;
+use crate::private::PrivateHeader;
+use crate::readpassword::{self, ReadPasswordHeader};
+use crate::store::PasteStore;
+use crate::ttl::ExpireHeader;
+use crate::viewlimit::ViewLimitHeader;
+use crate::wantsjson::WantsJson;
+use crate::{HtmlOrPlain, IndexForm, RedirectOrContent};
 
-#[post("/", data = "<input>")]
-pub async fn submit(
-    config: &State<BibinConfig>,
-    input: Form<IndexForm>,
-    pool: &State<WritePool>,
-) -> Result<Redirect, Status> {
-    let form_data = input.into_inner();
-    if !form_data.password.is_valid(&config.password) {
-        Err(Status::Unauthorized)
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ShowError {
+    message: String,
+}
+
+/// URL of a new paste with a limited number of views, shown instead of redirecting its author to
+/// it: opening it would use one of the views
+#[derive(Template)]
+#[template(path = "stored.html")]
+struct ShowStored {
+    url: String,
+    views: u32,
+}
+
+/// Name of an uploaded file, without its directory and the characters that would need escaping
+fn sanitize_filename(raw_name: &str) -> Option<String> {
+    let name: String = raw_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(255)
+        .collect();
+    let name = name.trim();
+    if name.is_empty() {
+        None
     } else {
-        match store_paste(pool, config.id_length, config.max_entries, form_data.val).await {
-            Ok(id) => {
-                let uri = uri!(get::get_item(id));
-                Ok(Redirect::to(uri))
+        Some(name.to_owned())
+    }
+}
+
+/// Content and options of a paste submitted with a form: the uploaded file if there is one,
+/// the text field otherwise.
+async fn form_paste(
+    config: &BibinConfig,
+    form: IndexForm<'_>,
+    identity: Option<&Identity>,
+) -> Result<(Vec<u8>, PasteOptions), Status> {
+    let max_views = match (form.burn, form.views) {
+        (true, _) => Some(1),
+        (false, Some(0)) => return Err(Status::BadRequest),
+        (false, views) => views,
+    };
+    let mut language = match form.lang.as_deref() {
+        None | Some("") => None,
+        Some(language) if is_valid_language(language) => Some(language.to_owned()),
+        Some(_) => return Err(Status::BadRequest),
+    };
+
+    // Rocket's form errors are not Sync, so the form is owned to keep the future Send
+    let file = match &form.file {
+        Ok(file) if file.len() > 0 => Some(file),
+        Ok(_) => None,
+        // Form fields are optional, the file is only an error when it was too large
+        Err(errors) if errors.status() == Status::PayloadTooLarge => {
+            warn!(
+                "[SUBMIT] Uploaded file larger than {}",
+                config.max_paste_size
+            );
+            return Err(Status::PayloadTooLarge);
+        }
+        Err(_) => None,
+    };
+
+    let (data, mime_type, filename) = match file {
+        Some(file) => {
+            let mut data = Vec::with_capacity(file.len() as usize);
+            let read = match file.open().await {
+                Ok(mut reader) => reader.read_to_end(&mut data).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = read {
+                error!("[SUBMIT] Error when reading the uploaded file: {}", e);
+                return Err(Status::InternalServerError);
             }
-            Err(e) => {
-                error!("[SUBMIT] {} (pool {:?})", e, pool.0);
-                Err(Status::InternalServerError)
+
+            let filename = file.raw_name().and_then(|name| {
+                sanitize_filename(name.dangerous_unsafe_unsanitized_raw().as_str())
+            });
+            // Highlight the file according to its extension, unless told otherwise
+            if language.is_none() {
+                language = filename
+                    .as_deref()
+                    .and_then(|name| Path::new(name).extension())
+                    .and_then(|ext| ext.to_str())
+                    .filter(|ext| is_valid_language(ext))
+                    .map(str::to_owned);
             }
+            let mime_type = raw_mime_type(file.content_type(), &data);
+            (data, mime_type, filename)
+        }
+        None if form.val.len() > config.max_paste_size => {
+            warn!("[SUBMIT] Text field larger than {}", config.max_paste_size);
+            return Err(Status::PayloadTooLarge);
+        }
+        None => (form.val.into_bytes(), mimetype::TEXT.to_owned(), None),
+    };
+
+    let options = PasteOptions {
+        ttl: config.effective_ttl(form.expire),
+        max_views,
+        language,
+        mime_type: Some(mime_type),
+        creator: identity.and_then(Identity::name).map(str::to_owned),
+        filename,
+        read_password_hash: readpassword::hash(form.read_password.as_deref())?,
+        private: auth::is_private(form.private, identity),
+        ..Default::default()
+    };
+    Ok((data, options))
+}
+
+/// Type of an uploaded content, guessed from the content itself when the client did not give a
+/// meaningful one. curl sends its data as a form by default and file pickers fall back to
+/// application/octet-stream, which does not tell us anything.
+fn raw_mime_type(content_type: Option<&ContentType>, data: &[u8]) -> String {
+    match content_type {
+        Some(content_type) if !content_type.is_form() && *content_type != ContentType::Binary => {
+            content_type.to_string()
+        }
+        _ => mimetype::sniff(data).to_owned(),
+    }
+}
+
+/// Body of a raw upload, rejected when larger than the configured limit
+pub async fn read_raw_paste(input: Data<'_>, limit: ByteUnit) -> Result<Vec<u8>, Status> {
+    let data = input.open(limit).into_bytes().await.map_err(|e| {
+        error!("[SUBMIT_RAW] Error when reading the data: {}", e);
+        Status::InternalServerError
+    })?;
+    if !data.is_complete() {
+        warn!("[SUBMIT_RAW] Data larger than {}", limit);
+        return Err(Status::PayloadTooLarge);
+    }
+    Ok(data.into_inner())
+}
+
+/// URI of a paste, with its language as extension so that it is highlighted
+pub fn paste_uri(id: &str, language: Option<&str>) -> Origin<'static> {
+    match language {
+        Some(language) => uri!(get::get_item(format!("{}.{}", id, language))),
+        None => uri!(get::get_item(id)),
+    }
+}
+
+/// A response to a write, with the edit token of the paste in the `X-Edit-Token` header when it
+/// has just been created
+pub struct WithEditToken<R>(pub R, pub Option<String>);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithEditToken<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+        if let Some(token) = self.1 {
+            response.set_raw_header("X-Edit-Token", token);
+        }
+        Ok(response)
+    }
+}
+
+/// Answer to a new paste: its URL, or its JSON object when the client asked for JSON
+async fn stored(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    json: WantsJson,
+    id: &str,
+    options: &PasteOptions,
+    token: Option<String>,
+) -> Result<WithEditToken<HtmlOrPlain>, Status> {
+    if *json {
+        let mut object = api::stored_object(store, config, id).await?;
+        object.edit_token = token.clone();
+        return Ok(WithEditToken(HtmlOrPlain::Json(object.to_json()?), token));
+    }
+    Ok(WithEditToken(
+        HtmlOrPlain::Plain(format!(
+            "{}{}",
+            config.prefix,
+            paste_uri(id, options.language.as_deref())
+        )),
+        token,
+    ))
+}
+
+/// Same as `stored`, redirecting the browsers to the new paste, unless reading it would use one of
+/// its views
+async fn redirect_to_stored(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    json: WantsJson,
+    id: &str,
+    options: &PasteOptions,
+    token: Option<String>,
+) -> Result<WithEditToken<RedirectOrContent>, Status> {
+    if *json {
+        let mut object = api::stored_object(store, config, id).await?;
+        object.edit_token = token.clone();
+        return Ok(WithEditToken(
+            RedirectOrContent::Json(object.to_json()?),
+            token,
+        ));
+    }
+    let uri = paste_uri(id, options.language.as_deref());
+    if let Some(views) = options.max_views {
+        let page = ShowStored {
+            url: format!("{}{}", config.prefix, uri),
+            views,
+        };
+        let html = page.render().map_err(|e| {
+            error!("[SUBMIT] Error when rendering the template: {}", e);
+            Status::InternalServerError
+        })?;
+        return Ok(WithEditToken(RedirectOrContent::Html(html), token));
+    }
+    Ok(WithEditToken(
+        RedirectOrContent::Redirect(Redirect::to(uri)),
+        token,
+    ))
+}
+
+/// Make the identity the owner of a new paste, and give it the edit token of the paste
+pub fn claim(options: &mut PasteOptions, identity: &Identity) -> String {
+    let (token, hash) = EditToken::generate();
+    options.owner = identity.owner().map(str::to_owned);
+    options.token_hash = Some(hash);
+    token
+}
+
+/// Identity storing a paste at this id, and whether the paste is a new one. Creating a paste
+/// needs the create scope, replacing one is for its owner, the holders of its edit token and the
+/// admins.
+pub async fn authorize_store_at(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    credentials: Option<&Credentials>,
+    token: Option<&EditToken>,
+    id: &str,
+) -> Result<(Option<Identity>, bool), Status> {
+    match store.meta(id).await {
+        Ok(Some(meta)) => {
+            let identity =
+                auth::authorize_on_paste(&meta, config, credentials, token, Scope::Overwrite)
+                    .await?;
+            Ok((identity, false))
+        }
+        Ok(None) => {
+            let identity = credentials
+                .ok_or(Status::Unauthorized)?
+                .authorize(config, Scope::Create)
+                .await?;
+            Ok((Some(identity), true))
+        }
+        Err(e) => {
+            error!("[SUBMIT] Error when reading the metadata of {}: {}", id, e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Check that the paste can be deleted: by its owner, the holders of its edit token and the
+/// admins
+pub async fn authorize_delete(
+    store: &dyn PasteStore,
+    config: &BibinConfig,
+    credentials: Option<&Credentials>,
+    token: Option<&EditToken>,
+    id: &str,
+) -> Result<(), Status> {
+    if credentials.is_none() && token.is_none() {
+        return Err(Status::Unauthorized);
+    }
+    match store.meta(id).await {
+        Ok(Some(meta)) => {
+            auth::authorize_on_paste(&meta, config, credentials, token, Scope::Delete).await?;
+            Ok(())
+        }
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => {
+            error!(
+                "[DELETE_PASTE] Error when reading the metadata of {}: {}",
+                id, e
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[post("/", data = "<input>")]
+pub async fn submit(
+    config: &State<BibinConfig>,
+    input: Form<IndexForm<'_>>,
+    store: &State<Arc<dyn PasteStore>>,
+    json: WantsJson,
+    tailnet: Option<TailscaleLogin>,
+) -> Result<WithEditToken<RedirectOrContent>, Status> {
+    let mut form_data = input.into_inner();
+    let credentials = Credentials::from_form(form_data.password.take(), tailnet);
+    let identity = credentials.authorize(config, Scope::Create).await?;
+    let (data, mut options) = form_paste(config, form_data, Some(&identity)).await?;
+    let token = claim(&mut options, &identity);
+    match store.store(data, &options).await {
+        Ok(id) => {
+            redirect_to_stored(store.as_ref(), config, json, &id, &options, Some(token)).await
+        }
+        Err(e) => {
+            error!("[SUBMIT] {}", e);
+            Err(Status::InternalServerError)
         }
     }
 }
@@ -39,83 +344,142 @@ pub async fn submit(
 #[post("/<key>", data = "<input>")]
 pub async fn submit_with_key(
     config: &State<BibinConfig>,
-    input: Form<IndexForm>,
-    pool: &State<WritePool>,
+    input: Form<IndexForm<'_>>,
+    store: &State<Arc<dyn PasteStore>>,
     key: String,
-) -> Result<Redirect, Status> {
-    let form_data = input.into_inner();
-    if !form_data.password.is_valid(&config.password) {
-        Err(Status::Unauthorized)
-    } else {
-        match store_paste_given_id(pool, key, form_data.val).await {
-            Ok(id) => {
-                let uri = uri!(get::get_item(id));
-                Ok(Redirect::to(uri))
-            }
-            Err(e) => {
-                error!("[SUBMIT_WITH_KEY] {} (pool {:?})", e, pool.0);
-                Err(Status::InternalServerError)
-            }
+    json: WantsJson,
+    tailnet: Option<TailscaleLogin>,
+    token: Option<EditToken>,
+) -> Result<WithEditToken<RedirectOrContent>, Status> {
+    let mut form_data = input.into_inner();
+    let credentials = Credentials::from_form(form_data.password.take(), tailnet);
+    let (identity, created) = authorize_store_at(
+        store.as_ref(),
+        config,
+        Some(&credentials),
+        token.as_ref(),
+        &key,
+    )
+    .await?;
+    let (data, mut options) = form_paste(config, form_data, identity.as_ref()).await?;
+    let token = match (&identity, created) {
+        (Some(identity), true) => Some(claim(&mut options, identity)),
+        _ => None,
+    };
+    match store.store_with_id(key, data, &options).await {
+        Ok(id) => redirect_to_stored(store.as_ref(), config, json, &id, &options, token).await,
+        Err(e) => {
+            error!("[SUBMIT_WITH_KEY] {}", e);
+            Err(Status::InternalServerError)
         }
     }
 }
 
 #[put("/", data = "<input>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn submit_raw(
     input: Data<'_>,
     config: &State<BibinConfig>,
-    password: auth::AuthKey,
-    pool: &State<WritePool>,
-) -> Result<String, Status> {
-    if !password.is_valid(&config.password) {
-        return Err(Status::Unauthorized);
-    }
+    password: auth::Credentials,
+    expire: ExpireHeader,
+    view_limit: ViewLimitHeader,
+    language: LanguageHeader,
+    read_password: ReadPasswordHeader,
+    private: PrivateHeader,
+    content_type: Option<&ContentType>,
+    store: &State<Arc<dyn PasteStore>>,
+    json: WantsJson,
+) -> Result<WithEditToken<HtmlOrPlain>, Status> {
+    let identity = password.authorize(config, Scope::Create).await?;
 
-    let mut data = String::new();
-    input
-        .open(5.megabytes())
-        .read_to_string(&mut data)
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+    let data = read_raw_paste(input, config.max_paste_size).await?;
 
-    match store_paste(pool, config.id_length, config.max_entries, data).await {
-        Ok(id) => {
-            let uri = uri!(get::get_item(id));
-            Ok(format!("{}{}", config.prefix, uri))
+    let mut options = PasteOptions {
+        ttl: config.effective_ttl(expire.0),
+        max_views: view_limit.0,
+        language: language.0,
+        mime_type: Some(raw_mime_type(content_type, &data)),
+        creator: identity.name().map(str::to_owned),
+        read_password_hash: readpassword::hash(read_password.0.as_deref())?,
+        private: auth::is_private(private.0, Some(&identity)),
+        ..Default::default()
+    };
+
+    // A paste meant to disappear after being read, or protected by a password, must not be shared
+    // with another one. The edit token of the existing paste is never given out again.
+    if config.reuse_duplicates
+        && options.max_views.is_none()
+        && options.read_password_hash.is_none()
+    {
+        match store.find_duplicate(&data, &options).await {
+            Ok(Some(id)) => {
+                info!("[SUBMIT_RAW] Same content as {}, reusing it", id);
+                return stored(store.as_ref(), config, json, &id, &options, None).await;
+            }
+            Ok(None) => (),
+            Err(e) => warn!("[SUBMIT_RAW] Error when looking for a duplicate: {}", e),
         }
+    }
+
+    let token = claim(&mut options, &identity);
+    match store.store(data, &options).await {
+        Ok(id) => stored(store.as_ref(), config, json, &id, &options, Some(token)).await,
         Err(e) => {
-            error!("[SUBMIT_RAW] {} (pool {:?})", e, pool.0);
+            error!("[SUBMIT_RAW] {}", e);
             Err(Status::InternalServerError)
         }
     }
 }
 
 #[put("/<key>", data = "<input>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn submit_raw_with_key(
     input: Data<'_>,
     config: &State<BibinConfig>,
-    password: auth::AuthKey,
-    pool: &State<WritePool>,
+    password: Option<auth::Credentials>,
+    token: Option<EditToken>,
+    expire: ExpireHeader,
+    view_limit: ViewLimitHeader,
+    language: LanguageHeader,
+    read_password: ReadPasswordHeader,
+    private: PrivateHeader,
+    content_type: Option<&ContentType>,
+    store: &State<Arc<dyn PasteStore>>,
     key: String,
-) -> Result<String, Status> {
-    if !password.is_valid(&config.password) {
-        return Err(Status::Unauthorized);
-    }
+    json: WantsJson,
+) -> Result<WithEditToken<HtmlOrPlain>, Status> {
+    let (identity, created) = authorize_store_at(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        &key,
+    )
+    .await?;
 
-    let mut data = String::new();
-    input
-        .open(5.megabytes())
-        .read_to_string(&mut data)
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+    let data = read_raw_paste(input, config.max_paste_size).await?;
 
-    match store_paste_given_id(pool, key, data).await {
-        Ok(id) => {
-            let uri = uri!(get::get_item(id));
-            Ok(format!("{}{}", config.prefix, uri))
-        }
+    let mut options = PasteOptions {
+        ttl: config.effective_ttl(expire.0),
+        max_views: view_limit.0,
+        language: language.0,
+        mime_type: Some(raw_mime_type(content_type, &data)),
+        creator: identity
+            .as_ref()
+            .and_then(Identity::name)
+            .map(str::to_owned),
+        read_password_hash: readpassword::hash(read_password.0.as_deref())?,
+        private: auth::is_private(private.0, identity.as_ref()),
+        ..Default::default()
+    };
+    let token = match (&identity, created) {
+        (Some(identity), true) => Some(claim(&mut options, identity)),
+        _ => None,
+    };
+    match store.store_with_id(key, data, &options).await {
+        Ok(id) => stored(store.as_ref(), config, json, &id, &options, token).await,
         Err(e) => {
-            error!("[SUBMIT_RAW_WITH_KEY] {} (pool {:?})", e, pool.0);
+            error!("[SUBMIT_RAW_WITH_KEY] {}", e);
             Err(Status::InternalServerError)
         }
     }
@@ -125,18 +489,408 @@ pub async fn submit_raw_with_key(
 pub async fn delete(
     id: String,
     config: &State<BibinConfig>,
-    password: auth::AuthKey,
-    pool: &State<WritePool>,
-) -> Result<String, Status> {
-    if !password.is_valid(&config.password) {
-        return Err(Status::Unauthorized);
-    }
+    password: Option<auth::Credentials>,
+    token: Option<EditToken>,
+    store: &State<Arc<dyn PasteStore>>,
+    json: WantsJson,
+) -> Result<HtmlOrPlain, Status> {
+    authorize_delete(
+        store.as_ref(),
+        config,
+        password.as_ref(),
+        token.as_ref(),
+        &id,
+    )
+    .await?;
 
-    match delete_paste(pool, &id).await {
-        Ok(id) => Ok(format!("{} deleted", id)),
+    match store.delete(&id).await {
+        Ok(true) if *json => Ok(HtmlOrPlain::Json(
+            serde_json::json!({ "id": id, "deleted": true }).to_string(),
+        )),
+        Ok(true) => Ok(HtmlOrPlain::Plain(format!("{} deleted", id))),
+        Ok(false) => Err(Status::NotFound),
         Err(e) => {
             error!("[DELETE_PASTE] {}", e);
             Err(Status::InternalServerError)
         }
     }
 }
+
+/// Explain the rejection of a paste that is too large, instead of the generic Rocket error page
+#[catch(413)]
+pub async fn payload_too_large(request: &Request<'_>) -> HtmlOrPlain {
+    let message = match request.rocket().state::<BibinConfig>() {
+        Some(config) => format!(
+            "The paste is too large: the maximum size is {}.",
+            config.max_paste_size
+        ),
+        None => "The paste is too large.".to_owned(),
+    };
+
+    let plaintext = request
+        .guard::<IsPlaintextRequest>()
+        .await
+        .succeeded()
+        .is_none_or(|plaintext| plaintext.0);
+    if plaintext {
+        return HtmlOrPlain::Plain(format!("{}\n", message));
+    }
+    match (ShowError {
+        message: message.clone(),
+    })
+    .render()
+    {
+        Ok(html) => HtmlOrPlain::Html(html),
+        Err(e) => {
+            error!(
+                "[PAYLOAD_TOO_LARGE] Error when rendering the template: {}",
+                e
+            );
+            HtmlOrPlain::Plain(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::BibinConfig;
+    use crate::get::{get_meta, rocket_uri_macro_get_meta};
+    use crate::rocket;
+    use crate::store;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use tempfile::NamedTempFile;
+
+    use super::sanitize_filename;
+    use super::{payload_too_large, rocket_uri_macro_submit, rocket_uri_macro_submit_raw};
+    use super::{submit, submit_raw};
+
+    const PASSWORD: &str = "password123";
+    const BOUNDARY: &str = "X-BIBIN-BOUNDARY";
+    const BROWSER_AGENT: &str =
+        "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/114.0";
+
+    async fn create_test_client() -> (NamedTempFile, Client) {
+        create_test_client_with(r#"{ "password": "password123", "prefix": "/" }"#).await
+    }
+
+    async fn create_test_client_with(config: &str) -> (NamedTempFile, Client) {
+        let temp = NamedTempFile::new().unwrap();
+        let mut config = serde_json::from_str::<BibinConfig>(config).unwrap();
+        config.database_file = temp.path().to_str().unwrap().to_owned();
+        let store = store::open(&config).await.unwrap();
+
+        let rocket = rocket::custom(config.apply_limits(rocket::Config::figment()))
+            .manage(store)
+            .manage(config)
+            .mount("/", routes![submit, submit_raw, get_meta])
+            .register("/", catchers![payload_too_large]);
+        (temp, Client::untracked(rocket).await.unwrap())
+    }
+
+    /// Build a multipart/form-data body from (name, filename, content) parts
+    fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, content) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            match filename {
+                Some(filename) => body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                        Content-Type: application/octet-stream\r\n\r\n",
+                        name, filename
+                    )
+                    .as_bytes(),
+                ),
+                None => body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes(),
+                ),
+            }
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn basic_auth(user: &str) -> String {
+        use base64::{engine::general_purpose, Engine as _};
+        format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!("{}:{}", user, PASSWORD))
+        )
+    }
+
+    fn multipart_content_type() -> ContentType {
+        ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY))
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("report.log").unwrap(), "report.log");
+        assert_eq!(sanitize_filename("../../etc/passwd").unwrap(), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\a b.txt").unwrap(), "a b.txt");
+        assert_eq!(sanitize_filename("a\"\nb").unwrap(), "ab");
+        assert_eq!(sanitize_filename("dir/"), None);
+        assert_eq!(sanitize_filename(""), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_file_upload() {
+        let (_temp, client) = create_test_client().await;
+
+        let response = client
+            .post(uri!(submit))
+            .header(multipart_content_type())
+            .body(multipart(&[
+                ("password", None, PASSWORD.as_bytes()),
+                ("file", Some("report.log"), b"line 1\nline 2\n"),
+            ]))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+        assert!(location.ends_with(".log"), "{}", location);
+
+        let key = location.trim_start_matches('/').trim_end_matches(".log");
+        let response = client
+            .get(uri!(get_meta(key)))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(meta["filename"], "report.log");
+        assert_eq!(meta["language"], "log");
+        assert_eq!(meta["mime_type"], "text/plain; charset=utf-8");
+        assert_eq!(meta["size"], 14);
+    }
+
+    #[rocket::async_test]
+    async fn test_text_field_with_multipart() {
+        let (_temp, client) = create_test_client().await;
+
+        // Browsers send an empty file field when no file has been chosen
+        let response = client
+            .post(uri!(submit))
+            .header(multipart_content_type())
+            .body(multipart(&[
+                ("val", None, b"some text"),
+                ("password", None, PASSWORD.as_bytes()),
+                ("file", Some(""), b""),
+            ]))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+
+        let response = client
+            .get(uri!(get_meta(location.trim_start_matches('/'))))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(meta["size"], 9);
+        assert!(meta["filename"].is_null());
+
+        let response = client
+            .post(uri!(submit))
+            .header(multipart_content_type())
+            .body(multipart(&[
+                ("password", None, b"wrong"),
+                ("file", Some("report.log"), b"data"),
+            ]))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_view_limited_not_redirected() {
+        let (_temp, client) = create_test_client().await;
+
+        for (field, value, views) in [("burn", "true", 1), ("views", "3", 3)] {
+            let response = client
+                .post(uri!(submit))
+                .header(multipart_content_type())
+                .body(multipart(&[
+                    ("val", None, b"secret"),
+                    ("password", None, PASSWORD.as_bytes()),
+                    (field, None, value.as_bytes()),
+                ]))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok, "{}", field);
+            assert_eq!(response.content_type(), Some(ContentType::HTML));
+            let body = response.into_string().await.unwrap();
+            let start = body.find("value=\"").unwrap() + "value=\"".len();
+            let url = &body[start..start + body[start..].find('"').unwrap()];
+            let key = url.trim_start_matches('/');
+
+            // Showing the URL did not use a view
+            let response = client
+                .get(uri!(get_meta(key)))
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap();
+            let meta: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(meta["views_left"], views, "{}", field);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_tailnet_member() {
+        let (_temp, client) = create_test_client_with(
+            r#"{ "password": "password123", "prefix": "/", "trusted_proxies": ["127.0.0.1"],
+                 "tailscale_users": [{ "login": "alice@example.com", "scopes": ["create"] }] }"#,
+        )
+        .await;
+        let proxy = "127.0.0.1:41641".parse().unwrap();
+
+        // No password in the form
+        let response = client
+            .post(uri!(submit))
+            .header(ContentType::Form)
+            .header(Header::new("Tailscale-User-Login", "alice@example.com"))
+            .remote(proxy)
+            .body("val=from+the+tailnet")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+        let response = client
+            .get(uri!(get_meta(location.trim_start_matches('/'))))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(meta["creator"], "alice@example.com");
+
+        let response = client
+            .put(uri!(submit_raw))
+            .header(Header::new("Tailscale-User-Login", "alice@example.com"))
+            .remote(proxy)
+            .body("from curl")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        for (login, remote) in [
+            ("bob@example.com", proxy),
+            ("alice@example.com", "100.64.0.2:41641".parse().unwrap()),
+        ] {
+            let response = client
+                .post(uri!(submit))
+                .header(ContentType::Form)
+                .header(Header::new("Tailscale-User-Login", login))
+                .remote(remote)
+                .body("val=from+the+tailnet&password=")
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Unauthorized, "{}", login);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_max_paste_size() {
+        let (_temp, client) = create_test_client_with(
+            r#"{ "password": "password123", "prefix": "/", "max_paste_size": 16 }"#,
+        )
+        .await;
+        let small = b"0123456789abcdef";
+        let large = b"0123456789abcdefX";
+
+        let response = client
+            .put(uri!(submit_raw))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(&small[..])
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .put(uri!(submit_raw))
+            .header(Header::new("X-API-Key", PASSWORD))
+            .body(&large[..])
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains("maximum size is 16B"), "{}", body);
+
+        for field in [("val", None), ("file", Some("large.txt"))] {
+            let response = client
+                .post(uri!(submit))
+                .header(multipart_content_type())
+                .body(multipart(&[
+                    ("password", None, PASSWORD.as_bytes()),
+                    (field.0, field.1, &large[..]),
+                ]))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::PayloadTooLarge, "{}", field.0);
+
+            let response = client
+                .post(uri!(submit))
+                .header(multipart_content_type())
+                .body(multipart(&[
+                    ("password", None, PASSWORD.as_bytes()),
+                    (field.0, field.1, &small[..]),
+                ]))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::SeeOther, "{}", field.0);
+        }
+
+        let response = client
+            .post(uri!(submit))
+            .header(ContentType::Form)
+            .header(Header::new("User-Agent", BROWSER_AGENT))
+            .body(format!("password={}&val={}", PASSWORD, "X".repeat(17)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+    }
+
+    #[rocket::async_test]
+    async fn test_reuse_duplicates() {
+        let (_temp, client) = create_test_client_with(
+            r#"{ "password": "password123", "prefix": "", "reuse_duplicates": true }"#,
+        )
+        .await;
+
+        let submit = |user: &'static str, headers: Vec<Header<'static>>| {
+            let mut request = client
+                .put(uri!(submit_raw))
+                .header(Header::new("Authorization", basic_auth(user)))
+                .body("same content");
+            for header in headers {
+                request = request.header(header);
+            }
+            async move { request.dispatch().await.into_string().await.unwrap() }
+        };
+
+        let url = submit("alice", vec![]).await;
+        assert_eq!(submit("alice", vec![]).await, url);
+        // The language of the new submission is kept in the URL
+        assert_eq!(
+            submit("alice", vec![Header::new("X-Language", "txt")]).await,
+            format!("{}.txt", url)
+        );
+        // Whoever uploads it
+        assert_eq!(submit("bob", vec![]).await, url);
+        assert_ne!(
+            submit("alice", vec![Header::new("X-Burn-After-Reading", "1")]).await,
+            url
+        );
+    }
+}